@@ -1,110 +1,1008 @@
 //! Caching things
 //!
-use chrono::{DateTime, Duration};
-use dirs::home_dir;
+//! Entries live in a directory per ecosystem, named by the sha256 of their URL, alongside a
+//! `manifest.jsonl` that maps those names back to URLs. The manifest's appended to as entries are
+//! saved, and rewritten when the cache is pruned.
+//!
+//! Several processes can share a cache. Entries are written to a temp file and renamed into place
+//! so nobody reads half of one, and whole-cache operations like pruning take an exclusive lock on
+//! `.lock`, while saving takes a shared one. Entries that won't parse get moved to `quarantine/`.
+//!
+//! What's in each entry file is described in [format].
+//!
+//! [Cache::get_fresh_with] and [Cache::get_fresh_json] hand content to the parser as it's
+//! decompressed, and [Cache::is_fresh] only reads the header, which is how the backends read their
+//! big indexes. [Cache::get_fresh] and [Cache::get_cache] read the whole entry into a string, which
+//! is fine for small responses. Saving compresses straight into the file. [Cache::save] takes the
+//! content as a string, because that's how most of it comes back from the server, and
+//! [Cache::save_reader] takes it as it's read, so a big index is never all in memory at once.
+use chrono::{DateTime, Duration, Utc};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{get_cache_dir, Errors, RepoType};
+
+mod format;
+use format::{ContentReader, BLOB_DIR, ENTRY_EXT, INLINE_MAX};
+
+const MANIFEST: &str = "manifest.jsonl";
+/// For entries that don't say which ecosystem they're from
+const MISC_DIR: &str = "misc";
+const QUARANTINE_DIR: &str = "quarantine";
+const LOCK_FILE: &str = ".lock";
+/// Temp files older than this were left by a process that didn't finish
+const STALE_TEMP: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Somewhere next to `path` to write before renaming over it, unique to this process and call
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        name,
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Readers either see the old file or the new one, never half of it
+fn write_atomic(path: &Path, content: &[u8]) -> Result<(), Errors> {
+    let temp = temp_path(path);
+    let result = std::fs::write(&temp, content).and_then(|_| std::fs::rename(&temp, path));
+    if result.is_err() {
+        std::fs::remove_file(&temp).ok();
+    }
+    Ok(result?)
+}
+
+/// Leftovers from a process that died between writing and renaming
+fn remove_stale_temp(dir: &Path) -> Result<(), Errors> {
+    for file in dir.read_dir()? {
+        let path = file?.path();
+        if path.extension().is_some_and(|ext| ext == "tmp")
+            && std::fs::metadata(&path)?
+                .modified()?
+                .elapsed()
+                .is_ok_and(|age| age > STALE_TEMP)
+        {
+            std::fs::remove_file(path).ok();
+        }
+    }
+    Ok(())
+}
 
-use crate::Errors;
+/// Blocks until it has the lock, which lasts until the file's closed
+fn lock_file(dir: &Path, exclusive: bool) -> Result<File, Errors> {
+    std::fs::create_dir_all(dir)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))?;
+    match exclusive {
+        true => file.lock()?,
+        false => file.lock_shared()?,
+    }
+    Ok(file)
+}
+
+/// An advisory lock on the cache, or part of it, released when it's dropped
+#[derive(Debug)]
+pub struct CacheLock {
+    _files: Vec<File>,
+}
+
+/// Keeps the first error reading an entry, to tell it apart from content that didn't parse
+struct Watched {
+    inner: ContentReader,
+    failed: Option<std::io::Error>,
+}
+
+impl Read for Watched {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf).map_err(|err| {
+            let copy = std::io::Error::new(err.kind(), err.to_string());
+            self.failed.get_or_insert(err);
+            copy
+        })
+    }
+}
 
 pub(crate) fn hash_url(url: &str) -> String {
     sha256::digest(url)
 }
 
+/// The files directly in `dir` with one of `extensions`
+fn files_with(dir: &Path, extensions: &[&str]) -> Result<Vec<PathBuf>, Errors> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for file in dir.read_dir()? {
+        let path = file?.path();
+        if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|ext| extensions.iter().any(|wanted| ext == *wanted))
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Entries in an ecosystem's directory, including any still in the old JSON format
+fn entry_files(dir: &Path) -> Result<Vec<PathBuf>, Errors> {
+    files_with(dir, &[ENTRY_EXT, "json"])
+}
+
+/// A manifest's entries by file name
+pub type Manifest = BTreeMap<String, ManifestEntry>;
+
+/// A line of `manifest.jsonl`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// The file name, `<sha256 of url>.entry`
+    pub key: String,
+    pub url: String,
+    pub kind: ResourceKind,
+    pub updated: DateTime<Utc>,
+    /// Of the entry file, not counting its blob
+    pub bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
+impl ManifestEntry {
+    fn new(key: String, meta: &CacheMeta, bytes: u64) -> Self {
+        Self {
+            key,
+            url: meta.url.clone(),
+            kind: meta.kind,
+            updated: meta.updated,
+            bytes,
+            blob: meta.blob.clone(),
+        }
+    }
+}
+
+/// Later lines win, and a line that didn't get finished is skipped
+fn read_manifest(dir: &Path) -> Result<Manifest, Errors> {
+    let path = dir.join(MANIFEST);
+    let mut manifest = Manifest::new();
+    if !path.exists() {
+        return Ok(manifest);
+    }
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Ok(entry) = serde_json::from_str::<ManifestEntry>(&line?) {
+            manifest.insert(entry.key.clone(), entry);
+        }
+    }
+    Ok(manifest)
+}
+
+fn append_manifest(dir: &Path, entry: &ManifestEntry) -> Result<(), Errors> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(MANIFEST))?;
+    // one write, so lines from different processes don't get interleaved
+    file.write_all(format!("{}\n", serde_json::to_string(entry)?).as_bytes())?;
+    Ok(())
+}
+
+fn write_manifest(dir: &Path, manifest: &Manifest) -> Result<(), Errors> {
+    let mut content = String::new();
+    for entry in manifest.values() {
+        content.push_str(&serde_json::to_string(entry)?);
+        content.push('\n');
+    }
+    write_atomic(&dir.join(MANIFEST), content.as_bytes())
+}
+
+/// What [Cache::prune] did
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    pub removed: usize,
+    /// Including blobs nothing points at any more
+    pub freed_bytes: u64,
+    pub kept: usize,
+    pub kept_bytes: u64,
+    /// Entries that wouldn't load, which were moved to the quarantine directory
+    pub quarantined: usize,
+}
+
+/// What a cache entry holds, which decides how long it's good for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResourceKind {
+    /// Search results, which go stale quickly
+    Search,
+    /// A whole index or package list
+    Index,
+    /// Anything about a package that can change, like its list of versions
+    #[default]
+    Metadata,
+    /// Published once and never changed, like a release's own metadata
+    Immutable,
+}
+
+impl ResourceKind {
+    pub const ALL: [ResourceKind; 4] = [
+        ResourceKind::Search,
+        ResourceKind::Index,
+        ResourceKind::Metadata,
+        ResourceKind::Immutable,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResourceKind::Search => "search",
+            ResourceKind::Index => "index",
+            ResourceKind::Metadata => "metadata",
+            ResourceKind::Immutable => "immutable",
+        }
+    }
+}
+
+impl std::str::FromStr for ResourceKind {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ResourceKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s.trim().to_lowercase())
+            .ok_or_else(|| Errors::InvalidInput(format!("Unknown resource kind {}", s)))
+    }
+}
+
+/// How long each kind of entry lasts, with overrides for particular ecosystems
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachePolicy {
+    pub defaults: HashMap<ResourceKind, Duration>,
+    pub overrides: HashMap<(RepoType, ResourceKind), Duration>,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            defaults: HashMap::from([
+                (ResourceKind::Search, Duration::minutes(15)),
+                (ResourceKind::Index, Duration::hours(6)),
+                (ResourceKind::Metadata, Duration::hours(1)),
+            ]),
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl CachePolicy {
+    /// `None` means it never expires, which is always the case for [ResourceKind::Immutable]
+    pub fn max_age(&self, repo_type: Option<RepoType>, kind: ResourceKind) -> Option<Duration> {
+        if kind == ResourceKind::Immutable {
+            return None;
+        }
+        repo_type
+            .and_then(|repo_type| self.overrides.get(&(repo_type, kind)))
+            .or_else(|| self.defaults.get(&kind))
+            .copied()
+    }
+
+    pub fn is_expired(&self, meta: &CacheMeta) -> bool {
+        self.max_age(meta.repo_type, meta.kind)
+            .is_some_and(|max_age| meta.updated + max_age < Utc::now())
+    }
+}
+
+/// A file in the cache, and its metadata if it could be read
+pub type CacheEntry = (PathBuf, Result<CacheMeta, Errors>);
+
 #[derive(Debug)]
 pub struct Cache {
     pub cache_dir: PathBuf,
     /// The key is the source URL, sha256'd to a hex string
     pub data: HashMap<String, CacheData>,
+    pub policy: CachePolicy,
 }
 
 impl Default for Cache {
     fn default() -> Self {
+        Self::new(get_cache_dir())
+    }
+}
+
+impl Cache {
+    pub fn new(cache_dir: PathBuf) -> Self {
         Self {
-            cache_dir: home_dir().unwrap().join(".cache").join("tidetrawler"),
+            cache_dir,
             data: HashMap::new(),
+            policy: CachePolicy::default(),
         }
     }
-}
 
-impl Cache {
+    pub fn with_policy(mut self, policy: CachePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Where an ecosystem's entries go, the same place as its [crate::repo::Repository::get_cache_dir]
+    pub fn ecosystem_dir(&self, repo_type: Option<RepoType>) -> PathBuf {
+        self.cache_dir
+            .join(repo_type.map_or(MISC_DIR, |repo_type| repo_type.cache_dir()))
+    }
+
     /// Get the cache path for an item
-    fn cache_path(&self, url: &str) -> PathBuf {
-        let cache_key = hash_url(url);
-        self.cache_dir.join(format!("{}.json", cache_key))
+    fn cache_path(&self, repo_type: Option<RepoType>, url: &str) -> PathBuf {
+        self.ecosystem_dir(repo_type)
+            .join(format!("{}.{}", hash_url(url), ENTRY_EXT))
+    }
+
+    /// Where `url` might be, newest layout first
+    fn candidate_paths(&self, repo_type: Option<RepoType>, url: &str) -> [PathBuf; 3] {
+        let json = format!("{}.json", hash_url(url));
+        [
+            self.cache_path(repo_type, url),
+            self.ecosystem_dir(repo_type).join(&json),
+            self.cache_dir.join(&json),
+        ]
+    }
+
+    /// The ecosystem directories that exist, we never touch anything else in the cache dir
+    fn ecosystem_dirs(&self) -> Vec<PathBuf> {
+        RepoType::ALL
+            .iter()
+            .map(|repo_type| self.ecosystem_dir(Some(*repo_type)))
+            .chain(std::iter::once(self.ecosystem_dir(None)))
+            .filter(|dir| dir.is_dir())
+            .collect()
+    }
+
+    /// Entries from before the cache was split up by ecosystem
+    fn legacy_files(&self) -> Result<Vec<PathBuf>, Errors> {
+        files_with(&self.cache_dir, &["json"])
+    }
+
+    /// The whole cache to ourselves, for pruning and purging
+    pub fn lock(&self) -> Result<CacheLock, Errors> {
+        Ok(CacheLock {
+            _files: vec![lock_file(&self.cache_dir, true)?],
+        })
+    }
+
+    /// One ecosystem to ourselves, for mirroring, which only keeps out whole-cache operations
+    /// and other mirrors of the same ecosystem
+    pub fn lock_ecosystem(&self, repo_type: RepoType) -> Result<CacheLock, Errors> {
+        Ok(CacheLock {
+            _files: vec![
+                lock_file(&self.cache_dir, false)?,
+                lock_file(&self.ecosystem_dir(Some(repo_type)), true)?,
+            ],
+        })
     }
 
     pub fn save(&self, data: CacheData) -> Result<(), Errors> {
-        let cache_path = self.cache_path(&data.url);
-        let cache_data = serde_json::to_string(&data)?;
-        std::fs::write(cache_path, cache_data)?;
-        Ok(())
+        let _lock = lock_file(&self.cache_dir, false)?;
+        self.save_unlocked(&data)
+    }
+
+    /// [Cache::save] for content that's too big to hold in memory, which is compressed into the
+    /// cache as it's read from `content`
+    pub fn save_reader(
+        &self,
+        url: &str,
+        repo_type: RepoType,
+        kind: ResourceKind,
+        content: impl Read,
+    ) -> Result<(), Errors> {
+        let _lock = lock_file(&self.cache_dir, false)?;
+        let meta = CacheData::new(url.to_string(), String::new(), String::new())
+            .with_kind(repo_type, kind)
+            .meta();
+        self.write(meta, content)
+    }
+
+    fn save_unlocked(&self, data: &CacheData) -> Result<(), Errors> {
+        self.write(data.meta(), data.content.as_bytes())
     }
 
-    pub fn clean_cache(&mut self, max_age: Duration) {
+    /// Only as much of `content` as fits inline is held in memory, the rest goes straight
+    /// into a blob
+    fn write(&self, meta: CacheMeta, mut content: impl Read) -> Result<(), Errors> {
+        let dir = self.ecosystem_dir(meta.repo_type);
+        std::fs::create_dir_all(&dir)?;
+        let mut head = Vec::new();
+        (&mut content)
+            .take(INLINE_MAX as u64 + 1)
+            .read_to_end(&mut head)?;
+        let meta = match head.len() > INLINE_MAX {
+            true => {
+                let (digest, size) =
+                    format::write_blob(&self.cache_dir, head.as_slice().chain(content))?;
+                CacheMeta {
+                    size,
+                    blob: Some(digest),
+                    ..meta
+                }
+            }
+            false => CacheMeta {
+                size: head.len() as u64,
+                ..meta
+            },
+        };
+        let inline = meta.blob.is_none().then_some(head.as_slice());
+        let [path, old_json, _] = self.candidate_paths(meta.repo_type, &meta.url);
+        format::write_entry(&path, &meta, inline)?;
+        if old_json.exists() {
+            std::fs::remove_file(old_json)?;
+        }
+        let key = format!("{}.{}", hash_url(&meta.url), ENTRY_EXT);
+        append_manifest(
+            &dir,
+            &ManifestEntry::new(key, &meta, std::fs::metadata(&path)?.len()),
+        )
+    }
+
+    /// Move a corrupt entry out of the way, so it gets fetched again instead of failing every time
+    fn quarantine(&self, path: &Path) -> Result<PathBuf, Errors> {
+        let dir = self.cache_dir.join(QUARANTINE_DIR);
+        std::fs::create_dir_all(&dir)?;
+        let name = path
+            .strip_prefix(&self.cache_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace(['/', '\\'], "-");
+        let dest = dir.join(format!("{}-{}", Utc::now().timestamp(), name));
+        std::fs::rename(path, &dest)?;
+        Ok(dest)
+    }
+
+    /// Pass `result` through, quarantining `path` if it says the entry's corrupt
+    fn checked<T>(&self, path: &Path, result: Result<T, Errors>) -> Result<T, Errors> {
+        if let Err(err @ Errors::CacheCorrupt { .. }) = &result {
+            match self.quarantine(path) {
+                Ok(dest) => eprintln!("{}, moved it to {}", err, dest.display()),
+                Err(move_err) => eprintln!("{}, and couldn't move it: {}", err, move_err),
+            }
+        }
+        result
+    }
+
+    /// Drop everything older than `max_age`, and keep what's left in [Cache::data]
+    pub fn clean_cache(&mut self, max_age: Duration) -> Result<PruneReport, Errors> {
+        let _lock = self.lock()?;
+        let report = self.prune_locked(Some(max_age), None)?;
         self.data.clear();
-        for file in self.cache_dir.read_dir().unwrap() {
-            let file = file.unwrap();
-            let path = file.path();
-            let file_name = path.file_name().unwrap().to_str().unwrap();
-            if file_name.ends_with(".json") {
-                let cache_path = self.cache_path(file_name);
-                match self.get_cache(file_name, Some(max_age), Some(true)) {
-                    Some(data) => {
-                        self.data.insert(data.get_hash(), data);
-                    }
-                    None => {
-                        std::fs::remove_file(cache_path).ok();
+        for (path, meta) in self.entries()? {
+            if meta.is_ok() {
+                if let Ok(data) = self.load_entry(&path) {
+                    self.data.insert(data.get_hash(), data);
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Remove entries older than `max_age`, then the oldest until it all fits in `max_bytes`
+    ///
+    /// Entries left over from older layouts are rewritten first, and corrupt ones are
+    /// quarantined. Anything that isn't a cache entry is left alone.
+    pub fn prune(
+        &mut self,
+        max_age: Option<Duration>,
+        max_bytes: Option<u64>,
+    ) -> Result<PruneReport, Errors> {
+        let _lock = self.lock()?;
+        self.prune_locked(max_age, max_bytes)
+    }
+
+    fn prune_locked(
+        &mut self,
+        max_age: Option<Duration>,
+        max_bytes: Option<u64>,
+    ) -> Result<PruneReport, Errors> {
+        let mut report = PruneReport::default();
+        let mut old_files = self.legacy_files()?;
+        for dir in self.ecosystem_dirs() {
+            old_files.extend(files_with(&dir, &["json"])?);
+        }
+        for path in old_files {
+            match self.checked(&path, self.load_file(&path)) {
+                Ok(data) => {
+                    self.save_unlocked(&data)?;
+                    if path.exists() {
+                        std::fs::remove_file(&path)?;
                     }
                 }
-            } else {
-                eprintln!(
-                    "Uh, what's this extra file in the cache dir? {:?}",
-                    file_name
-                );
+                Err(Errors::CacheCorrupt { .. }) => report.quarantined += 1,
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut manifests = Vec::new();
+        for dir in self.ecosystem_dirs() {
+            let (manifest, quarantined) = self.reconcile(&dir)?;
+            report.quarantined += quarantined;
+            manifests.push((dir, manifest));
+        }
+
+        let now = Utc::now();
+        let mut keep: Vec<(usize, &ManifestEntry)> = Vec::new();
+        let mut remove: Vec<(usize, String)> = Vec::new();
+        for (index, (_, manifest)) in manifests.iter().enumerate() {
+            for entry in manifest.values() {
+                match max_age.is_some_and(|max_age| entry.updated + max_age < now) {
+                    true => remove.push((index, entry.key.clone())),
+                    false => keep.push((index, entry)),
+                }
+            }
+        }
+        // newest first, with a blob only counting against the budget the first time it's seen
+        keep.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.updated));
+        let mut blobs = HashSet::new();
+        let mut total = 0;
+        keep.retain(|(index, entry)| {
+            let mut bytes = entry.bytes;
+            if let Some(blob) = entry.blob.as_ref() {
+                if !blobs.contains(blob) {
+                    bytes += self.blob_bytes(blob);
+                }
+            }
+            if max_bytes.is_some_and(|max_bytes| total + bytes > max_bytes) {
+                remove.push((*index, entry.key.clone()));
+                return false;
+            }
+            total += bytes;
+            blobs.extend(entry.blob.clone());
+            true
+        });
+        report.kept = keep.len();
+        report.kept_bytes = total;
+
+        for (index, key) in remove {
+            let (dir, manifest) = &mut manifests[index];
+            if let Some(entry) = manifest.remove(&key) {
+                std::fs::remove_file(dir.join(&key))?;
+                report.removed += 1;
+                report.freed_bytes += entry.bytes;
+            }
+        }
+        for (dir, manifest) in manifests.iter() {
+            write_manifest(dir, manifest)?;
+        }
+        report.freed_bytes += self.collect_blobs(&blobs)?;
+        Ok(report)
+    }
+
+    fn blob_bytes(&self, digest: &str) -> u64 {
+        format::blob_path(&self.cache_dir, digest)
+            .and_then(|path| std::fs::metadata(path).ok())
+            .map_or(0, |m| m.len())
+    }
+
+    /// Every blob file, by its sha256
+    fn blob_files(&self) -> Result<Vec<(String, PathBuf)>, Errors> {
+        let mut blobs = Vec::new();
+        let dir = self.cache_dir.join(BLOB_DIR);
+        if !dir.is_dir() {
+            return Ok(blobs);
+        }
+        // where blobs are written before they've got a name
+        remove_stale_temp(&dir)?;
+        for prefix in dir.read_dir()? {
+            let prefix = prefix?.path();
+            if !prefix.is_dir() {
+                continue;
+            }
+            remove_stale_temp(&prefix)?;
+            for path in files_with(&prefix, &["zst"])? {
+                if let Some(digest) = path.file_stem() {
+                    blobs.push((digest.to_string_lossy().to_string(), path));
+                }
+            }
+        }
+        Ok(blobs)
+    }
+
+    /// Delete the blobs that aren't in `referenced`, and how many bytes that freed
+    fn collect_blobs(&self, referenced: &HashSet<String>) -> Result<u64, Errors> {
+        let mut freed = 0;
+        for (digest, path) in self.blob_files()? {
+            if !referenced.contains(&digest) {
+                freed += std::fs::metadata(&path)?.len();
+                std::fs::remove_file(path)?;
             }
         }
+        Ok(freed)
     }
 
-    fn load_file(&self, cache_path: &PathBuf) -> Option<CacheData> {
-        let file = File::open(cache_path).ok()?;
+    /// An ecosystem's manifest brought into line with the files actually there, and how many
+    /// of those files had to be quarantined
+    fn reconcile(&self, dir: &Path) -> Result<(Manifest, usize), Errors> {
+        remove_stale_temp(dir)?;
+        let mut manifest = read_manifest(dir)?;
+        let mut quarantined = 0;
+        let files = entry_files(dir)?;
+        manifest.retain(|key, _| files.iter().any(|path| path.ends_with(key)));
+        for path in files {
+            let Some(key) = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+            else {
+                continue;
+            };
+            if manifest.contains_key(&key) {
+                continue;
+            }
+            match self.checked(&path, self.load_meta(&path)) {
+                Ok(meta) => {
+                    let bytes = std::fs::metadata(&path)?.len();
+                    manifest.insert(key.clone(), ManifestEntry::new(key, &meta, bytes));
+                }
+                Err(Errors::CacheCorrupt { .. }) => quarantined += 1,
+                Err(err) => return Err(err),
+            }
+        }
+        write_manifest(dir, &manifest)?;
+        Ok((manifest, quarantined))
+    }
+
+    /// What the manifest says is in an ecosystem's directory, keyed by file name
+    pub fn manifest(&self, repo_type: Option<RepoType>) -> Result<Manifest, Errors> {
+        read_manifest(&self.ecosystem_dir(repo_type))
+    }
+
+    /// An entry in the JSON format from before entries were compressed
+    fn load_file(&self, cache_path: &Path) -> Result<CacheData, Errors> {
+        let file = File::open(cache_path)?;
         let reader = BufReader::new(file);
 
         // Read the JSON contents of the file into the object
-        serde_json::from_reader(reader).ok()
+        serde_json::from_reader(reader).map_err(|err| Errors::CacheCorrupt {
+            path: cache_path.to_path_buf(),
+            source: Box::new(err),
+        })
+    }
+
+    fn is_legacy(path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext == "json")
+    }
+
+    /// Only reads as far as the end of the header
+    fn load_meta(&self, path: &Path) -> Result<CacheMeta, Errors> {
+        match Self::is_legacy(path) {
+            true => Ok(self.load_file(path)?.meta()),
+            false => format::read_meta(&mut BufReader::new(File::open(path)?), path),
+        }
+    }
+
+    fn open_path(&self, path: &Path) -> Result<(CacheMeta, ContentReader), Errors> {
+        if Self::is_legacy(path) {
+            let data = self.load_file(path)?;
+            return Ok((data.meta(), Box::new(std::io::Cursor::new(data.content))));
+        }
+        let mut file = BufReader::new(File::open(path)?);
+        let meta = format::read_meta(&mut file, path)?;
+        let content = format::content_reader(&self.cache_dir, &meta, file)?;
+        Ok((meta, content))
+    }
+
+    /// The whole of an entry, checking the content decompresses and matches its blob's name
+    fn load_entry(&self, path: &Path) -> Result<CacheData, Errors> {
+        let (meta, mut reader) = self.checked(path, self.open_path(path))?;
+        let mut content = String::new();
+        if let Err(err) = reader.read_to_string(&mut content) {
+            return self.unreadable(path, &meta, err);
+        }
+        Ok(CacheData::from_meta(meta, content))
+    }
+
+    /// Quarantine an entry whose content couldn't be read
+    fn unreadable<T>(
+        &self,
+        path: &Path,
+        meta: &CacheMeta,
+        err: std::io::Error,
+    ) -> Result<T, Errors> {
+        // other entries can share the blob, so it only goes if it's the blob that's bad
+        if let Some(blob) = meta.blob.as_ref() {
+            if !format::verify_blob(&self.cache_dir, blob) {
+                if let Some(path) = format::blob_path(&self.cache_dir, blob) {
+                    self.quarantine(&path).ok();
+                }
+            }
+        }
+        self.checked(
+            path,
+            Err(Errors::CacheCorrupt {
+                path: path.to_path_buf(),
+                source: Box::new(err),
+            }),
+        )
+    }
+
+    /// Where `url` is cached, if it is
+    fn find(&self, repo_type: RepoType, url: &str) -> Option<PathBuf> {
+        self.candidate_paths(Some(repo_type), url)
+            .into_iter()
+            .find(|path| path.exists())
+    }
+
+    /// Stream an entry's content rather than reading it all into memory
+    pub fn open(&self, repo_type: RepoType, url: &str) -> Option<(CacheMeta, ContentReader)> {
+        let path = self.find(repo_type, url)?;
+        self.checked(&path, self.open_path(&path)).ok()
+    }
+
+    /// Whether `url` was cached within `max_age`, only reading the entry's header
+    pub fn is_fresh(&self, repo_type: RepoType, url: &str, max_age: Duration) -> bool {
+        let Some(path) = self.find(repo_type, url) else {
+            return false;
+        };
+        self.checked(&path, self.load_meta(&path))
+            .is_ok_and(|meta| meta.updated + max_age >= chrono::Utc::now())
     }
 
     pub fn get_cache(
         &self,
+        repo_type: RepoType,
         url: &str,
         max_age: Option<Duration>,
         delete_expired: Option<bool>,
     ) -> Option<CacheData> {
-        let cache_path = self.cache_path(url);
-        match self.cache_path(url).exists() {
-            false => None,
-            true => {
-                let data = self.load_file(&cache_path)?;
-                if let Some(max_age) = max_age {
-                    if data.updated + max_age < chrono::Utc::now() {
-                        if let Some(delete_expired) = delete_expired {
-                            if delete_expired {
-                                std::fs::remove_file(cache_path).ok();
-                            }
-                        }
-                        return None;
-                    }
+        let cache_path = self.find(repo_type, url)?;
+        if let Some(max_age) = max_age {
+            let meta = self
+                .checked(&cache_path, self.load_meta(&cache_path))
+                .ok()?;
+            if meta.updated + max_age < chrono::Utc::now() {
+                if delete_expired == Some(true) {
+                    std::fs::remove_file(cache_path).ok();
+                }
+                return None;
+            }
+        }
+        self.load_entry(&cache_path).ok()
+    }
+
+    /// The cached copy of `url`, as long as it's within the policy's TTL for what it is
+    ///
+    /// This reads the whole of it into a string, big indexes are better off with
+    /// [Cache::get_fresh_with].
+    pub fn get_fresh(&self, repo_type: RepoType, url: &str) -> Option<CacheData> {
+        let path = self.find(repo_type, url)?;
+        let meta = self.checked(&path, self.load_meta(&path)).ok()?;
+        match self.policy.is_expired(&meta) {
+            true => None,
+            false => self.load_entry(&path).ok(),
+        }
+    }
+
+    /// Hand the cached copy of `url` to `parse` as it's decompressed, if it's within the policy's
+    /// TTL
+    ///
+    /// It's `None` when `parse` fails too, so the caller fetches it again. An entry that failed
+    /// because it couldn't be read, rather than because it didn't parse, is quarantined.
+    pub fn get_fresh_with<T>(
+        &self,
+        repo_type: RepoType,
+        url: &str,
+        parse: impl FnOnce(&mut dyn Read) -> Result<T, Errors>,
+    ) -> Option<T> {
+        let path = self.find(repo_type, url)?;
+        let (meta, reader) = self.checked(&path, self.open_path(&path)).ok()?;
+        if self.policy.is_expired(&meta) {
+            return None;
+        }
+        let mut reader = Watched {
+            inner: reader,
+            failed: None,
+        };
+        match parse(&mut reader) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                if let Some(err) = reader.failed {
+                    self.unreadable::<()>(&path, &meta, err).ok();
                 }
+                None
+            }
+        }
+    }
+
+    /// [Cache::get_fresh_with] for JSON
+    pub fn get_fresh_json<T: DeserializeOwned>(&self, repo_type: RepoType, url: &str) -> Option<T> {
+        self.get_fresh_with(repo_type, url, |reader| {
+            Ok(serde_json::from_reader(BufReader::new(reader))?)
+        })
+    }
+
+    /// Every entry on disk, with the ones whose header won't load as errors
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, Errors> {
+        let mut entries = Vec::new();
+        let mut files = self.legacy_files()?;
+        for dir in self.ecosystem_dirs() {
+            files.extend(entry_files(&dir)?);
+        }
+        for path in files {
+            let meta = self.load_meta(&path);
+            entries.push((path, meta));
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+
+    pub fn stats(&self) -> Result<CacheStats, Errors> {
+        let mut stats = CacheStats::default();
+        let now = Utc::now();
+        for (path, meta) in self.entries()? {
+            let bytes = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            let (ecosystem, age) = match meta.as_ref() {
+                Ok(meta) => (
+                    meta.repo_type
+                        .map_or("unknown", |repo_type| repo_type.name())
+                        .to_string(),
+                    Some(now - meta.updated),
+                ),
+                Err(_) => ("corrupt".to_string(), None),
+            };
+            let ecosystem = stats.ecosystems.entry(ecosystem).or_default();
+            ecosystem.entries += 1;
+            ecosystem.bytes += bytes;
+            if let Some(age) = age {
+                ecosystem.ages[CacheStats::age_bucket(age)] += 1;
+            }
+            if meta.is_ok_and(|meta| self.policy.is_expired(&meta)) {
+                ecosystem.expired += 1;
+            }
+        }
+        for (_, path) in self.blob_files()? {
+            let blobs = stats.ecosystems.entry(BLOB_DIR.to_string()).or_default();
+            blobs.entries += 1;
+            blobs.bytes += std::fs::metadata(path)?.len();
+        }
+        let quarantine = self.cache_dir.join(QUARANTINE_DIR);
+        if quarantine.is_dir() {
+            let quarantined = stats
+                .ecosystems
+                .entry(QUARANTINE_DIR.to_string())
+                .or_default();
+            for file in quarantine.read_dir()? {
+                quarantined.entries += 1;
+                quarantined.bytes += file?.metadata()?.len();
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Where the entry for `url` lives and what it says about itself, if there is one
+    pub fn inspect(&self, url: &str) -> Option<(PathBuf, CacheMeta)> {
+        let hash = hash_url(url);
+        let path = self
+            .ecosystem_dirs()
+            .into_iter()
+            .chain(std::iter::once(self.cache_dir.clone()))
+            .flat_map(|dir| [ENTRY_EXT, "json"].map(|ext| dir.join(format!("{}.{}", hash, ext))))
+            .find(|path| path.exists())?;
+        let meta = self.checked(&path, self.load_meta(&path)).ok()?;
+        Some((path, meta))
+    }
 
-                Some(data)
+    /// Delete the entries matching both filters, `None` matching everything, and how many went
+    ///
+    /// Entries that won't load are only removed when neither filter is set.
+    pub fn purge(
+        &mut self,
+        repo_type: Option<RepoType>,
+        kind: Option<ResourceKind>,
+    ) -> Result<usize, Errors> {
+        let _lock = self.lock()?;
+        let mut removed = 0;
+        for (path, meta) in self.entries()? {
+            let matches = match meta {
+                Ok(meta) => {
+                    repo_type.is_none_or(|repo_type| meta.repo_type == Some(repo_type))
+                        && kind.is_none_or(|kind| meta.kind == kind)
+                }
+                Err(_) => repo_type.is_none() && kind.is_none(),
+            };
+            if matches {
+                std::fs::remove_file(path)?;
+                removed += 1;
             }
         }
+        let mut referenced = HashSet::new();
+        for dir in self.ecosystem_dirs() {
+            let (manifest, _) = self.reconcile(&dir)?;
+            referenced.extend(manifest.into_values().filter_map(|entry| entry.blob));
+        }
+        self.collect_blobs(&referenced)?;
+        self.data.clear();
+        Ok(removed)
     }
 }
 
+/// Per-ecosystem totals, entries we can't read are counted under "corrupt", and the ones that have
+/// already been moved aside under "quarantine"
+#[derive(Serialize, Debug, Default)]
+pub struct CacheStats {
+    pub ecosystems: BTreeMap<String, EcosystemStats>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct EcosystemStats {
+    pub entries: usize,
+    pub bytes: u64,
+    /// Past the TTL for their kind
+    pub expired: usize,
+    /// How many entries are in each of [CacheStats::AGE_BUCKETS]
+    pub ages: [usize; 5],
+}
+
+impl CacheStats {
+    pub const AGE_BUCKETS: [&'static str; 5] = ["<1h", "<1d", "<1w", "<30d", "older"];
+
+    fn age_bucket(age: Duration) -> usize {
+        match age {
+            age if age < Duration::hours(1) => 0,
+            age if age < Duration::days(1) => 1,
+            age if age < Duration::weeks(1) => 2,
+            age if age < Duration::days(30) => 3,
+            _ => 4,
+        }
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<10} {:>8} {:>12} {:>8}",
+            "ecosystem", "entries", "bytes", "expired"
+        )?;
+        for bucket in Self::AGE_BUCKETS {
+            write!(f, " {:>6}", bucket)?;
+        }
+        writeln!(f)?;
+        for (name, stats) in self.ecosystems.iter() {
+            write!(
+                f,
+                "{:<10} {:>8} {:>12} {:>8}",
+                name, stats.entries, stats.bytes, stats.expired
+            )?;
+            for count in stats.ages {
+                write!(f, " {:>6}", count)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything about an entry but its content, which is what an entry file's header holds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CacheMeta {
+    pub cache_id: String,
+    pub updated: DateTime<Utc>,
+    pub url: String,
+    pub repo_type: Option<RepoType>,
+    pub kind: ResourceKind,
+    /// Of the content, uncompressed
+    pub size: u64,
+    /// The sha256 of the content, when it's in `blobs/` rather than after the header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CacheData {
     /// Typically the etag or whatever expiry data you've got
@@ -115,6 +1013,11 @@ pub struct CacheData {
     pub url: String,
     /// The data
     pub content: String,
+    /// Which backend stored it, missing from entries written before this was recorded
+    #[serde(default)]
+    pub repo_type: Option<RepoType>,
+    #[serde(default)]
+    pub kind: ResourceKind,
 }
 
 impl CacheData {
@@ -124,8 +1027,41 @@ impl CacheData {
             cache_id,
             content,
             updated: chrono::Utc::now(),
+            repo_type: None,
+            kind: ResourceKind::default(),
         }
     }
+
+    fn from_meta(meta: CacheMeta, content: String) -> Self {
+        Self {
+            cache_id: meta.cache_id,
+            updated: meta.updated,
+            url: meta.url,
+            content,
+            repo_type: meta.repo_type,
+            kind: meta.kind,
+        }
+    }
+
+    /// Tag it with what it is, so the right TTL applies
+    pub fn with_kind(mut self, repo_type: RepoType, kind: ResourceKind) -> Self {
+        self.repo_type = Some(repo_type);
+        self.kind = kind;
+        self
+    }
+
+    pub fn meta(&self) -> CacheMeta {
+        CacheMeta {
+            cache_id: self.cache_id.clone(),
+            updated: self.updated,
+            url: self.url.clone(),
+            repo_type: self.repo_type,
+            kind: self.kind,
+            size: self.content.len() as u64,
+            blob: None,
+        }
+    }
+
     pub fn get_hash(&self) -> String {
         hash_url(&self.url)
     }
@@ -0,0 +1,239 @@
+//! How entries are laid out on disk
+//!
+//! An entry is [MAGIC], the length of its metadata as a little-endian `u32`, the metadata as JSON,
+//! then the content zstd compressed. Content bigger than [INLINE_MAX] goes in `blobs/` instead,
+//! named by its sha256, so identical payloads are only stored once.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use super::{temp_path, CacheMeta};
+use crate::Errors;
+
+pub(super) const MAGIC: &[u8; 4] = b"TTC1";
+pub(super) const ENTRY_EXT: &str = "entry";
+pub(super) const BLOB_DIR: &str = "blobs";
+/// Content bigger than this is stored content-addressed
+pub(super) const INLINE_MAX: usize = 64 * 1024;
+/// Anything claiming more metadata than this is a corrupt header
+const META_MAX: u32 = 1024 * 1024;
+const LEVEL: i32 = 3;
+
+pub(super) type ContentReader = Box<dyn Read + Send>;
+
+fn corrupt(path: &Path, message: &str) -> Errors {
+    Errors::CacheCorrupt {
+        path: path.to_path_buf(),
+        source: message.into(),
+    }
+}
+
+/// Write to `path` by way of a temp file, so it's either all there or not at all
+fn write_via_temp(
+    path: &Path,
+    write: impl FnOnce(BufWriter<File>) -> Result<BufWriter<File>, Errors>,
+) -> Result<(), Errors> {
+    let temp = temp_path(path);
+    let result = File::create(&temp)
+        .map_err(Errors::from)
+        .and_then(|file| write(BufWriter::new(file)))
+        .and_then(|mut file| Ok(file.flush()?))
+        .and_then(|_| Ok(std::fs::rename(&temp, path)?));
+    if result.is_err() {
+        std::fs::remove_file(&temp).ok();
+    }
+    result
+}
+
+/// `content` is `None` when it's in a blob
+pub(super) fn write_entry(
+    path: &Path,
+    meta: &CacheMeta,
+    content: Option<&[u8]>,
+) -> Result<(), Errors> {
+    let meta_json = serde_json::to_vec(meta)?;
+    write_via_temp(path, |mut file| {
+        file.write_all(MAGIC)?;
+        file.write_all(&(meta_json.len() as u32).to_le_bytes())?;
+        file.write_all(&meta_json)?;
+        if let Some(content) = content {
+            let mut encoder = zstd::Encoder::new(file, LEVEL)?;
+            encoder.write_all(content)?;
+            file = encoder.finish()?;
+        }
+        Ok(file)
+    })
+}
+
+/// Just the metadata, leaving `reader` at the start of the content
+pub(super) fn read_meta(reader: &mut impl Read, path: &Path) -> Result<CacheMeta, Errors> {
+    let mut magic = [0; 4];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|_| corrupt(path, "truncated header"))?;
+    if &magic != MAGIC {
+        return Err(corrupt(path, "not a cache entry"));
+    }
+    let mut len = [0; 4];
+    reader
+        .read_exact(&mut len)
+        .map_err(|_| corrupt(path, "truncated header"))?;
+    let len = u32::from_le_bytes(len);
+    if len > META_MAX {
+        return Err(corrupt(path, "metadata length is implausible"));
+    }
+    let mut meta = vec![0; len as usize];
+    reader
+        .read_exact(&mut meta)
+        .map_err(|_| corrupt(path, "truncated metadata"))?;
+    let meta: CacheMeta = serde_json::from_slice(&meta).map_err(|err| Errors::CacheCorrupt {
+        path: path.to_path_buf(),
+        source: Box::new(err),
+    })?;
+    // it's joined onto the cache dir, so anything else could point outside it
+    if meta
+        .blob
+        .as_deref()
+        .is_some_and(|digest| !is_sha256(digest))
+    {
+        return Err(corrupt(path, "blob digest isn't a sha256"));
+    }
+    Ok(meta)
+}
+
+/// 64 lowercase hex digits, the only thing that's safe to use as a blob's file name
+fn is_sha256(digest: &str) -> bool {
+    digest.len() == 64
+        && digest
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// `None` if `digest` isn't a sha256, which only happens when something's been tampered with
+pub(super) fn blob_path(cache_dir: &Path, digest: &str) -> Option<PathBuf> {
+    is_sha256(digest).then(|| {
+        cache_dir
+            .join(BLOB_DIR)
+            .join(&digest[..2])
+            .join(format!("{}.zst", digest))
+    })
+}
+
+/// Compress `content` into `blobs/` as it's read, handing back its sha256 and uncompressed size
+///
+/// The name isn't known until it's all been read, so it goes to a temp file in `blobs/` first,
+/// which is dropped if there's already a blob with the same content.
+pub(super) fn write_blob(cache_dir: &Path, content: impl Read) -> Result<(String, u64), Errors> {
+    let dir = cache_dir.join(BLOB_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let temp = temp_path(&dir.join("incoming.zst"));
+    let mut hashing = Hashing {
+        inner: content,
+        hasher: Sha256::new(),
+        size: 0,
+    };
+    let written = File::create(&temp).map_err(Errors::from).and_then(|file| {
+        let mut encoder = zstd::Encoder::new(BufWriter::new(file), LEVEL)?;
+        std::io::copy(&mut hashing, &mut encoder)?;
+        Ok(encoder.finish()?.flush()?)
+    });
+    let digest = format!("{:x}", hashing.hasher.finalize());
+    let placed = written.and_then(|_| {
+        let path = blob_path(cache_dir, &digest).ok_or_else(|| corrupt(&temp, "bad digest"))?;
+        if path.exists() {
+            return Ok(std::fs::remove_file(&temp)?);
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(std::fs::rename(&temp, path)?)
+    });
+    if placed.is_err() {
+        std::fs::remove_file(&temp).ok();
+    }
+    placed.map(|_| (digest, hashing.size))
+}
+
+/// Hashes and counts what's read through it
+struct Hashing<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.size += read as u64;
+        Ok(read)
+    }
+}
+
+/// Decompresses as it's read, from what follows the header or from the blob it points at
+pub(super) fn content_reader(
+    cache_dir: &Path,
+    meta: &CacheMeta,
+    rest: BufReader<File>,
+) -> Result<ContentReader, Errors> {
+    match meta.blob.as_ref() {
+        None => Ok(Box::new(zstd::Decoder::with_buffer(rest)?)),
+        Some(digest) => {
+            let path = blob_path(cache_dir, digest)
+                .ok_or_else(|| corrupt(Path::new(digest), "blob digest isn't a sha256"))?;
+            let blob = File::open(&path).map_err(|_| corrupt(&path, "blob is missing"))?;
+            Ok(Box::new(Verified {
+                inner: zstd::Decoder::new(blob)?,
+                hasher: Sha256::new(),
+                expected: digest.clone(),
+                finished: false,
+            }))
+        }
+    }
+}
+
+/// Whether a blob decompresses to content matching its name, reading it all to find out
+pub(super) fn verify_blob(cache_dir: &Path, digest: &str) -> bool {
+    let Some(Ok(blob)) = blob_path(cache_dir, digest).map(File::open) else {
+        return false;
+    };
+    let Ok(decoder) = zstd::Decoder::new(blob) else {
+        return false;
+    };
+    let mut verified = Verified {
+        inner: decoder,
+        hasher: Sha256::new(),
+        expected: digest.to_string(),
+        finished: false,
+    };
+    std::io::copy(&mut verified, &mut std::io::sink()).is_ok()
+}
+
+/// Checks a blob's content against its name once it's all been read
+struct Verified<R> {
+    inner: R,
+    hasher: Sha256,
+    expected: String,
+    finished: bool,
+}
+
+impl<R: Read> Read for Verified<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        if read == 0 && !buf.is_empty() && !self.finished {
+            self.finished = true;
+            let digest = format!("{:x}", self.hasher.finalize_reset());
+            if digest != self.expected {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("blob content doesn't match its sha256 {}", self.expected),
+                ));
+            }
+        }
+        Ok(read)
+    }
+}
@@ -0,0 +1,567 @@
+//! Configuration, layered from the defaults, `~/.config/tidetrawler/config.toml`, `TIDETRAWLER_*`
+//! environment variables and command line flags, each winning over the ones before it
+//!
+//! Environment variables put `__` between the parts of a key, so `cache.dir` is
+//! `TIDETRAWLER_CACHE__DIR` and `registries.npm.token` is `TIDETRAWLER_REGISTRIES__NPM__TOKEN`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::cache::{CachePolicy, ResourceKind};
+use crate::request::{ClientSettings, RateLimit, RetryPolicy};
+use crate::{get_cache_dir, Errors, RepoType};
+
+/// Points at a config file other than [default_path]
+pub const CONFIG_ENV: &str = "TIDETRAWLER_CONFIG";
+const ENV_PREFIX: &str = "TIDETRAWLER_";
+/// Keys that `config show` doesn't print the value of
+const SECRET_KEYS: [&str; 2] = ["token", "password"];
+/// Keys holding URLs, which `config show` prints without any credentials in them
+const URL_KEYS: [&str; 3] = ["url", "extra_urls", "proxy"];
+
+/// `$XDG_CONFIG_HOME/tidetrawler/config.toml`, which is usually under `~/.config`
+pub fn default_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("tidetrawler").join("config.toml"))
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The ecosystems to ask, by [RepoType::name]
+    pub backends: Vec<String>,
+    pub cache: CacheConfig,
+    pub http: HttpConfig,
+    pub output: OutputConfig,
+    /// Endpoints and credentials, by ecosystem
+    pub registries: HashMap<String, RegistryConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backends: [RepoType::Cargo, RepoType::PyPi, RepoType::Npm]
+                .iter()
+                .map(|repo_type| repo_type.name().to_string())
+                .collect(),
+            cache: CacheConfig::default(),
+            http: HttpConfig::default(),
+            output: OutputConfig::default(),
+            registries: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn repo_types(&self) -> Result<Vec<RepoType>, Errors> {
+        self.backends
+            .iter()
+            .map(|backend| RepoType::from_str(backend))
+            .collect()
+    }
+
+    pub fn registry(&self, repo_type: RepoType) -> Option<&RegistryConfig> {
+        self.registries.get(repo_type.name())
+    }
+
+    /// Catch typos in the ecosystem names and rate limits that never refill, which serde can't see
+    fn validate(&self) -> Result<(), Errors> {
+        let names = self
+            .backends
+            .iter()
+            .chain(self.registries.keys())
+            .chain(self.cache.ttl.keys())
+            .chain(self.http.ecosystem_rate_limits.keys());
+        for name in names {
+            RepoType::from_str(name).map_err(|err| Errors::Config(err.to_string()))?;
+        }
+        self.cache
+            .policy()
+            .map_err(|err| Errors::Config(err.to_string()))?;
+        let limits = self
+            .http
+            .rate_limits
+            .iter()
+            .map(|(host, limit)| ("rate_limits", host, limit))
+            .chain(
+                self.http
+                    .ecosystem_rate_limits
+                    .iter()
+                    .map(|(name, limit)| ("ecosystem_rate_limits", name, limit)),
+            );
+        for (table, key, limit) in limits {
+            if !limit.is_valid() {
+                return Err(Errors::Config(format!(
+                    "http.{}.{}.per_second has to be more than 0, not {}",
+                    table, key, limit.per_second
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Defaults to `~/.cache/tidetrawler`
+    pub dir: Option<PathBuf>,
+    /// Seconds search results are kept
+    pub search_ttl: u64,
+    /// Seconds before a mirrored index gets fetched again
+    pub index_ttl: u64,
+    /// Seconds package metadata is kept, immutable release metadata is kept forever
+    pub metadata_ttl: u64,
+    /// Overrides by ecosystem and then kind, eg `ttl.npm.search = 60`
+    pub ttl: HashMap<String, HashMap<String, u64>>,
+    /// Bytes `cache prune` trims the cache down to, oldest entries first
+    pub max_size: Option<u64>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        let policy = CachePolicy::default();
+        let default_ttl = |kind| {
+            policy
+                .max_age(None, kind)
+                .map_or(0, |max_age| max_age.num_seconds() as u64)
+        };
+        Self {
+            dir: None,
+            search_ttl: default_ttl(ResourceKind::Search),
+            index_ttl: default_ttl(ResourceKind::Index),
+            metadata_ttl: default_ttl(ResourceKind::Metadata),
+            ttl: HashMap::new(),
+            max_size: None,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn policy(&self) -> Result<CachePolicy, Errors> {
+        let seconds = |ttl: u64| chrono::Duration::seconds(ttl as i64);
+        let mut policy = CachePolicy {
+            defaults: HashMap::from([
+                (ResourceKind::Search, seconds(self.search_ttl)),
+                (ResourceKind::Index, seconds(self.index_ttl)),
+                (ResourceKind::Metadata, seconds(self.metadata_ttl)),
+            ]),
+            overrides: HashMap::new(),
+        };
+        for (ecosystem, kinds) in self.ttl.iter() {
+            let repo_type = RepoType::from_str(ecosystem)?;
+            for (kind, ttl) in kinds {
+                policy
+                    .overrides
+                    .insert((repo_type, ResourceKind::from_str(kind)?), seconds(*ttl));
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Seconds before an ecosystem's index is due a refresh
+    pub fn ttl_for(&self, repo_type: RepoType) -> u64 {
+        self.ttl
+            .get(repo_type.name())
+            .and_then(|kinds| kinds.get(ResourceKind::Index.name()))
+            .copied()
+            .unwrap_or(self.index_ttl)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Used for everything, otherwise `HTTPS_PROXY` and friends are
+    pub proxy: Option<String>,
+    /// Seconds for the whole request
+    pub timeout: u64,
+    pub connect_timeout: u64,
+    pub max_retries: u32,
+    /// How many backends to ask at once
+    pub concurrency: usize,
+    /// By host, over the top of each ecosystem's default
+    pub rate_limits: HashMap<String, RateLimit>,
+    /// By ecosystem, for whatever host that backend talks to, eg
+    /// `ecosystem_rate_limits.cargo = { per_second = 2.0, burst = 2 }`
+    pub ecosystem_rate_limits: HashMap<String, RateLimit>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        let settings = ClientSettings::default();
+        Self {
+            proxy: None,
+            timeout: settings.timeout.as_secs(),
+            connect_timeout: settings.connect_timeout.as_secs(),
+            max_retries: settings.retry.max_retries,
+            concurrency: 4,
+            rate_limits: HashMap::new(),
+            ecosystem_rate_limits: HashMap::new(),
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn client_settings(&self) -> ClientSettings {
+        ClientSettings {
+            timeout: Duration::from_secs(self.timeout),
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            retry: RetryPolicy {
+                max_retries: self.max_retries,
+                ..Default::default()
+            },
+            rate_limits: self.rate_limits.clone(),
+            // the names were checked when the config was loaded
+            ecosystem_rate_limits: self
+                .ecosystem_rate_limits
+                .iter()
+                .filter_map(|(name, limit)| Some((RepoType::from_str(name).ok()?, *limit)))
+                .collect(),
+            proxy: self.proxy.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Pretty-printed
+    #[default]
+    Json,
+    /// One package per line
+    JsonLines,
+    /// For people
+    Text,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Value::String(s.to_string()).try_into()?)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub format: OutputFormat,
+}
+
+/// Where to find one ecosystem's registry, what the URL means depends on the ecosystem
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistryConfig {
+    pub url: Option<String>,
+    /// Which sort of server `url` is, for the ecosystems that can talk to more than one, eg
+    /// `gitea` for a Gitea or Forgejo server under `github`
+    pub kind: Option<String>,
+    /// More indexes or repositories, for the ecosystems that can have several
+    pub extra_urls: Vec<String>,
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Where a setting came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    /// The variable's name
+    Env(String),
+    /// The flag
+    Cli(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Cli(flag) => write!(f, "{}", flag),
+        }
+    }
+}
+
+/// Every setting by its key path, with where it came from
+type Settings = BTreeMap<Vec<String>, (Value, Source)>;
+
+fn flatten(table: &Table, prefix: &[String], source: &Source, settings: &mut Settings) {
+    for (key, value) in table {
+        let mut path = prefix.to_vec();
+        path.push(key.clone());
+        match value {
+            Value::Table(table) => flatten(table, &path, source, settings),
+            value => {
+                settings.insert(normalise(path), (value.clone(), source.clone()));
+            }
+        }
+    }
+}
+
+/// Ecosystems and kinds by their proper names, so `ttl.crates.index` and `ttl.cargo.index` are
+/// the same setting, and the later layer wins
+fn normalise(mut path: Vec<String>) -> Vec<String> {
+    let at = match path.as_slice() {
+        [section, ..] if section == "registries" => 1,
+        [section, table, ..] if section == "cache" && table == "ttl" => 2,
+        [section, table, ..] if section == "http" && table == "ecosystem_rate_limits" => 2,
+        _ => return path,
+    };
+    if let Some(Ok(repo_type)) = path.get(at).map(|name| RepoType::from_str(name)) {
+        path[at] = repo_type.name().to_string();
+    }
+    if path[0] == "cache" {
+        if let Some(Ok(kind)) = path.get(3).map(|kind| ResourceKind::from_str(kind)) {
+            path[3] = kind.name().to_string();
+        }
+    }
+    path
+}
+
+fn unflatten(settings: &Settings) -> Table {
+    let mut root = Table::new();
+    for (path, (value, _)) in settings {
+        let Some((last, parents)) = path.split_last() else {
+            continue;
+        };
+        let mut table = &mut root;
+        for part in parents {
+            let entry = table
+                .entry(part.clone())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            let Value::Table(inner) = entry else {
+                unreachable!()
+            };
+            table = inner;
+        }
+        table.insert(last.clone(), value.clone());
+    }
+    root
+}
+
+/// Hides the `user:password@` of a URL, or of each one in a list. Proxies are often
+/// written without a scheme, so this doesn't go through [reqwest::Url]
+fn mask_userinfo(value: &Value) -> Value {
+    match value {
+        Value::String(url) => {
+            let start = url.find("://").map_or(0, |at| at + 3);
+            let end = url[start..]
+                .find(['/', '?', '#'])
+                .map_or(url.len(), |at| start + at);
+            match url[start..end].rfind('@') {
+                Some(at) => {
+                    Value::String(format!("{}********{}", &url[..start], &url[start + at..]))
+                }
+                None => value.clone(),
+            }
+        }
+        Value::Array(urls) => Value::Array(urls.iter().map(mask_userinfo).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// A key as you'd write it in TOML, quoting the parts that need it
+fn display_key(path: &[String]) -> String {
+    path.iter()
+        .map(|part| {
+            match !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                true => part.clone(),
+                false => Value::String(part.clone()).to_string(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Builds up a [Config] a layer at a time
+#[derive(Debug, Clone)]
+pub struct ConfigLoader {
+    settings: Settings,
+    defaults: Settings,
+}
+
+impl Default for ConfigLoader {
+    fn default() -> Self {
+        let mut defaults = Settings::new();
+        if let Ok(Value::Table(table)) = Value::try_from(Config::default()) {
+            flatten(&table, &[], &Source::Default, &mut defaults);
+        }
+        Self {
+            settings: defaults.clone(),
+            defaults,
+        }
+    }
+}
+
+impl ConfigLoader {
+    /// Add a TOML file over the top, which is fine to be missing unless it's `required`
+    pub fn with_file(mut self, path: &Path, required: bool) -> Result<Self, Errors> {
+        if !required && !path.exists() {
+            return Ok(self);
+        }
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| Errors::Config(format!("Can't read {}: {}", path.display(), err)))?;
+        let table: Table = toml::from_str(&content).map_err(|err| Errors::Parse {
+            url: Some(path.display().to_string()),
+            offset: err.span().map(|span| span.start),
+            source: Box::new(err),
+        })?;
+        flatten(
+            &table,
+            &[],
+            &Source::File(path.to_path_buf()),
+            &mut self.settings,
+        );
+        Ok(self)
+    }
+
+    /// Pick out the `TIDETRAWLER_SECTION__KEY` variables, ignoring other `TIDETRAWLER_` ones
+    pub fn with_env(
+        mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, Errors> {
+        let sections: Vec<&String> = self
+            .defaults
+            .keys()
+            .filter_map(|path| path.first())
+            .collect();
+        let mut found = Vec::new();
+        for (var, value) in vars {
+            let Some(key) = var.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let path: Vec<String> = key.split("__").map(|part| part.to_lowercase()).collect();
+            if !sections.contains(&&path[0]) && path[0] != "registries" {
+                continue;
+            }
+            found.push((path, value, var));
+        }
+        for (path, value, var) in found {
+            self = self.with_setting(path, &value, Source::Env(var))?;
+        }
+        Ok(self)
+    }
+
+    /// Set `key` (dotted, as in TOML) to `value`, which is a string if it isn't valid TOML
+    pub fn with_override(self, key: &str, value: &str, source: Source) -> Result<Self, Errors> {
+        let parsed: Table = toml::from_str(&format!("{} = true", key))
+            .map_err(|_| Errors::Config(format!("Invalid config key {}", key)))?;
+        let mut paths = Settings::new();
+        flatten(&parsed, &[], &source, &mut paths);
+        match paths.into_keys().next() {
+            Some(path) => self.with_setting(path, value, source),
+            None => Err(Errors::Config(format!("Invalid config key {}", key))),
+        }
+    }
+
+    fn with_setting(
+        mut self,
+        path: Vec<String>,
+        raw: &str,
+        source: Source,
+    ) -> Result<Self, Errors> {
+        let value = toml::from_str::<Table>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(|| Value::String(raw.to_string()));
+        // lists can be given comma separated
+        let value = match (self.defaults.get(&path), value) {
+            (Some((Value::Array(_), _)), Value::String(list)) => Value::Array(
+                list.split(',')
+                    .map(|item| Value::String(item.trim().to_string()))
+                    .filter(|item| item.as_str() != Some(""))
+                    .collect(),
+            ),
+            (_, value) => value,
+        };
+        self.settings.insert(normalise(path), (value, source));
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<LoadedConfig, Errors> {
+        let config: Config = Value::Table(unflatten(&self.settings))
+            .try_into()
+            .map_err(|err: toml::de::Error| Errors::Config(err.message().to_string()))?;
+        config.validate()?;
+        Ok(LoadedConfig {
+            config,
+            settings: self.settings,
+        })
+    }
+
+    /// The file (from `path`, [CONFIG_ENV] or [default_path]) and then the environment
+    pub fn load(path: Option<&Path>) -> Result<Self, Errors> {
+        let loader = Self::default();
+        let loader = match path.map(PathBuf::from) {
+            Some(path) => loader.with_file(&path, true)?,
+            None => match std::env::var_os(CONFIG_ENV) {
+                Some(path) => loader.with_file(Path::new(&path), true)?,
+                None => match default_path() {
+                    Some(path) => loader.with_file(&path, false)?,
+                    None => loader,
+                },
+            },
+        };
+        loader.with_env(std::env::vars())
+    }
+}
+
+/// The [Config] everything ended up as, and where each bit of it came from
+#[derive(Debug, Clone)]
+pub struct LoadedConfig {
+    pub config: Config,
+    settings: Settings,
+}
+
+impl LoadedConfig {
+    /// Where a dotted key's value came from
+    pub fn source(&self, key: &str) -> Option<&Source> {
+        let path: Vec<String> = key.split('.').map(String::from).collect();
+        self.settings
+            .get(&normalise(path))
+            .map(|(_, source)| source)
+    }
+
+    /// A line per setting, `key = value  # source`, with secrets hidden
+    pub fn show(&self) -> String {
+        let mut settings = self.settings.clone();
+        // there's no default in the file, but it's worth knowing where it is
+        settings
+            .entry(vec!["cache".to_string(), "dir".to_string()])
+            .or_insert_with(|| {
+                let dir = get_cache_dir().display().to_string();
+                (Value::String(dir), Source::Default)
+            });
+        settings
+            .iter()
+            .map(|(path, (value, source))| {
+                let key = path.last().map(String::as_str).unwrap_or_default();
+                let value = match key {
+                    key if SECRET_KEYS.contains(&key) => "\"********\"".to_string(),
+                    key if URL_KEYS.contains(&key) => mask_userinfo(value).to_string(),
+                    _ => value.to_string(),
+                };
+                format!("{} = {}  # {}\n", display_key(path), value, source)
+            })
+            .collect()
+    }
+}
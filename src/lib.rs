@@ -1,62 +1,285 @@
 use std::path::PathBuf;
-use std::time::SystemTimeError;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTimeError};
 
 use serde::{Deserialize, Serialize};
 
 pub mod cache;
+pub mod config;
+pub mod orchestrator;
 pub mod repo;
 pub mod request;
+#[cfg(feature = "store")]
+pub mod store;
 
 #[cfg(test)]
 mod tests;
 
+/// Everything that can go wrong talking to a repository or the cache, structured so callers
+/// can tell a missing package apart from a network failure
 #[derive(Debug)]
 pub enum Errors {
-    Generic(String),
+    /// The package doesn't exist in that ecosystem
+    NotFound {
+        repo_type: RepoType,
+        name: String,
+    },
+    /// Any other unsuccessful HTTP status
+    Http {
+        url: String,
+        status: reqwest::StatusCode,
+    },
+    /// A 429 (or a 503 with `Retry-After`), with how long the server asked us to wait
+    RateLimited {
+        url: String,
+        retry_after: Option<Duration>,
+    },
+    /// A 401 or 403, the credentials are missing or wrong
+    Unauthorized {
+        url: String,
+    },
+    /// Something didn't parse, with where it came from and how far in it went wrong when we know
+    Parse {
+        url: Option<String>,
+        offset: Option<usize>,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// A cache entry we couldn't read back
+    CacheCorrupt {
+        path: PathBuf,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// Couldn't reach the server and there's nothing cached to fall back on
+    Offline {
+        url: Option<String>,
+        source: Option<reqwest::Error>,
+    },
+    Timeout {
+        url: Option<String>,
+        source: reqwest::Error,
+    },
+    /// The repository can't do that, or this one hasn't been taught how yet
+    Unsupported {
+        repo_type: RepoType,
+        operation: String,
+    },
+    /// A package name, version or spec we can't make sense of
+    InvalidInput(String),
+    /// Something wrong with a config file or the environment
+    Config(String),
+    Url(url::ParseError),
+    Io(std::io::Error),
     Reqwest(reqwest::Error),
+    /// From the SQLite metadata store
+    #[cfg(feature = "store")]
+    Store(rusqlite::Error),
+}
+
+impl Errors {
+    /// Whether this is a missing package, or a 404/410 that hasn't been turned into one yet
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Self::NotFound { .. } => true,
+            Self::Http { status, .. } => {
+                *status == reqwest::StatusCode::NOT_FOUND || *status == reqwest::StatusCode::GONE
+            }
+            _ => false,
+        }
+    }
+
+    /// Turn a 404 from fetching a package into a [Errors::NotFound] for it
+    pub fn or_not_found(self, repo_type: RepoType, name: &str) -> Self {
+        match self.is_not_found() {
+            true => Self::NotFound {
+                repo_type,
+                name: name.to_string(),
+            },
+            false => self,
+        }
+    }
+
+    /// A parse error from something other than serde, like a hand-rolled format
+    pub fn parse(url: Option<&str>, offset: Option<usize>, message: impl Into<String>) -> Self {
+        Self::Parse {
+            url: url.map(String::from),
+            offset,
+            source: message.into().into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Errors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound { repo_type, name } => {
+                write!(f, "{} wasn't found in {:?}", name, repo_type)
+            }
+            Self::Http { url, status } => write!(f, "{} returned {}", url, status),
+            Self::RateLimited { url, retry_after } => match retry_after {
+                Some(retry_after) => write!(
+                    f,
+                    "Rate limited by {}, retry after {}s",
+                    url,
+                    retry_after.as_secs()
+                ),
+                None => write!(f, "Rate limited by {}", url),
+            },
+            Self::Unauthorized { url } => write!(f, "Not authorized to access {}", url),
+            Self::Parse {
+                url,
+                offset,
+                source,
+            } => {
+                write!(f, "Couldn't parse")?;
+                if let Some(url) = url {
+                    write!(f, " {}", url)?;
+                }
+                if let Some(offset) = offset {
+                    write!(f, " at byte {}", offset)?;
+                }
+                write!(f, ": {}", source)
+            }
+            Self::CacheCorrupt { path, source } => {
+                write!(f, "Corrupt cache entry {}: {}", path.display(), source)
+            }
+            Self::Offline { url, .. } => match url {
+                Some(url) => write!(f, "Couldn't reach {} and it isn't cached", url),
+                None => write!(f, "Offline and it isn't cached"),
+            },
+            Self::Timeout { url, .. } => match url {
+                Some(url) => write!(f, "Timed out fetching {}", url),
+                None => write!(f, "Timed out"),
+            },
+            Self::Unsupported {
+                repo_type,
+                operation,
+            } => write!(f, "{:?} doesn't support {}", repo_type, operation),
+            Self::InvalidInput(message) => write!(f, "{}", message),
+            Self::Config(message) => write!(f, "Config error: {}", message),
+            Self::Url(err) => write!(f, "Invalid URL: {}", err),
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Reqwest(err) => write!(f, "{}", err),
+            #[cfg(feature = "store")]
+            Self::Store(err) => write!(f, "Metadata store error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Errors {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Parse { source, .. } | Self::CacheCorrupt { source, .. } => Some(source.as_ref()),
+            Self::Offline {
+                source: Some(source),
+                ..
+            }
+            | Self::Timeout { source, .. }
+            | Self::Reqwest(source) => Some(source),
+            Self::Url(err) => Some(err),
+            Self::Io(err) => Some(err),
+            #[cfg(feature = "store")]
+            Self::Store(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
 impl From<reqwest::Error> for Errors {
     fn from(err: reqwest::Error) -> Self {
-        Self::Reqwest(err)
+        let url = err.url().map(|url| url.to_string());
+        if err.is_timeout() {
+            return Self::Timeout { url, source: err };
+        }
+        if err.is_connect() {
+            return Self::Offline {
+                url,
+                source: Some(err),
+            };
+        }
+        if err.is_decode() {
+            return Self::Parse {
+                url,
+                offset: None,
+                source: Box::new(err),
+            };
+        }
+        match (err.status(), url) {
+            (Some(status), Some(url)) => request::status_error(url, status, None),
+            _ => Self::Reqwest(err),
+        }
     }
 }
-impl From<&reqwest::Error> for Errors {
-    fn from(err: &reqwest::Error) -> Self {
-        Self::Generic(format!("reqwest error: {:?}", err))
+
+impl From<url::ParseError> for Errors {
+    fn from(err: url::ParseError) -> Self {
+        Self::Url(err)
+    }
+}
+
+#[cfg(feature = "store")]
+impl From<rusqlite::Error> for Errors {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Store(err)
     }
 }
 
 impl From<std::io::Error> for Errors {
     fn from(err: std::io::Error) -> Self {
-        Self::Generic(format!("{:?}", err))
+        Self::Io(err)
     }
 }
 impl From<SystemTimeError> for Errors {
     fn from(err: SystemTimeError) -> Self {
-        Self::Generic(format!("{:?}", err))
+        Self::Io(std::io::Error::other(err))
     }
 }
 impl From<serde_json::Error> for Errors {
     fn from(err: serde_json::Error) -> Self {
-        Self::Generic(format!("{:?}", err))
+        Self::Parse {
+            url: None,
+            offset: None,
+            source: Box::new(err),
+        }
+    }
+}
+impl From<serde_yaml::Error> for Errors {
+    fn from(err: serde_yaml::Error) -> Self {
+        Self::Parse {
+            url: None,
+            offset: err.location().map(|location| location.index()),
+            source: Box::new(err),
+        }
+    }
+}
+impl From<toml::de::Error> for Errors {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Parse {
+            url: None,
+            offset: err.span().map(|span| span.start),
+            source: Box::new(err),
+        }
     }
 }
 
-pub fn get_cache_dir() -> PathBuf {
-    let mut path = dirs::home_dir().unwrap();
-    path.push(".cache");
-    path.push("tidetrawler");
-    path
+/// Set from the config, before anything's touched the cache
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use somewhere other than `~/.cache/tidetrawler`, which only works before anything's asked for it
+pub fn set_cache_dir(path: PathBuf) -> bool {
+    CACHE_DIR.set(path).is_ok()
 }
 
-pub fn make_cache_dir() -> Result<(), Errors> {
-    let path = get_cache_dir();
-    if path.exists() {
-        Ok(())
-    } else {
-        std::fs::create_dir_all(path).map_err(|err| err.into())
-    }
+pub fn get_cache_dir() -> PathBuf {
+    CACHE_DIR
+        .get_or_init(|| {
+            dirs::home_dir()
+                // no home directory is odd, but it shouldn't stop us working
+                .unwrap_or_else(std::env::temp_dir)
+                .join(".cache")
+                .join("tidetrawler")
+        })
+        .clone()
 }
 
 pub fn file_older_than(filepath: &PathBuf, min_age: u64) -> Result<bool, Errors> {
@@ -65,16 +288,98 @@ pub fn file_older_than(filepath: &PathBuf, min_age: u64) -> Result<bool, Errors>
         let modified = metadata.modified()?;
         let now = std::time::SystemTime::now();
         let age = now.duration_since(modified)?;
-        dbg!(min_age, age.as_secs());
         Ok(age.as_secs() < min_age)
     } else {
         Ok(false)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum RepoType {
     Cargo,
     PyPi,
     Npm,
+    Alpine,
+    Conda,
+    Hex,
+    Packagist,
+    PubDev,
+    Homebrew,
+    Cpan,
+    Hackage,
+    Oci,
+    Helm,
+    Terraform,
+    GitHub,
+}
+
+impl RepoType {
+    pub const ALL: [RepoType; 15] = [
+        RepoType::Cargo,
+        RepoType::PyPi,
+        RepoType::Npm,
+        RepoType::Alpine,
+        RepoType::Conda,
+        RepoType::Hex,
+        RepoType::Packagist,
+        RepoType::PubDev,
+        RepoType::Homebrew,
+        RepoType::Cpan,
+        RepoType::Hackage,
+        RepoType::Oci,
+        RepoType::Helm,
+        RepoType::Terraform,
+        RepoType::GitHub,
+    ];
+
+    /// What it's called in the config and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            RepoType::Cargo => "cargo",
+            RepoType::PyPi => "pypi",
+            RepoType::Npm => "npm",
+            RepoType::Alpine => "alpine",
+            RepoType::Conda => "conda",
+            RepoType::Hex => "hex",
+            RepoType::Packagist => "packagist",
+            RepoType::PubDev => "pubdev",
+            RepoType::Homebrew => "homebrew",
+            RepoType::Cpan => "cpan",
+            RepoType::Hackage => "hackage",
+            RepoType::Oci => "oci",
+            RepoType::Helm => "helm",
+            RepoType::Terraform => "terraform",
+            RepoType::GitHub => "github",
+        }
+    }
+
+    /// Its directory under the cache dir
+    pub fn cache_dir(&self) -> &'static str {
+        match self {
+            RepoType::Cargo => "crates",
+            other => other.name(),
+        }
+    }
+}
+
+impl FromStr for RepoType {
+    type Err = Errors;
+
+    /// Takes the [RepoType::name], or what people tend to call it
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        if let Some(repo_type) = RepoType::ALL.iter().find(|r| r.name() == s) {
+            return Ok(*repo_type);
+        }
+        match s.as_str() {
+            "crates" | "crates.io" | "rust" => Ok(RepoType::Cargo),
+            "pip" | "python" => Ok(RepoType::PyPi),
+            "apk" => Ok(RepoType::Alpine),
+            "pub" | "dart" => Ok(RepoType::PubDev),
+            "brew" => Ok(RepoType::Homebrew),
+            "docker" => Ok(RepoType::Oci),
+            "opentofu" => Ok(RepoType::Terraform),
+            _ => Err(Errors::InvalidInput(format!("Unknown ecosystem {}", s))),
+        }
+    }
 }
@@ -1,44 +1,354 @@
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 
-use clap::Parser;
-use tidetrawler::cache::Cache;
-use tidetrawler::repo::crates::Cargo;
-use tidetrawler::repo::npm::Npm;
-use tidetrawler::repo::pypi::PyPi;
-use tidetrawler::repo::Repository;
+use clap::{Args, Parser, Subcommand};
+use tidetrawler::cache::{Cache, ResourceKind};
+use tidetrawler::config::{
+    default_path, CacheConfig, ConfigLoader, LoadedConfig, OutputFormat, Source,
+};
+use tidetrawler::orchestrator::{Orchestrator, Results};
+use tidetrawler::repo::Package;
+use tidetrawler::request::WebClient;
+use tidetrawler::{set_cache_dir, Errors, RepoType};
 use tokio::sync::RwLock;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct CliOpts {
-    query: String,
+    #[command(flatten)]
+    global: GlobalOpts,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Shorthand for `search <QUERY>`
+    query: Option<String>,
+}
+
+#[derive(Args)]
+struct GlobalOpts {
+    /// Use this config file instead of ~/.config/tidetrawler/config.toml
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+    /// json, json-lines or text
+    #[arg(long, global = true)]
+    format: Option<String>,
+    /// Comma separated, eg cargo,pypi,npm
+    #[arg(long, global = true)]
+    backends: Option<String>,
+    #[arg(long, global = true)]
+    proxy: Option<String>,
+    /// Any other setting, eg --set http.concurrency=8
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    set: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Search every enabled backend that has a search
+    Search { query: String },
+    /// Every version of a package, from everywhere that has it
+    Get { name: String },
+    /// Refresh the mirrored indexes that are older than their TTL
+    Update,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Look at or clear out the local cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Filter the packages fetched so far, across every ecosystem
+    #[cfg(feature = "store")]
+    Query(QueryOpts),
+}
+
+#[cfg(feature = "store")]
+#[derive(Args)]
+struct QueryOpts {
+    #[arg(long)]
+    ecosystem: Option<String>,
+    /// Part of the name
+    #[arg(long)]
+    name: Option<String>,
+    /// Part of the license, eg GPL
+    #[arg(long)]
+    license: Option<String>,
+    /// Released in the last this many days
+    #[arg(long)]
+    updated_within: Option<u32>,
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+#[cfg(feature = "store")]
+fn run_query(opts: QueryOpts) -> Result<Vec<Package>, Errors> {
+    use tidetrawler::store::{Store, StoreQuery};
+
+    Store::open_default()?.query(&StoreQuery {
+        ecosystem: opts
+            .ecosystem
+            .as_deref()
+            .map(RepoType::from_str)
+            .transpose()?,
+        name: opts.name,
+        license: opts.license,
+        since: opts
+            .updated_within
+            .map(|days| chrono::Utc::now() - chrono::Duration::days(days as i64)),
+        limit: opts.limit,
+    })
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// The effective configuration, and where each value came from
+    Show,
+    /// Where the config file is read from
+    Path,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Entries, size and ages for each ecosystem
+    Stats,
+    /// What's cached for a URL
+    Inspect { url: String },
+    /// Remove old entries, then the oldest until it fits in cache.max_size
+    Prune {
+        /// Seconds
+        #[arg(long)]
+        max_age: Option<u64>,
+        /// Bytes, overriding cache.max_size
+        #[arg(long)]
+        max_size: Option<u64>,
+    },
+    /// Delete entries, everything unless it's narrowed down
+    Purge {
+        #[arg(long)]
+        ecosystem: Option<String>,
+        /// search, index, metadata or immutable
+        #[arg(long)]
+        kind: Option<String>,
+    },
+}
+
+fn run_cache_command(
+    cache: &mut Cache,
+    command: CacheCommand,
+    config: &CacheConfig,
+    format: OutputFormat,
+) -> Result<(), Errors> {
+    match command {
+        CacheCommand::Stats => {
+            let stats = cache.stats()?;
+            match format {
+                OutputFormat::Text => print!("{}", stats),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+                OutputFormat::JsonLines => println!("{}", serde_json::to_string(&stats)?),
+            }
+        }
+        CacheCommand::Inspect { url } => {
+            let (path, meta) = cache
+                .inspect(&url)
+                .ok_or_else(|| Errors::InvalidInput(format!("Nothing cached for {}", url)))?;
+            println!("path: {}", path.display());
+            println!("url: {}", meta.url);
+            println!(
+                "ecosystem: {}",
+                meta.repo_type
+                    .map_or("unknown", |repo_type| repo_type.name())
+            );
+            println!("kind: {}", meta.kind.name());
+            println!("updated: {}", meta.updated.to_rfc3339());
+            println!("expired: {}", cache.policy.is_expired(&meta));
+            if !meta.cache_id.is_empty() {
+                println!("cache id: {}", meta.cache_id);
+            }
+            println!("bytes: {}", meta.size);
+            println!("stored: {}", std::fs::metadata(&path)?.len());
+            if let Some(blob) = meta.blob.as_ref() {
+                println!("blob: {}", blob);
+            }
+        }
+        CacheCommand::Prune { max_age, max_size } => {
+            let max_age = max_age.map(|max_age| chrono::Duration::seconds(max_age as i64));
+            let report = cache.prune(max_age, max_size.or(config.max_size))?;
+            eprintln!(
+                "Removed {} cache entries ({} bytes), kept {} ({} bytes)",
+                report.removed, report.freed_bytes, report.kept, report.kept_bytes
+            );
+            if report.quarantined > 0 {
+                eprintln!("Quarantined {} corrupt entries", report.quarantined);
+            }
+        }
+        CacheCommand::Purge { ecosystem, kind } => {
+            let repo_type = ecosystem.as_deref().map(RepoType::from_str).transpose()?;
+            let kind = kind.as_deref().map(ResourceKind::from_str).transpose()?;
+            let removed = cache.purge(repo_type, kind)?;
+            eprintln!("Removed {} cache entries", removed);
+        }
+    }
+    Ok(())
+}
+
+fn load_config(opts: &GlobalOpts) -> Result<LoadedConfig, Errors> {
+    let mut loader = ConfigLoader::load(opts.config.as_deref())?;
+    let flags = [
+        (
+            "cache.dir",
+            "--cache-dir",
+            opts.cache_dir.as_ref().map(|d| d.display().to_string()),
+        ),
+        ("output.format", "--format", opts.format.clone()),
+        ("backends", "--backends", opts.backends.clone()),
+        ("http.proxy", "--proxy", opts.proxy.clone()),
+    ];
+    for (key, flag, value) in flags {
+        if let Some(value) = value {
+            loader = loader.with_override(key, &value, Source::Cli(flag.to_string()))?;
+        }
+    }
+    for setting in opts.set.iter() {
+        let (key, value) = setting
+            .split_once('=')
+            .ok_or_else(|| Errors::Config(format!("Expected KEY=VALUE, got {}", setting)))?;
+        loader =
+            loader.with_override(key.trim(), value.trim(), Source::Cli("--set".to_string()))?;
+    }
+    loader.build()
+}
+
+fn print_packages(packages: &[Package], format: OutputFormat) {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(packages).unwrap()),
+        OutputFormat::JsonLines => {
+            for package in packages {
+                println!("{}", serde_json::to_string(package).unwrap());
+            }
+        }
+        OutputFormat::Text => {
+            for package in packages {
+                let field = |key: &str| {
+                    package
+                        .other_metadata
+                        .get(key)
+                        .and_then(|value| value.as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                println!(
+                    "{:<10} {} {} {}",
+                    format!("{:?}", package.repo_type).to_lowercase(),
+                    package.name,
+                    field("version"),
+                    field("description")
+                );
+            }
+        }
+    }
+}
+
+fn report(results: &Results) {
+    for err in results.errors.iter() {
+        eprintln!("Error: {}", err);
+    }
 }
 
 #[tokio::main]
 async fn main() {
     let opts = CliOpts::parse();
+    let loaded = match load_config(&opts.global) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("Error loading config: {}", err);
+            std::process::exit(2);
+        }
+    };
+    let config = &loaded.config;
 
-    let cache = Arc::new(RwLock::new(Cache::default()));
+    let command = match (opts.command, opts.query) {
+        (Some(command), _) => command,
+        (None, Some(query)) => Command::Search { query },
+        (None, None) => {
+            eprintln!("Nothing to do, try --help");
+            std::process::exit(2);
+        }
+    };
+    if let Command::Config { command } = &command {
+        match command {
+            ConfigCommand::Show => print!("{}", loaded.show()),
+            ConfigCommand::Path => match opts.global.config.or_else(default_path) {
+                Some(path) => println!("{}", path.display()),
+                None => eprintln!("Couldn't work out where the config file goes"),
+            },
+        }
+        return;
+    }
 
-    let mut packages = Vec::new();
-    let mut cargo = Cargo::new(cache.clone());
-    let mut _cargo1 = Cargo::new(cache.clone());
+    if let Some(dir) = config.cache.dir.as_ref() {
+        set_cache_dir(dir.clone());
+    }
+    if let Err(err) = WebClient::configure(&config.http.client_settings()) {
+        eprintln!("Error setting up HTTP client: {}", err);
+        std::process::exit(2);
+    }
 
-    match cargo.search(&opts.query).await {
-        Ok(val) => packages.extend(val),
-        Err(err) => println!("Error getting cargo results: {:?}", err),
+    let policy = match config.cache.policy() {
+        Ok(policy) => policy,
+        Err(err) => {
+            eprintln!("Error in cache TTLs: {}", err);
+            std::process::exit(2);
+        }
     };
+    let mut cache = Cache::default().with_policy(policy);
+    if let Command::Cache { command } = command {
+        if let Err(err) =
+            run_cache_command(&mut cache, command, &config.cache, config.output.format)
+        {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    #[cfg(feature = "store")]
+    if let Command::Query(opts) = command {
+        match run_query(opts) {
+            Ok(packages) => print_packages(&packages, config.output.format),
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let cache = Arc::new(RwLock::new(cache));
+    let (mut orchestrator, errors) = Orchestrator::from_config(config, cache);
+    for err in errors {
+        eprintln!("Error reading backend config: {}", err);
+    }
+    #[cfg(feature = "store")]
+    match tidetrawler::store::Store::open_default() {
+        Ok(store) => orchestrator = orchestrator.with_store(Arc::new(store)),
+        Err(err) => eprintln!("Error opening the metadata store: {}", err),
+    }
 
-    let mut pypi = PyPi::new(cache.clone());
-    match pypi.search(&opts.query).await {
-        Ok(val) => packages.extend(val),
-        Err(err) => println!("Error getting pypi results: {:?}", err),
-    };
-    let mut npm = Npm::new(cache.clone());
-    match npm.search(&opts.query).await {
-        Ok(val) => packages.extend(val),
-        Err(err) => println!("Error getting npm results: {:?}", err),
+    let results = match command {
+        Command::Search { query } => orchestrator.search(&query).await,
+        Command::Get { name } => orchestrator.get_package(&name).await,
+        Command::Update => {
+            orchestrator
+                .update_caches(|repo_type| Some(config.cache.ttl_for(repo_type)))
+                .await
+        }
+        Command::Config { .. } | Command::Cache { .. } => unreachable!(),
+        #[cfg(feature = "store")]
+        Command::Query(_) => unreachable!(),
     };
-
-    println!("{}", serde_json::to_string_pretty(&packages).unwrap());
+    report(&results);
+    print_packages(&results.packages, config.output.format);
 }
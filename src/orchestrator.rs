@@ -0,0 +1,375 @@
+//! Running the same query across a set of repositories
+//!
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures::StreamExt;
+use tokio::sync::RwLock;
+
+use crate::cache::Cache;
+use crate::config::{Config, RegistryConfig};
+use crate::repo::cargo_config::{CargoRegistry, IndexUrl};
+use crate::repo::github::ForgeKind;
+use crate::repo::helm::HelmRepository;
+use crate::repo::npmrc::{nerf_dart, NpmAuth};
+use crate::repo::pip_config::PyPiIndex;
+use crate::repo::{
+    alpine::Alpine, conda::Conda, cpan::Cpan, crates::Cargo, github::GitHub, hackage::Hackage,
+    helm::Helm, hex::Hex, homebrew::Homebrew, npm::Npm, oci::Oci, packagist::Packagist,
+    pubdev::PubDev, pypi::PyPi, terraform::Terraform,
+};
+use crate::repo::{Capabilities, Package, Repository};
+#[cfg(feature = "store")]
+use crate::store::Store;
+use crate::{Errors, RepoType};
+
+/// What came back from asking every repository, errors don't stop the others
+#[derive(Debug, Default)]
+pub struct Results {
+    pub packages: Vec<Package>,
+    pub errors: Vec<Errors>,
+    /// The ones that can't do what was asked, so weren't
+    pub skipped: Vec<RepoType>,
+}
+
+enum Outcome {
+    Done(Result<Vec<Package>, Errors>),
+    Skipped(RepoType),
+}
+
+impl Results {
+    fn from_outcomes(outcomes: Vec<Outcome>, not_found_is_error: bool) -> Self {
+        let mut results = Self::default();
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Done(Ok(packages)) => results.packages.extend(packages),
+                Outcome::Done(Err(err)) if !not_found_is_error && err.is_not_found() => {}
+                Outcome::Done(Err(err)) => results.errors.push(err),
+                Outcome::Skipped(repo_type) => results.skipped.push(repo_type),
+            }
+        }
+        results
+    }
+}
+
+pub struct Orchestrator {
+    pub repositories: Vec<Box<dyn Repository + Send + Sync>>,
+    /// How many repositories to ask at once
+    pub concurrency: usize,
+    /// Where the packages that come back get recorded
+    #[cfg(feature = "store")]
+    pub store: Option<Arc<Store>>,
+}
+
+impl Default for Orchestrator {
+    fn default() -> Self {
+        Self {
+            repositories: Vec::new(),
+            concurrency: 1,
+            #[cfg(feature = "store")]
+            store: None,
+        }
+    }
+}
+
+impl Orchestrator {
+    pub fn with_repository(mut self, repository: impl Repository + Send + Sync + 'static) -> Self {
+        self.repositories.push(Box::new(repository));
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    #[cfg(feature = "store")]
+    pub fn with_store(mut self, store: Arc<Store>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn add(&mut self, repository: impl Repository + Send + Sync + 'static) {
+        self.repositories.push(Box::new(repository));
+    }
+
+    /// The enabled backends, set up from the config
+    ///
+    /// Anything that goes wrong reading a tool's own config (`.npmrc` and friends) is handed back
+    /// alongside, with that backend falling back to its defaults.
+    pub fn from_config(config: &Config, cache: Arc<RwLock<Cache>>) -> (Self, Vec<Errors>) {
+        let mut orchestrator = Self::default().with_concurrency(config.http.concurrency);
+        let mut errors = Vec::new();
+        let repo_types = match config.repo_types() {
+            Ok(repo_types) => repo_types,
+            Err(err) => {
+                errors.push(err);
+                Vec::new()
+            }
+        };
+        let empty = RegistryConfig::default();
+        for repo_type in repo_types {
+            let registry = config.registry(repo_type).unwrap_or(&empty);
+            if let Err(err) = orchestrator.add_configured(repo_type, registry, cache.clone()) {
+                errors.push(err);
+            }
+        }
+        (orchestrator, errors)
+    }
+
+    fn add_configured(
+        &mut self,
+        repo_type: RepoType,
+        registry: &RegistryConfig,
+        cache: Arc<RwLock<Cache>>,
+    ) -> Result<(), Errors> {
+        let url = registry.url.as_deref();
+        match repo_type {
+            RepoType::Cargo => match url {
+                Some(url) => self.add(
+                    Cargo::new(cache).with_registry(CargoRegistry {
+                        name: reqwest::Url::parse(url.trim_start_matches("sparse+"))?
+                            .host_str()
+                            .unwrap_or(url)
+                            .to_string(),
+                        index: IndexUrl::from_str(url)?,
+                        token: registry.token.clone(),
+                    }),
+                ),
+                None => {
+                    let (registries, err) = match Cargo::from_config(cache.clone()) {
+                        Ok(registries) => (registries, None),
+                        Err(err) => (vec![Cargo::new(cache)], Some(err)),
+                    };
+                    for cargo in registries {
+                        self.add(cargo);
+                    }
+                    return err.map_or(Ok(()), Err);
+                }
+            },
+            RepoType::PyPi => {
+                let (mut pypi, err) = match PyPi::from_config(cache.clone()) {
+                    Ok(pypi) => (pypi, None),
+                    Err(err) => (PyPi::new(cache), Some(err)),
+                };
+                if let Some(url) = url {
+                    let mut index = PyPiIndex::from_str(url)?;
+                    index.username = registry.username.clone().or(index.username);
+                    index.password = registry.password.clone().or(index.password);
+                    pypi.indexes = vec![index];
+                }
+                for extra in registry.extra_urls.iter() {
+                    pypi = pypi.with_extra_index_url(extra)?;
+                }
+                self.add(pypi);
+                return err.map_or(Ok(()), Err);
+            }
+            RepoType::Npm => {
+                let (mut npm, err) = match Npm::from_config(cache.clone()) {
+                    Ok(npm) => (npm, None),
+                    Err(err) => (Npm::new(cache), Some(err)),
+                };
+                if let Some(url) = url {
+                    npm.npmrc.registry = Some(url.trim_end_matches('/').to_string());
+                }
+                if let Some(token) = registry.token.as_ref() {
+                    let registry_url = npm.npmrc.registry_for("");
+                    npm.npmrc.auth.insert(
+                        nerf_dart(&registry_url),
+                        NpmAuth {
+                            token: Some(token.clone()),
+                            ..Default::default()
+                        },
+                    );
+                }
+                self.add(npm);
+                return err.map_or(Ok(()), Err);
+            }
+            RepoType::Alpine => {
+                let alpine = Alpine::new(cache);
+                self.add(match url {
+                    Some(url) => alpine.with_mirror(url),
+                    None => alpine,
+                })
+            }
+            RepoType::Conda => {
+                let conda = Conda::new(cache);
+                self.add(match url {
+                    Some(url) => conda.with_channel(url),
+                    None => conda,
+                })
+            }
+            RepoType::Cpan => {
+                let cpan = Cpan::new(cache);
+                self.add(match url {
+                    Some(url) => cpan.with_mirror(url),
+                    None => cpan,
+                })
+            }
+            RepoType::Hackage => {
+                let hackage = Hackage::new(cache);
+                self.add(match url {
+                    Some(url) => hackage.with_base_url(url),
+                    None => hackage,
+                })
+            }
+            RepoType::Hex => {
+                let hex = Hex::new(cache);
+                self.add(match url {
+                    Some(url) => hex.with_api_url(url),
+                    None => hex,
+                })
+            }
+            RepoType::Homebrew => {
+                let homebrew = Homebrew::new(cache);
+                self.add(match url {
+                    Some(url) => homebrew.with_api_url(url),
+                    None => homebrew,
+                })
+            }
+            RepoType::Packagist => {
+                let packagist = Packagist::new(cache);
+                self.add(match url {
+                    Some(url) => packagist.with_repo_url(url),
+                    None => packagist,
+                })
+            }
+            RepoType::PubDev => {
+                let pubdev = PubDev::new(cache);
+                self.add(match url {
+                    Some(url) => pubdev.with_base_url(url),
+                    None => pubdev,
+                })
+            }
+            RepoType::Terraform => {
+                let terraform = Terraform::new(cache);
+                self.add(match url {
+                    Some(url) => terraform.with_registry(url),
+                    None => terraform,
+                })
+            }
+            RepoType::GitHub => {
+                let mut github = GitHub::new(cache);
+                let kind = registry.kind.as_deref().map(ForgeKind::from_str);
+                github = match (kind.transpose()?.unwrap_or_default(), url) {
+                    (ForgeKind::Gitea, Some(url)) => github.with_gitea(url),
+                    (ForgeKind::Gitea, None) => {
+                        return Err(Errors::Config(
+                            "a gitea server needs registries.github.url".to_string(),
+                        ))
+                    }
+                    (ForgeKind::GitHub, Some(url)) => github.with_api_url(url),
+                    (ForgeKind::GitHub, None) => github,
+                };
+                if let Some(token) = registry.token.as_ref() {
+                    github = github.with_token(token);
+                }
+                self.add(github)
+            }
+            RepoType::Oci => {
+                let mut oci = Oci::new(cache);
+                if let Some(url) = url {
+                    oci = oci.with_registry(url);
+                }
+                if let (Some(username), Some(password)) =
+                    (registry.username.as_ref(), registry.password.as_ref())
+                {
+                    oci = oci.with_credentials(username, password);
+                }
+                self.add(oci)
+            }
+            RepoType::Helm => {
+                let mut helm = Helm::new(cache);
+                for repository in url
+                    .iter()
+                    .copied()
+                    .chain(registry.extra_urls.iter().map(String::as_str))
+                {
+                    let repository = HelmRepository::from_str(repository)?;
+                    helm = helm.with_repository(&repository.name, &repository.url);
+                }
+                self.add(helm)
+            }
+        }
+        Ok(())
+    }
+
+    pub fn capabilities(&self) -> Vec<Capabilities> {
+        self.repositories
+            .iter()
+            .map(|repository| repository.capabilities())
+            .collect()
+    }
+
+    /// Search everything that has a search
+    pub async fn search(&mut self, query: &str) -> Results {
+        let outcomes = futures::stream::iter(self.repositories.iter_mut())
+            .map(|repository| async move {
+                let capabilities = repository.capabilities();
+                match capabilities.search {
+                    true => Outcome::Done(repository.search(query).await),
+                    false => Outcome::Skipped(capabilities.repo_type),
+                }
+            })
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+        let mut results = Results::from_outcomes(outcomes, true);
+        self.record(&mut results);
+        results
+    }
+
+    /// Look the package up everywhere, not finding it somewhere isn't an error
+    pub async fn get_package(&mut self, name: &str) -> Results {
+        let outcomes = futures::stream::iter(self.repositories.iter_mut())
+            .map(|repository| async move {
+                let capabilities = repository.capabilities();
+                match capabilities.package_detail {
+                    true => Outcome::Done(repository.get_package(name).await),
+                    false => Outcome::Skipped(capabilities.repo_type),
+                }
+            })
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+        let mut results = Results::from_outcomes(outcomes, false);
+        self.record(&mut results);
+        results
+    }
+
+    /// Put what came back in the store, if there is one
+    #[cfg(feature = "store")]
+    fn record(&self, results: &mut Results) {
+        if let Some(store) = self.store.as_ref() {
+            if let Err(err) = store.record(&results.packages) {
+                results.errors.push(err);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "store"))]
+    fn record(&self, _results: &mut Results) {}
+
+    /// Refresh the local copy of everything that keeps one, `min_age` in seconds for each
+    pub async fn update_caches(&self, min_age: impl Fn(RepoType) -> Option<u64>) -> Results {
+        let min_age = &min_age;
+        let outcomes = futures::stream::iter(self.repositories.iter())
+            .map(|repository| async move {
+                let capabilities = repository.capabilities();
+                match capabilities.mirror {
+                    true => Outcome::Done(
+                        repository
+                            .update_cache(min_age(capabilities.repo_type))
+                            .await
+                            .map(|_| Vec::new()),
+                    ),
+                    false => Outcome::Skipped(capabilities.repo_type),
+                }
+            })
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+        Results::from_outcomes(outcomes, true)
+    }
+}
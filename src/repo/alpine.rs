@@ -0,0 +1,383 @@
+//! Repository hooks for Alpine Linux
+//!
+//! Reads the `APKINDEX.tar.gz` for a branch/repository/architecture, format reference - <https://wiki.alpinelinux.org/wiki/Apk_spec#APKINDEX_Format>
+
+use std::cmp::Ordering;
+use std::io::{BufRead, BufReader, Read};
+
+use flate2::read::MultiGzDecoder;
+
+use super::prelude::*;
+
+const ALPINE_MIRROR_URL: &str = "https://dl-cdn.alpinelinux.org/alpine";
+
+#[derive(Debug)]
+pub struct Alpine {
+    pub cache: Arc<RwLock<Cache>>,
+    pub mirror: String,
+    /// eg `latest-stable`, `edge` or `v3.19`
+    pub branch: String,
+    /// eg `main` or `community`
+    pub repository: String,
+    pub arch: String,
+}
+
+impl Alpine {
+    pub fn with_mirror(mut self, mirror: &str) -> Self {
+        self.mirror = mirror.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_branch(mut self, branch: &str) -> Self {
+        self.branch = branch.to_string();
+        self
+    }
+
+    pub fn with_repository(mut self, repository: &str) -> Self {
+        self.repository = repository.to_string();
+        self
+    }
+
+    pub fn with_arch(mut self, arch: &str) -> Self {
+        self.arch = arch.to_string();
+        self
+    }
+
+    fn index_url(&self) -> String {
+        format!(
+            "{}/{}/{}/{}/APKINDEX.tar.gz",
+            self.mirror, self.branch, self.repository, self.arch
+        )
+    }
+
+    /// Get the parsed index, from the cache if we've got it
+    async fn load_index(&self) -> Result<Vec<ApkIndexEntry>, Errors> {
+        let url = self.index_url();
+        let cached = self
+            .cache
+            .read()
+            .await
+            .get_fresh_with(RepoType::Alpine, &url, |reader| {
+                Ok(parse_apkindex(BufReader::new(reader))?)
+            });
+        if let Some(entries) = cached {
+            return Ok(entries);
+        }
+        let content = self.fetch_index(&url).await?;
+        Ok(parse_apkindex(content.as_bytes())?)
+    }
+
+    /// Download the index, unpack it and store the `APKINDEX` text in the cache
+    async fn fetch_index(&self, url: &str) -> Result<String, Errors> {
+        let body = WebClient::for_repo_type(RepoType::Alpine)
+            .get_bytes(url)
+            .await?;
+        let content = extract_apkindex(&body)?;
+        self.cache.read().await.save(
+            CacheData::new(url.to_string(), String::new(), content.clone())
+                .with_kind(RepoType::Alpine, ResourceKind::Index),
+        )?;
+        Ok(content)
+    }
+}
+
+/// A single record from an `APKINDEX` file
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct ApkIndexEntry {
+    /// `C:` - the `Q1` prefixed, base64 encoded SHA1 of the control segment
+    pub checksum: String,
+    /// `P:`
+    pub name: String,
+    /// `V:`
+    pub version: String,
+    /// `A:`
+    pub arch: Option<String>,
+    /// `T:`
+    pub description: Option<String>,
+    /// `U:`
+    pub url: Option<String>,
+    /// `L:`
+    pub license: Option<String>,
+    /// `D:`
+    pub depends: Vec<String>,
+    /// `p:`
+    pub provides: Vec<String>,
+    /// `o:`
+    pub origin: Option<String>,
+    /// `m:`
+    pub maintainer: Option<String>,
+    /// `t:`
+    pub build_time: Option<DateTime<chrono::Utc>>,
+}
+
+/// Parse the text of an `APKINDEX` file, records are separated by blank lines
+pub fn parse_apkindex(content: impl BufRead) -> std::io::Result<Vec<ApkIndexEntry>> {
+    let mut entries = Vec::new();
+    let mut entry = ApkIndexEntry::default();
+
+    for line in content.lines().chain(std::iter::once(Ok(String::new()))) {
+        let line = line?;
+        if line.trim().is_empty() {
+            if !entry.name.is_empty() {
+                entries.push(entry);
+            }
+            entry = ApkIndexEntry::default();
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.to_string();
+        match key {
+            "C" => entry.checksum = value,
+            "P" => entry.name = value,
+            "V" => entry.version = value,
+            "A" => entry.arch = Some(value),
+            "T" => entry.description = Some(value),
+            "U" => entry.url = Some(value),
+            "L" => entry.license = Some(value),
+            "D" => entry.depends = value.split_whitespace().map(String::from).collect(),
+            "p" => entry.provides = value.split_whitespace().map(String::from).collect(),
+            "o" => entry.origin = Some(value),
+            "m" => entry.maintainer = Some(value),
+            "t" => {
+                entry.build_time = value
+                    .parse::<i64>()
+                    .ok()
+                    .and_then(|ts| DateTime::from_timestamp(ts, 0))
+            }
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// Pull the `APKINDEX` file out of an `APKINDEX.tar.gz`
+///
+/// The file is a signature tarball and the index tarball as concatenated gzip streams.
+pub fn extract_apkindex(body: &[u8]) -> Result<String, Errors> {
+    let mut archive = tar::Archive::new(MultiGzDecoder::new(body));
+    archive.set_ignore_zeros(true);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.to_str() == Some("APKINDEX") {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            return Ok(content);
+        }
+    }
+    Err(Errors::parse(
+        None,
+        None,
+        "Couldn't find APKINDEX in the archive",
+    ))
+}
+
+// Order of the `_suffix` parts of a version, with no suffix sitting between `rc` and `cvs`
+const APK_SUFFIXES: [&str; 10] = [
+    "alpha", "beta", "pre", "rc", "", "cvs", "svn", "git", "hg", "p",
+];
+const APK_NO_SUFFIX: usize = 4;
+
+#[derive(Debug, PartialEq, Eq)]
+struct ApkVersion {
+    numbers: Vec<u64>,
+    letter: Option<char>,
+    suffixes: Vec<(usize, u64)>,
+    revision: u64,
+}
+
+impl From<&str> for ApkVersion {
+    fn from(value: &str) -> Self {
+        let (value, revision) = match value.rsplit_once("-r") {
+            Some((version, revision)) if revision.chars().all(|c| c.is_ascii_digit()) => {
+                (version, revision.parse().unwrap_or(0))
+            }
+            _ => (value, 0),
+        };
+
+        let mut parts = value.split('_');
+        let mut numbers = Vec::new();
+        let mut letter = None;
+        for number in parts.next().unwrap_or_default().split('.') {
+            let digits: String = number.chars().take_while(|c| c.is_ascii_digit()).collect();
+            numbers.push(digits.parse().unwrap_or(0));
+            letter = number[digits.len()..].chars().next();
+        }
+
+        let suffixes = parts
+            .map(|suffix| {
+                let name: String = suffix
+                    .chars()
+                    .take_while(|c| c.is_ascii_alphabetic())
+                    .collect();
+                let rank = APK_SUFFIXES
+                    .iter()
+                    .position(|s| !s.is_empty() && *s == name)
+                    .unwrap_or(APK_NO_SUFFIX);
+                (rank, suffix[name.len()..].parse().unwrap_or(0))
+            })
+            .collect();
+
+        Self {
+            numbers,
+            letter,
+            suffixes,
+            revision,
+        }
+    }
+}
+
+impl Ord for ApkVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.numbers
+            .cmp(&other.numbers)
+            .then_with(|| self.letter.cmp(&other.letter))
+            .then_with(|| {
+                let len = self.suffixes.len().max(other.suffixes.len());
+                (0..len)
+                    .map(|i| {
+                        let ours = self.suffixes.get(i).unwrap_or(&(APK_NO_SUFFIX, 0));
+                        let theirs = other.suffixes.get(i).unwrap_or(&(APK_NO_SUFFIX, 0));
+                        ours.cmp(theirs)
+                    })
+                    .find(|ord| ord.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            })
+            .then_with(|| self.revision.cmp(&other.revision))
+    }
+}
+
+impl PartialOrd for ApkVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compare two Alpine package versions, eg `1.2.3_rc1-r0` < `1.2.3-r0` < `1.2.3_p1-r0`
+pub fn compare_versions(left: &str, right: &str) -> Ordering {
+    ApkVersion::from(left).cmp(&ApkVersion::from(right))
+}
+
+impl From<ApkIndexEntry> for Package {
+    fn from(value: ApkIndexEntry) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        other_metadata.insert("version".to_string(), Value::String(value.version));
+        other_metadata.insert("checksum".to_string(), Value::String(value.checksum));
+        if let Some(arch) = value.arch {
+            other_metadata.insert("arch".to_string(), Value::String(arch));
+        }
+        if let Some(description) = value.description {
+            other_metadata.insert("description".to_string(), Value::String(description));
+        }
+        if let Some(license) = value.license {
+            other_metadata.insert("license".to_string(), Value::String(license));
+        }
+        if !value.depends.is_empty() {
+            other_metadata.insert("dependencies".to_string(), Value::from(value.depends));
+        }
+        if !value.provides.is_empty() {
+            other_metadata.insert("provides".to_string(), Value::from(value.provides));
+        }
+        if let Some(origin) = value.origin {
+            other_metadata.insert("origin".to_string(), Value::String(origin));
+        }
+        if let Some(build_time) = value.build_time {
+            other_metadata.insert(
+                "release_date".to_string(),
+                Value::String(build_time.to_rfc3339()),
+            );
+        }
+
+        Package {
+            name: value.name,
+            url: value.url,
+            owner: value.maintainer,
+            other_metadata,
+            repo_type: RepoType::Alpine,
+        }
+    }
+}
+
+#[async_trait]
+impl Repository for Alpine {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            mirror: ALPINE_MIRROR_URL.to_string(),
+            branch: "latest-stable".to_string(),
+            repository: "main".to_string(),
+            arch: "x86_64".to_string(),
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::Alpine
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            mirror: true,
+            dependencies: true,
+            ..Capabilities::none(RepoType::Alpine)
+        }
+    }
+
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let query = query.to_lowercase();
+        Ok(self
+            .load_index()
+            .await?
+            .into_iter()
+            .filter(|entry| {
+                entry.name.to_lowercase().contains(&query)
+                    || entry
+                        .description
+                        .as_ref()
+                        .is_some_and(|d| d.to_lowercase().contains(&query))
+            })
+            .map(|entry| entry.into())
+            .collect())
+    }
+
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let mut entries: Vec<ApkIndexEntry> = self
+            .load_index()
+            .await?
+            .into_iter()
+            .filter(|entry| entry.name == name)
+            .collect();
+        if entries.is_empty() {
+            return Err(Errors::NotFound {
+                repo_type: RepoType::Alpine,
+                name: name.to_string(),
+            });
+        }
+        entries.sort_by(|a, b| compare_versions(&a.version, &b.version));
+        Ok(entries.into_iter().map(|entry| entry.into()).collect())
+    }
+
+    async fn cacheable(&self) -> bool {
+        true
+    }
+
+    async fn update_cache(&self, min_age: Option<u64>) -> Result<(), Errors> {
+        let _lock = self.cache.read().await.lock_ecosystem(RepoType::Alpine)?;
+        let url = self.index_url();
+        if let Some(min_age) = min_age {
+            let max_age = chrono::Duration::seconds(min_age as i64);
+            if self
+                .cache
+                .read()
+                .await
+                .is_fresh(RepoType::Alpine, &url, max_age)
+            {
+                return Ok(());
+            }
+        }
+        self.fetch_index(&url).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+//! Finding Cargo registries the way Cargo does, from `.cargo/config.toml` and `CARGO_HOME`
+//!
+//! Config reference - <https://doc.rust-lang.org/cargo/reference/config.html>
+//!
+//! Registry index reference - <https://doc.rust-lang.org/cargo/reference/registry-index.html>
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::prelude::*;
+
+pub const CRATES_IO: &str = "crates-io";
+const CRATES_IO_GIT_INDEX: &str = "https://github.com/rust-lang/crates.io-index";
+const CRATES_IO_SPARSE_INDEX: &str = "https://index.crates.io";
+
+/// Where a registry's index lives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexUrl {
+    /// `sparse+https://...`, fetched file by file over HTTP
+    Sparse(String),
+    /// A git repository, which we read through the forge's raw file URLs
+    Git(String),
+}
+
+impl FromStr for IndexUrl {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(url) = s.strip_prefix("sparse+") {
+            return Ok(Self::Sparse(url.trim_end_matches('/').to_string()));
+        }
+        let url = s
+            .strip_prefix("registry+")
+            .unwrap_or(s)
+            .trim_end_matches('/');
+        if url.is_empty() {
+            return Err(Errors::Config("Empty registry index URL".to_string()));
+        }
+        // nobody needs to clone the crates.io index any more
+        if url.trim_end_matches(".git") == CRATES_IO_GIT_INDEX {
+            return Ok(Self::Sparse(CRATES_IO_SPARSE_INDEX.to_string()));
+        }
+        Ok(Self::Git(url.to_string()))
+    }
+}
+
+impl IndexUrl {
+    /// The index's own URL, without the `sparse+`
+    pub fn url(&self) -> &str {
+        match self {
+            Self::Sparse(url) | Self::Git(url) => url,
+        }
+    }
+
+    /// The base URL that index files (and `config.json`) hang off
+    pub fn file_base(&self) -> Result<String, Errors> {
+        match self {
+            Self::Sparse(url) => Ok(url.clone()),
+            Self::Git(url) => {
+                let url = url.trim_end_matches(".git");
+                if let Some(repo) = url.strip_prefix("https://github.com/") {
+                    Ok(format!("https://raw.githubusercontent.com/{}/HEAD", repo))
+                } else if url.contains("gitlab") {
+                    Ok(format!("{}/-/raw/HEAD", url))
+                } else {
+                    Err(Errors::Unsupported {
+                        repo_type: RepoType::Cargo,
+                        operation: format!(
+                            "reading the git index at {} without cloning it, use a sparse+ URL",
+                            url
+                        ),
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// A registry we can query, with everything Cargo would use to talk to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CargoRegistry {
+    pub name: String,
+    pub index: IndexUrl,
+    pub token: Option<String>,
+}
+
+impl Default for CargoRegistry {
+    fn default() -> Self {
+        Self {
+            name: CRATES_IO.to_string(),
+            index: IndexUrl::Sparse(CRATES_IO_SPARSE_INDEX.to_string()),
+            token: None,
+        }
+    }
+}
+
+/// The environment variable Cargo reads a registry's token from
+pub fn token_env_var(registry: &str) -> String {
+    match registry {
+        CRATES_IO => "CARGO_REGISTRY_TOKEN".to_string(),
+        name => format!(
+            "CARGO_REGISTRIES_{}_TOKEN",
+            name.to_uppercase().replace('-', "_")
+        ),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct RegistryDefaults {
+    pub default: Option<String>,
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct RegistryEntry {
+    pub index: Option<String>,
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct SourceEntry {
+    pub registry: Option<String>,
+    pub replace_with: Option<String>,
+}
+
+/// The registry-related parts of Cargo's config, `credentials.toml` has the same shape
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct CargoConfig {
+    #[serde(default)]
+    pub registry: RegistryDefaults,
+    #[serde(default)]
+    pub registries: HashMap<String, RegistryEntry>,
+    #[serde(default)]
+    pub source: HashMap<String, SourceEntry>,
+}
+
+impl FromStr for CargoConfig {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+impl CargoConfig {
+    /// Fill in anything we don't have from a lower priority config
+    pub fn merge(&mut self, other: CargoConfig) {
+        self.registry.default = self.registry.default.take().or(other.registry.default);
+        self.registry.token = self.registry.token.take().or(other.registry.token);
+        for (name, entry) in other.registries {
+            let ours = self.registries.entry(name).or_default();
+            ours.index = ours.index.take().or(entry.index);
+            ours.token = ours.token.take().or(entry.token);
+        }
+        for (name, entry) in other.source {
+            let ours = self.source.entry(name).or_default();
+            ours.registry = ours.registry.take().or(entry.registry);
+            ours.replace_with = ours.replace_with.take().or(entry.replace_with);
+        }
+    }
+
+    fn read_file(path: &Path) -> Result<Option<Self>, Errors> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(Self::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    /// Reads the config from `dir` and all of its parents, then `cargo_home`, closest first
+    pub fn load_from(dir: &Path, cargo_home: &Path) -> Result<Self, Errors> {
+        let mut config = Self::default();
+        let mut config_dirs: Vec<PathBuf> = dir.ancestors().map(|d| d.join(".cargo")).collect();
+        if !config_dirs.iter().any(|d| d == cargo_home) {
+            config_dirs.push(cargo_home.to_path_buf());
+        }
+
+        for config_dir in config_dirs {
+            // Cargo prefers the .toml one if both exist
+            for filename in ["config.toml", "config"] {
+                if let Some(found) = Self::read_file(&config_dir.join(filename))? {
+                    config.merge(found);
+                    break;
+                }
+            }
+        }
+        for filename in ["credentials.toml", "credentials"] {
+            if let Some(credentials) = Self::read_file(&cargo_home.join(filename))? {
+                config.merge(credentials);
+                break;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads the config the way Cargo would from the current directory
+    pub fn load() -> Result<Self, Errors> {
+        let cargo_home = match std::env::var_os("CARGO_HOME") {
+            Some(cargo_home) => PathBuf::from(cargo_home),
+            None => dirs::home_dir()
+                .ok_or_else(|| Errors::Config("Couldn't find a home directory".to_string()))?
+                .join(".cargo"),
+        };
+        Self::load_from(&std::env::current_dir()?, &cargo_home)
+    }
+
+    /// Follow `replace-with` from a source to wherever it ends up
+    fn resolve_source(&self, name: &str) -> Result<(String, Option<IndexUrl>), Errors> {
+        let mut current = name.to_string();
+        for _ in 0..16 {
+            match self.source.get(&current) {
+                Some(SourceEntry {
+                    replace_with: Some(replacement),
+                    ..
+                }) => current = replacement.clone(),
+                Some(SourceEntry {
+                    registry: Some(index),
+                    ..
+                }) => return Ok((current, Some(IndexUrl::from_str(index)?))),
+                _ => {
+                    let index = self
+                        .registries
+                        .get(&current)
+                        .and_then(|entry| entry.index.as_ref())
+                        .map(|index| IndexUrl::from_str(index))
+                        .transpose()?;
+                    return Ok((current, index));
+                }
+            }
+        }
+        Err(Errors::Config(format!(
+            "Source replacement for {} loops",
+            name
+        )))
+    }
+
+    fn token(&self, name: &str, env: &impl Fn(&str) -> Option<String>) -> Option<String> {
+        env(&token_env_var(name)).or_else(|| match name {
+            CRATES_IO => self.registry.token.clone(),
+            name => self
+                .registries
+                .get(name)
+                .and_then(|entry| entry.token.clone()),
+        })
+    }
+
+    /// Every registry we know about, with crates.io (or its replacement) first
+    pub fn registries_with_env(
+        &self,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Vec<CargoRegistry>, Errors> {
+        let mut registries = Vec::new();
+
+        let (name, index) = self.resolve_source(CRATES_IO)?;
+        registries.push(CargoRegistry {
+            token: self.token(&name, &env),
+            index: index.unwrap_or(CargoRegistry::default().index),
+            name,
+        });
+
+        let mut names: Vec<&String> = self.registries.keys().collect();
+        names.sort();
+        for name in names {
+            if registries.iter().any(|registry| &registry.name == name) {
+                continue;
+            }
+            let (resolved, index) = self.resolve_source(name)?;
+            let Some(index) = index else {
+                // a token in credentials.toml for a registry we don't have an index for
+                continue;
+            };
+            registries.push(CargoRegistry {
+                token: self.token(&resolved, &env),
+                name: name.clone(),
+                index,
+            });
+        }
+        Ok(registries)
+    }
+
+    pub fn registries(&self) -> Result<Vec<CargoRegistry>, Errors> {
+        self.registries_with_env(|key| std::env::var(key).ok())
+    }
+}
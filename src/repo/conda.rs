@@ -0,0 +1,554 @@
+//! Repository hooks for conda channels
+//!
+//! Reads the per-subdir `repodata.json` of a channel, format reference - <https://docs.conda.io/projects/conda-build/en/stable/concepts/generating-index.html#repodata-json>
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use super::prelude::*;
+use crate::request::parse_json;
+
+const CONDA_CHANNEL_URL: &str = "https://conda.anaconda.org";
+
+/// A channel name like `conda-forge` on anaconda.org, or a full URL as it is
+fn channel_url(channel: &str) -> String {
+    match channel.contains("://") {
+        true => channel.trim_end_matches('/').to_string(),
+        false => format!("{}/{}", CONDA_CHANNEL_URL, channel),
+    }
+}
+
+#[derive(Debug)]
+pub struct Conda {
+    pub cache: Arc<RwLock<Cache>>,
+    /// The full URL of the channel, eg `https://conda.anaconda.org/conda-forge`
+    pub channel: String,
+    /// eg `noarch`, `linux-64`
+    pub subdirs: Vec<String>,
+}
+
+impl Conda {
+    /// Takes either a channel name like `conda-forge` or a full URL
+    pub fn with_channel(mut self, channel: &str) -> Self {
+        self.channel = channel_url(channel);
+        self
+    }
+
+    pub fn with_subdirs(mut self, subdirs: &[&str]) -> Self {
+        self.subdirs = subdirs.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    fn repodata_url(&self, subdir: &str) -> String {
+        format!("{}/{}/repodata.json", self.channel, subdir)
+    }
+
+    /// Download the repodata for a subdir, preferring the zstd compressed version
+    async fn fetch_repodata(&self, url: &str) -> Result<String, Errors> {
+        let client = WebClient::for_repo_type(RepoType::Conda);
+        let content = match client.get_bytes(&format!("{}.zst", url)).await {
+            Ok(body) => String::from_utf8(zstd::decode_all(body.as_slice())?).map_err(|err| {
+                Errors::Parse {
+                    url: Some(format!("{}.zst", url)),
+                    offset: Some(err.utf8_error().valid_up_to()),
+                    source: Box::new(err),
+                }
+            })?,
+            Err(_) => client.get_text(url).await?,
+        };
+        self.cache.read().await.save(
+            CacheData::new(url.to_string(), String::new(), content.clone())
+                .with_kind(RepoType::Conda, ResourceKind::Index),
+        )?;
+        Ok(content)
+    }
+
+    /// All the records from all the configured subdirs, from the cache if we've got them
+    async fn load_records(&self) -> Result<Vec<CondaRecord>, Errors> {
+        let mut records = Vec::new();
+        for subdir in self.subdirs.iter() {
+            let url = self.repodata_url(subdir);
+            let cached = self
+                .cache
+                .read()
+                .await
+                .get_fresh_json(RepoType::Conda, &url);
+            let repodata: RepoData = match cached {
+                Some(repodata) => repodata,
+                None => parse_json(&url, &self.fetch_repodata(&url).await?)?,
+            };
+            records.extend(repodata.into_records(&self.channel, subdir));
+        }
+        Ok(records)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RepoData {
+    pub info: Option<Value>,
+    #[serde(default)]
+    pub packages: HashMap<String, CondaRecord>,
+    #[serde(default, rename = "packages.conda")]
+    pub packages_conda: HashMap<String, CondaRecord>,
+    pub repodata_version: Option<u64>,
+}
+
+impl RepoData {
+    /// Flatten both the `.tar.bz2` and `.conda` entries, filling in the filename and download URL
+    pub fn into_records(self, channel: &str, subdir: &str) -> Vec<CondaRecord> {
+        self.packages
+            .into_iter()
+            .chain(self.packages_conda)
+            .map(|(filename, mut record)| {
+                record.url = Some(format!("{}/{}/{}", channel, subdir, filename));
+                record.filename = filename;
+                record
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CondaRecord {
+    #[serde(skip)]
+    pub filename: String,
+    #[serde(skip)]
+    pub url: Option<String>,
+    pub name: String,
+    pub version: String,
+    pub build: String,
+    #[serde(default)]
+    pub build_number: u64,
+    #[serde(default)]
+    pub depends: Vec<String>,
+    #[serde(default)]
+    pub constrains: Vec<String>,
+    pub license: Option<String>,
+    pub license_family: Option<String>,
+    /// Milliseconds since the epoch, older records use seconds
+    pub timestamp: Option<i64>,
+    pub md5: Option<String>,
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+    pub subdir: Option<String>,
+    pub noarch: Option<Value>,
+}
+
+impl CondaRecord {
+    pub fn release_date(&self) -> Option<DateTime<chrono::Utc>> {
+        let timestamp = self.timestamp?;
+        match timestamp > 100_000_000_000 {
+            true => DateTime::from_timestamp(timestamp / 1000, 0),
+            false => DateTime::from_timestamp(timestamp, 0),
+        }
+    }
+
+    /// Orders by version, then build number
+    pub fn cmp_version(&self, other: &Self) -> Ordering {
+        compare_versions(&self.version, &other.version)
+            .then_with(|| self.build_number.cmp(&other.build_number))
+    }
+}
+
+impl From<CondaRecord> for Package {
+    fn from(value: CondaRecord) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        if let Some(release_date) = value.release_date() {
+            other_metadata.insert(
+                "release_date".to_string(),
+                Value::String(release_date.to_rfc3339()),
+            );
+        }
+        other_metadata.insert("version".to_string(), Value::String(value.version));
+        other_metadata.insert("build".to_string(), Value::String(value.build));
+        other_metadata.insert("build_number".to_string(), Value::from(value.build_number));
+        other_metadata.insert("filename".to_string(), Value::String(value.filename));
+        if !value.depends.is_empty() {
+            other_metadata.insert("dependencies".to_string(), Value::from(value.depends));
+        }
+        if !value.constrains.is_empty() {
+            other_metadata.insert("constrains".to_string(), Value::from(value.constrains));
+        }
+        if let Some(license) = value.license {
+            other_metadata.insert("license".to_string(), Value::String(license));
+        }
+        if let Some(sha256) = value.sha256 {
+            other_metadata.insert("checksum".to_string(), Value::String(sha256));
+        }
+        if let Some(subdir) = value.subdir {
+            other_metadata.insert("subdir".to_string(), Value::String(subdir));
+        }
+        if let Some(url) = value.url {
+            other_metadata.insert("download_url".to_string(), Value::String(url));
+        }
+
+        Package {
+            name: value.name,
+            url: None,
+            owner: None,
+            other_metadata,
+            repo_type: RepoType::Conda,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum VersionPart {
+    // declaration order matters - strings sort before numbers, `post` after everything
+    Str(String),
+    Num(u64),
+    Post,
+}
+
+/// A parsed conda version, following conda's `VersionOrder`
+#[derive(Debug, PartialEq, Eq)]
+struct CondaVersion {
+    epoch: u64,
+    version: Vec<Vec<VersionPart>>,
+    local: Vec<Vec<VersionPart>>,
+}
+
+fn parse_components(value: &str) -> Vec<Vec<VersionPart>> {
+    value
+        .split(['.', '_', '-'])
+        .map(|component| {
+            let mut parts = Vec::new();
+            let mut chars = component.chars().peekable();
+            while let Some(c) = chars.peek().copied() {
+                let is_digit = c.is_ascii_digit();
+                let mut run = String::new();
+                while let Some(c) = chars.peek().copied() {
+                    if c.is_ascii_digit() != is_digit {
+                        break;
+                    }
+                    run.push(c);
+                    chars.next();
+                }
+                parts.push(match (is_digit, run.as_str()) {
+                    (true, _) => VersionPart::Num(run.parse().unwrap_or(u64::MAX)),
+                    (false, "post") => VersionPart::Post,
+                    // sorts before any other string, as everything else is lowercase
+                    (false, "dev") => VersionPart::Str("DEV".to_string()),
+                    (false, _) => VersionPart::Str(run),
+                });
+            }
+            if !matches!(parts.first(), Some(VersionPart::Num(_))) {
+                parts.insert(0, VersionPart::Num(0));
+            }
+            parts
+        })
+        .collect()
+}
+
+fn cmp_components(left: &[Vec<VersionPart>], right: &[Vec<VersionPart>]) -> Ordering {
+    let empty = Vec::new();
+    let zero = VersionPart::Num(0);
+    for i in 0..left.len().max(right.len()) {
+        let ours = left.get(i).unwrap_or(&empty);
+        let theirs = right.get(i).unwrap_or(&empty);
+        for j in 0..ours.len().max(theirs.len()) {
+            let ord = ours
+                .get(j)
+                .unwrap_or(&zero)
+                .cmp(theirs.get(j).unwrap_or(&zero));
+            if ord.is_ne() {
+                return ord;
+            }
+        }
+    }
+    Ordering::Equal
+}
+
+impl From<&str> for CondaVersion {
+    fn from(value: &str) -> Self {
+        let value = value.trim().to_lowercase();
+        let (epoch, value) = match value.split_once('!') {
+            Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+            None => (0, value.as_str()),
+        };
+        let (version, local) = match value.split_once('+') {
+            Some((version, local)) => (version, parse_components(local)),
+            None => (value, Vec::new()),
+        };
+        Self {
+            epoch,
+            version: parse_components(version),
+            local,
+        }
+    }
+}
+
+impl Ord for CondaVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.epoch
+            .cmp(&other.epoch)
+            .then_with(|| cmp_components(&self.version, &other.version))
+            .then_with(|| cmp_components(&self.local, &other.local))
+    }
+}
+
+impl PartialOrd for CondaVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compare two conda versions, eg `1.1dev1` < `1.1a1` < `1.1rc1` < `1.1` < `1.1post1`
+///
+/// A dot makes a difference, `1.1.dev1` is its own component so it's after `1.1a1`.
+pub fn compare_versions(left: &str, right: &str) -> Ordering {
+    CondaVersion::from(left).cmp(&CondaVersion::from(right))
+}
+
+/// Simple glob matching, only supports `*`
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Check a single constraint like `>=1.2` or `1.2.*` against a version
+fn constraint_matches(constraint: &str, version: &str) -> bool {
+    let constraint = constraint.trim();
+    if constraint.is_empty() || constraint == "*" {
+        return true;
+    }
+    for (op, check) in [
+        (">=", Ordering::is_ge as fn(Ordering) -> bool),
+        ("<=", Ordering::is_le),
+        ("==", Ordering::is_eq),
+        ("!=", Ordering::is_ne),
+        (">", Ordering::is_gt),
+        ("<", Ordering::is_lt),
+    ] {
+        if let Some(target) = constraint.strip_prefix(op) {
+            if target.ends_with('*') && (op == "==" || op == "!=") {
+                return glob_matches(target, version) == (op == "==");
+            }
+            return check(compare_versions(version, target.trim_end_matches(".*")));
+        }
+    }
+    if let Some(target) = constraint.strip_prefix("~=") {
+        let prefix = target.rsplit_once('.').map(|(p, _)| p).unwrap_or(target);
+        return compare_versions(version, target).is_ge()
+            && constraint_matches(&format!("{}.*", prefix), version);
+    }
+    if let Some(target) = constraint.strip_prefix('=') {
+        return constraint_matches(&format!("{}*", target), version);
+    }
+    if let Some(prefix) = constraint.strip_suffix('*') {
+        let prefix = prefix.trim_end_matches('.');
+        return version == prefix
+            || version.starts_with(&format!("{}.", prefix))
+            || (!constraint.ends_with(".*") && version.starts_with(prefix));
+    }
+    compare_versions(version, constraint).is_eq()
+}
+
+/// Check a version spec like `>=1.21,<2.0a0|1.19.*` against a version
+pub fn version_matches(spec: &str, version: &str) -> bool {
+    spec.split('|').any(|alternative| {
+        alternative
+            .split(',')
+            .all(|constraint| constraint_matches(constraint, version))
+    })
+}
+
+/// A conda match spec, eg `conda-forge::numpy >=1.21,<2 py310*`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MatchSpec {
+    pub channel: Option<String>,
+    pub name: String,
+    pub version: Option<String>,
+    pub build: Option<String>,
+}
+
+impl FromStr for MatchSpec {
+    type Err = Errors;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut spec = MatchSpec::default();
+        let mut value = value.trim().to_string();
+
+        // name[version='>=1.2', build=py*]
+        if let Some((head, brackets)) = value.clone().split_once('[') {
+            for pair in brackets.trim_end_matches(']').split(',') {
+                if let Some((key, val)) = pair.split_once('=') {
+                    let val = val.trim().trim_matches(['\'', '"']).to_string();
+                    match key.trim() {
+                        "version" => spec.version = Some(val),
+                        "build" => spec.build = Some(val),
+                        "channel" => spec.channel = Some(val),
+                        _ => {}
+                    }
+                }
+            }
+            value = head.trim().to_string();
+        }
+
+        if let Some((channel, rest)) = value.clone().rsplit_once("::") {
+            spec.channel = Some(channel.to_string());
+            value = rest.to_string();
+        }
+
+        let name_end = value
+            .find(|c: char| c.is_whitespace() || "<>=!~".contains(c))
+            .unwrap_or(value.len());
+        spec.name = value[..name_end].to_string();
+        let rest = value[name_end..].trim();
+
+        if spec.name.is_empty() {
+            return Err(Errors::InvalidInput(format!(
+                "Invalid match spec: {}",
+                value
+            )));
+        }
+
+        let mut parts = rest.split_whitespace();
+        if let Some(version) = parts.next() {
+            // `numpy=1.2=py38` is the version and build in one
+            match version.strip_prefix('=').filter(|v| !v.starts_with('=')) {
+                Some(fuzzy) => match fuzzy.split_once('=') {
+                    Some((version, build)) => {
+                        spec.version = Some(format!("{}*", version));
+                        spec.build = Some(build.to_string());
+                    }
+                    None => spec.version = Some(format!("{}*", fuzzy)),
+                },
+                None => spec.version = Some(version.to_string()),
+            }
+        }
+        if let Some(build) = parts.next() {
+            spec.build = Some(build.to_string());
+        }
+        Ok(spec)
+    }
+}
+
+impl MatchSpec {
+    pub fn matches(&self, record: &CondaRecord) -> bool {
+        record.name == self.name
+            && self
+                .version
+                .as_ref()
+                .is_none_or(|spec| version_matches(spec, &record.version))
+            && self
+                .build
+                .as_ref()
+                .is_none_or(|build| glob_matches(build, &record.build))
+    }
+}
+
+#[async_trait]
+impl Repository for Conda {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            channel: format!("{}/conda-forge", CONDA_CHANNEL_URL),
+            subdirs: vec!["noarch".to_string(), "linux-64".to_string()],
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::Conda
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            mirror: true,
+            dependencies: true,
+            downloads: true,
+            ..Capabilities::none(RepoType::Conda)
+        }
+    }
+
+    /// Returns the newest record for each package whose name contains the query
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let query = query.to_lowercase();
+        let mut latest: HashMap<String, CondaRecord> = HashMap::new();
+        for record in self.load_records().await? {
+            if !record.name.contains(&query) {
+                continue;
+            }
+            match latest.get(&record.name) {
+                Some(existing) if existing.cmp_version(&record).is_ge() => {}
+                _ => {
+                    latest.insert(record.name.clone(), record);
+                }
+            }
+        }
+        let mut records: Vec<CondaRecord> = latest.into_values().collect();
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(records.into_iter().map(|record| record.into()).collect())
+    }
+
+    /// Takes a name or a match spec, eg `numpy >=1.21,<2`, looking in the spec's channel
+    /// if it names one
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let spec = MatchSpec::from_str(name)?;
+        let records = match spec.channel.as_deref().map(channel_url) {
+            Some(channel) if channel != self.channel => {
+                Conda {
+                    cache: self.cache.clone(),
+                    channel,
+                    subdirs: self.subdirs.clone(),
+                }
+                .load_records()
+                .await?
+            }
+            _ => self.load_records().await?,
+        };
+        let mut records: Vec<CondaRecord> = records
+            .into_iter()
+            .filter(|record| spec.matches(record))
+            .collect();
+        if records.is_empty() {
+            return Err(Errors::NotFound {
+                repo_type: RepoType::Conda,
+                name: name.to_string(),
+            });
+        }
+        records.sort_by(|a, b| a.cmp_version(b));
+        Ok(records.into_iter().map(|record| record.into()).collect())
+    }
+
+    async fn cacheable(&self) -> bool {
+        true
+    }
+
+    async fn update_cache(&self, min_age: Option<u64>) -> Result<(), Errors> {
+        let _lock = self.cache.read().await.lock_ecosystem(RepoType::Conda)?;
+        for subdir in self.subdirs.iter() {
+            let url = self.repodata_url(subdir);
+            if let Some(min_age) = min_age {
+                let max_age = chrono::Duration::seconds(min_age as i64);
+                if self
+                    .cache
+                    .read()
+                    .await
+                    .is_fresh(RepoType::Conda, &url, max_age)
+                {
+                    continue;
+                }
+            }
+            self.fetch_repodata(&url).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+//! Repository hooks for CPAN, the Perl module archive
+//!
+//! Uses the `02packages.details.txt.gz` module index, and the `.meta` files PAUSE extracts
+//! from each distribution - <https://metacpan.org/pod/CPAN::Meta::Spec>
+
+use std::io::{BufRead, BufReader, Read};
+
+use flate2::read::GzDecoder;
+
+use super::prelude::*;
+use crate::request::parse_json;
+
+const CPAN_MIRROR_URL: &str = "https://www.cpan.org";
+
+#[derive(Debug)]
+pub struct Cpan {
+    pub cache: Arc<RwLock<Cache>>,
+    pub mirror: String,
+}
+
+impl Cpan {
+    pub fn with_mirror(mut self, mirror: &str) -> Self {
+        self.mirror = mirror.trim_end_matches('/').to_string();
+        self
+    }
+
+    fn index_url(&self) -> String {
+        format!("{}/modules/02packages.details.txt.gz", self.mirror)
+    }
+
+    /// Download the index, which is decompressed into the cache rather than into memory
+    async fn fetch_index(&self, url: &str) -> Result<(), Errors> {
+        let body = WebClient::for_repo_type(RepoType::Cpan)
+            .get_bytes(url)
+            .await?;
+        self.cache.read().await.save_reader(
+            url,
+            RepoType::Cpan,
+            ResourceKind::Index,
+            decode_02packages(&body),
+        )
+    }
+
+    /// Get the module index, from the cache if we've got it
+    async fn load_index(&self) -> Result<Vec<CpanModule>, Errors> {
+        let url = self.index_url();
+        let cached = self
+            .cache
+            .read()
+            .await
+            .get_fresh_with(RepoType::Cpan, &url, |reader| {
+                Ok(parse_02packages(BufReader::new(reader))?)
+            });
+        if let Some(modules) = cached {
+            return Ok(modules);
+        }
+        self.fetch_index(&url).await?;
+        // read back from where it was just saved, whether or not it counts as fresh
+        let cache = self.cache.read().await;
+        let (_, reader) = cache
+            .open(RepoType::Cpan, &url)
+            .ok_or_else(|| Errors::CacheCorrupt {
+                path: cache.ecosystem_dir(Some(RepoType::Cpan)),
+                source: "the index couldn't be read back after saving it".into(),
+            })?;
+        Ok(parse_02packages(BufReader::new(reader))?)
+    }
+
+    /// Fetch the META file PAUSE extracted from a distribution, if there's a JSON one
+    async fn get_meta(&self, module: &CpanModule) -> Option<CpanMeta> {
+        let url = format!("{}/authors/id/{}", self.mirror, module.meta_path()?);
+        if let Some(meta) = self.cache.read().await.get_fresh_json(RepoType::Cpan, &url) {
+            return Some(meta);
+        }
+        let body = WebClient::for_repo_type(RepoType::Cpan)
+            .get_text(&url)
+            .await
+            .ok()?;
+        let meta = parse_json(&url, &body).ok()?;
+        // a release never changes once it's on PAUSE, so this can be kept forever
+        self.cache
+            .read()
+            .await
+            .save(
+                CacheData::new(url, String::new(), body)
+                    .with_kind(RepoType::Cpan, ResourceKind::Immutable),
+            )
+            .ok();
+        Some(meta)
+    }
+}
+
+/// Read the raw body of `02packages.details.txt.gz`, which might have already been decompressed
+pub fn decode_02packages(body: &[u8]) -> Box<dyn Read + '_> {
+    match body.starts_with(&[0x1f, 0x8b]) {
+        true => Box::new(GzDecoder::new(body)),
+        false => Box::new(body),
+    }
+}
+
+/// A line from `02packages.details.txt`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CpanModule {
+    pub module: String,
+    /// `None` when the index says `undef`
+    pub version: Option<String>,
+    /// Relative to `authors/id/`, eg `O/OA/OALDERS/libwww-perl-6.76.tar.gz`
+    pub path: String,
+}
+
+impl CpanModule {
+    pub fn author(&self) -> Option<&str> {
+        self.path.split('/').nth(2)
+    }
+
+    fn filename_stem(&self) -> Option<&str> {
+        let filename = self.path.rsplit('/').next()?;
+        [".tar.gz", ".tgz", ".tar.bz2", ".zip"]
+            .iter()
+            .find_map(|ext| filename.strip_suffix(ext))
+    }
+
+    /// The distribution name and version, eg `libwww-perl` and `6.76`
+    pub fn distribution(&self) -> Option<(String, String)> {
+        let stem = self.filename_stem()?;
+        let (name, version) = stem.rsplit_once('-')?;
+        Some((name.to_string(), version.to_string()))
+    }
+
+    fn meta_path(&self) -> Option<String> {
+        let stem = self.filename_stem()?;
+        let dir = self.path.rsplit_once('/')?.0;
+        Some(format!("{}/{}.meta", dir, stem))
+    }
+}
+
+/// Parse `02packages.details.txt`, skipping the header which ends at the first blank line
+pub fn parse_02packages(content: impl BufRead) -> std::io::Result<Vec<CpanModule>> {
+    content
+        .lines()
+        .skip_while(|line| line.as_ref().is_ok_and(|line| !line.trim().is_empty()))
+        .filter_map(|line| line.map(|line| parse_02packages_line(&line)).transpose())
+        .collect()
+}
+
+/// `Module::Name  1.23  A/AU/AUTHOR/Dist-1.23.tar.gz`
+fn parse_02packages_line(line: &str) -> Option<CpanModule> {
+    let mut parts = line.split_whitespace();
+    let module = parts.next()?.to_string();
+    let version = parts.next()?;
+    let path = parts.next()?.to_string();
+    Some(CpanModule {
+        module,
+        version: match version {
+            "undef" => None,
+            version => Some(version.to_string()),
+        },
+        path,
+    })
+}
+
+/// The requirements for one phase, by relationship (`requires`, `recommends`, ...)
+pub type CpanPhasePrereqs = HashMap<String, HashMap<String, Value>>;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct CpanResources {
+    pub homepage: Option<String>,
+    pub repository: Option<Value>,
+    pub bugtracker: Option<Value>,
+}
+
+/// The parts of a `META.json` we care about
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CpanMeta {
+    pub name: String,
+    pub version: Value,
+    #[serde(rename = "abstract")]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub author: Vec<String>,
+    #[serde(default)]
+    pub license: Vec<String>,
+    /// By phase - `runtime`, `build`, `test`, `configure` and `develop`
+    #[serde(default)]
+    pub prereqs: HashMap<String, CpanPhasePrereqs>,
+    #[serde(default)]
+    pub resources: CpanResources,
+    pub release_status: Option<String>,
+}
+
+impl CpanMeta {
+    /// The required modules and versions for a phase
+    pub fn requires(&self, phase: &str) -> Option<&HashMap<String, Value>> {
+        self.prereqs.get(phase)?.get("requires")
+    }
+
+    /// Merge the distribution metadata into a module's [Package]
+    pub fn apply(self, package: &mut Package) {
+        let metadata = &mut package.other_metadata;
+        if let Some(summary) = self.summary.as_ref() {
+            metadata.insert("description".to_string(), Value::String(summary.clone()));
+        }
+        if !self.license.is_empty() {
+            metadata.insert(
+                "license".to_string(),
+                Value::String(self.license.join(" OR ")),
+            );
+        }
+        if !self.author.is_empty() {
+            metadata.insert("authors".to_string(), Value::from(self.author.clone()));
+        }
+        if let Some(homepage) = self.resources.homepage.as_ref() {
+            metadata.insert("homepage".to_string(), Value::String(homepage.clone()));
+        }
+        if let Some(repository) = self.resources.repository.as_ref() {
+            metadata.insert("repository".to_string(), repository.clone());
+        }
+        if let Some(runtime) = self.requires("runtime") {
+            metadata.insert(
+                "dependencies".to_string(),
+                serde_json::to_value(runtime).unwrap_or_default(),
+            );
+        }
+        if !self.prereqs.is_empty() {
+            metadata.insert(
+                "prereqs".to_string(),
+                serde_json::to_value(&self.prereqs).unwrap_or_default(),
+            );
+        }
+        if let Some(status) = self.release_status {
+            metadata.insert("release_status".to_string(), Value::String(status));
+        }
+    }
+}
+
+impl From<CpanModule> for Package {
+    fn from(value: CpanModule) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        if let Some(version) = value.version.as_ref() {
+            other_metadata.insert("version".to_string(), Value::String(version.clone()));
+        }
+        if let Some((distribution, dist_version)) = value.distribution() {
+            other_metadata.insert("distribution".to_string(), Value::String(distribution));
+            other_metadata.insert(
+                "distribution_version".to_string(),
+                Value::String(dist_version),
+            );
+        }
+        other_metadata.insert("path".to_string(), Value::String(value.path.clone()));
+
+        Package {
+            url: Some(format!("https://metacpan.org/pod/{}", value.module)),
+            owner: value.author().map(String::from),
+            name: value.module,
+            other_metadata,
+            repo_type: RepoType::Cpan,
+        }
+    }
+}
+
+#[async_trait]
+impl Repository for Cpan {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            mirror: CPAN_MIRROR_URL.to_string(),
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::Cpan
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            mirror: true,
+            dependencies: true,
+            ..Capabilities::none(RepoType::Cpan)
+        }
+    }
+
+    /// Searches module names, case insensitively
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let query = query.to_lowercase();
+        Ok(self
+            .load_index()
+            .await?
+            .into_iter()
+            .filter(|module| module.module.to_lowercase().contains(&query))
+            .map(|module| module.into())
+            .collect())
+    }
+
+    /// Takes a module name like `LWP::UserAgent`, adding the distribution's prereqs if we can get them
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let Some(module) = self
+            .load_index()
+            .await?
+            .into_iter()
+            .find(|module| module.module == name)
+        else {
+            return Err(Errors::NotFound {
+                repo_type: RepoType::Cpan,
+                name: name.to_string(),
+            });
+        };
+
+        let meta = self.get_meta(&module).await;
+        let mut package: Package = module.into();
+        if let Some(meta) = meta {
+            meta.apply(&mut package);
+        }
+        Ok(vec![package])
+    }
+
+    async fn cacheable(&self) -> bool {
+        true
+    }
+
+    async fn update_cache(&self, min_age: Option<u64>) -> Result<(), Errors> {
+        let _lock = self.cache.read().await.lock_ecosystem(RepoType::Cpan)?;
+        let url = self.index_url();
+        if let Some(min_age) = min_age {
+            let max_age = chrono::Duration::seconds(min_age as i64);
+            if self
+                .cache
+                .read()
+                .await
+                .is_fresh(RepoType::Cpan, &url, max_age)
+            {
+                return Ok(());
+            }
+        }
+        self.fetch_index(&url).await?;
+        Ok(())
+    }
+}
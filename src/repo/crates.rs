@@ -4,22 +4,24 @@ use super::prelude::*;
 
 use async_trait::async_trait;
 use chrono::DateTime;
+use reqwest::header::AUTHORIZATION;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::{make_cache_dir, Errors};
+use crate::request::{parse_json, read_json};
+use crate::Errors;
 
+use super::cargo_config::{CargoConfig, CargoRegistry, CRATES_IO};
 use super::Repository;
 
-const CARGO_API_URL: &str = "https://crates.io/api/v1/crates";
-
 #[derive(Deserialize, Serialize, Debug)]
 struct IndexPackage {
     name: String,
     #[serde(alias = "vers")]
     version: String,
-    deps: Vec<String>,
-    features: HashMap<String, String>,
+    deps: Vec<Value>,
+    features: HashMap<String, Vec<String>>,
     cksum: String,
     yanked: bool,
 }
@@ -27,98 +29,296 @@ struct IndexPackage {
 // Data's also available at
 // https://github.com/rust-lang/crates.io-index
 
-impl From<IndexPackage> for Package {
-    fn from(pkg: IndexPackage) -> Self {
+impl IndexPackage {
+    fn into_package(self, registry: &CargoRegistry, config: &RegistryConfig) -> Package {
         let mut other_metadata = HashMap::new();
-        other_metadata.insert("checksum".to_string(), Value::String(pkg.cksum));
-        other_metadata.insert("yanked".to_string(), Value::String(pkg.yanked.to_string()));
-        if !pkg.features.is_empty() {
+        other_metadata.insert("version".to_string(), Value::String(self.version.clone()));
+        other_metadata.insert("registry".to_string(), Value::String(registry.name.clone()));
+        other_metadata.insert(
+            "download_url".to_string(),
+            Value::String(config.download_url(&self.name, &self.version, &self.cksum)),
+        );
+        other_metadata.insert("checksum".to_string(), Value::String(self.cksum));
+        other_metadata.insert("yanked".to_string(), Value::String(self.yanked.to_string()));
+        if !self.deps.is_empty() {
+            other_metadata.insert("dependencies".to_string(), Value::Array(self.deps));
+        }
+        if !self.features.is_empty() {
             other_metadata.insert(
                 "features".to_string(),
-                Value::from(format!("{:?}", pkg.features)),
+                Value::from(format!("{:?}", self.features)),
             );
         }
-        Self {
-            name: pkg.name.clone(),
-            url: Some(format!("https://crates.io/crates/{}", pkg.name)),
+        Package {
+            url: Self::url(registry, &self.name),
+            name: self.name,
             owner: None,
             other_metadata,
             repo_type: RepoType::Cargo,
         }
     }
+
+    fn url(registry: &CargoRegistry, name: &str) -> Option<String> {
+        (registry.name == CRATES_IO).then(|| format!("https://crates.io/crates/{}", name))
+    }
+}
+
+/// The directories a crate's file is in, eg `ba/se` or `3/s`, keeping the name's case
+fn index_prefix(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    match chars.len() {
+        0..=2 => chars.len().to_string(),
+        3 => format!("3/{}", chars[0]),
+        _ => format!(
+            "{}/{}",
+            chars[0..2].iter().collect::<String>(),
+            chars[2..4].iter().collect::<String>()
+        ),
+    }
+}
+
+/// Where a crate's file lives in the index, eg `ba/se/base64` or `3/s/syn`
+pub fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    format!("{}/{}", index_prefix(&name), name)
+}
+
+/// The registry's `config.json`, from the root of the index
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RegistryConfig {
+    pub dl: String,
+    /// Registries don't have to provide the web API, and without it there's no search
+    pub api: Option<String>,
+    #[serde(rename = "auth-required", default)]
+    pub auth_required: bool,
+}
+
+impl RegistryConfig {
+    /// Fill in the `dl` template, which gets `/{crate}/{version}/download` on the end if it hasn't got any markers
+    pub fn download_url(&self, name: &str, version: &str, checksum: &str) -> String {
+        let markers = [
+            "{crate}",
+            "{version}",
+            "{prefix}",
+            "{lowerprefix}",
+            "{sha256-checksum}",
+        ];
+        if !markers.iter().any(|marker| self.dl.contains(marker)) {
+            return format!(
+                "{}/{}/{}/download",
+                self.dl.trim_end_matches('/'),
+                name,
+                version
+            );
+        }
+        self.dl
+            .replace("{crate}", name)
+            .replace("{version}", version)
+            .replace("{prefix}", &index_prefix(name))
+            .replace("{lowerprefix}", &index_prefix(&name.to_lowercase()))
+            .replace("{sha256-checksum}", checksum)
+    }
 }
 
 #[derive(Debug)]
 pub struct Cargo {
     cache: Arc<RwLock<Cache>>,
+    pub registry: CargoRegistry,
+    config: Option<RegistryConfig>,
+}
+
+impl Cargo {
+    pub fn with_registry(mut self, registry: CargoRegistry) -> Self {
+        self.registry = registry;
+        self.config = None;
+        self
+    }
+
+    /// One [Cargo] per registry in the Cargo config, crates.io (or whatever replaces it) first
+    pub fn from_config(cache: Arc<RwLock<Cache>>) -> Result<Vec<Self>, Errors> {
+        Ok(CargoConfig::load()?
+            .registries()?
+            .into_iter()
+            .map(|registry| Self::new(cache.clone()).with_registry(registry))
+            .collect())
+    }
+
+    /// The token, if there is one and `url` is on the index's own origin. A git index's
+    /// files come from the forge's raw host, which never gets it
+    fn token_for(&self, url: &str) -> Option<&str> {
+        let token = self.registry.token.as_deref()?;
+        match (
+            reqwest::Url::parse(url),
+            reqwest::Url::parse(self.registry.index.url()),
+        ) {
+            (Ok(url), Ok(index)) if url.origin() == index.origin() => Some(token),
+            _ => None,
+        }
+    }
+
+    /// GET something from the registry. Like Cargo, the token only goes with the request
+    /// once `config.json` says `auth-required`, or after a 401 without it
+    async fn get(&self, url: &str) -> Result<reqwest::Response, Errors> {
+        let client = WebClient::for_repo_type(RepoType::Cargo);
+        let Some(token) = self.token_for(url) else {
+            return client.send(client.client.get(url)).await;
+        };
+        let with_token = || client.client.get(url).header(AUTHORIZATION, token);
+        if self
+            .config
+            .as_ref()
+            .is_some_and(|config| config.auth_required)
+        {
+            return client.send(with_token()).await;
+        }
+        let res = client.send(client.client.get(url)).await?;
+        match res.status() {
+            StatusCode::UNAUTHORIZED => client.send(with_token()).await,
+            _ => Ok(res),
+        }
+    }
+
+    async fn registry_config(&mut self) -> Result<RegistryConfig, Errors> {
+        if let Some(config) = self.config.as_ref() {
+            return Ok(config.clone());
+        }
+        let url = format!("{}/config.json", self.registry.index.file_base()?);
+        let config: RegistryConfig = read_json(self.get(&url).await?.check_status()?).await?;
+        self.config = Some(config.clone());
+        Ok(config)
+    }
+}
+
+/// crates.io's search response, or the few fields other registries have to return
+fn parse_search(url: &str, content: &str) -> Result<Vec<Package>, Errors> {
+    match parse_json::<CratesResponse>(url, content) {
+        Ok(cratedata) => Ok(cratedata
+            .crates
+            .unwrap_or_default()
+            .into_iter()
+            .map(Package::from)
+            .collect()),
+        Err(_) => {
+            let cratedata: MinimalSearchResponse = parse_json(url, content)?;
+            Ok(cratedata.crates.into_iter().map(Package::from).collect())
+        }
+    }
+}
+
+/// What a registry that isn't crates.io is guaranteed to return from a search
+#[derive(Deserialize, Serialize, Debug)]
+struct MinimalSearchCrate {
+    name: String,
+    max_version: String,
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct MinimalSearchResponse {
+    crates: Vec<MinimalSearchCrate>,
+}
+
+impl From<MinimalSearchCrate> for Package {
+    fn from(value: MinimalSearchCrate) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        other_metadata.insert("max_version".to_string(), Value::String(value.max_version));
+        if let Some(description) = value.description {
+            other_metadata.insert("description".to_string(), Value::String(description));
+        }
+        Package {
+            name: value.name,
+            url: None,
+            owner: None,
+            other_metadata,
+            repo_type: RepoType::Cargo,
+        }
+    }
 }
 
 #[async_trait]
 impl Repository for Cargo {
     fn new(cache: Arc<RwLock<Cache>>) -> Self {
-        Self { cache }
+        Self {
+            cache,
+            registry: CargoRegistry::default(),
+            config: None,
+        }
     }
 
     fn repo_type() -> RepoType {
         RepoType::Cargo
     }
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            dependencies: true,
+            downloads: true,
+            ..Capabilities::none(RepoType::Cargo)
+        }
+    }
+
     async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
-        let mut url = reqwest::Url::from_str(CARGO_API_URL)
-            .expect("Failed to turn static crates URL into a URL object!");
+        let config = self.registry_config().await?;
+        let api = config.api.ok_or_else(|| Errors::Unsupported {
+            repo_type: RepoType::Cargo,
+            operation: format!("search on {}", self.registry.name),
+        })?;
+        let mut url =
+            reqwest::Url::from_str(&format!("{}/api/v1/crates", api.trim_end_matches('/')))?;
         url.query_pairs_mut().append_pair("q", query);
 
-        let mut packages = Vec::new();
-        let cratedata: CratesResponse =
-            match self.cache.read().await.get_cache(url.as_ref(), None, None) {
-                Some(val) => serde_json::from_str(&val.content)?,
-                None => {
-                    let res = WebClient::default().client.get(url).send().await?;
-                    res.json().await?
-                }
-            };
-
-        if let Some(crates) = cratedata.crates {
-            for crt in crates {
-                let package: Package = crt.into();
-                packages.push(package);
+        let cached = self
+            .cache
+            .read()
+            .await
+            .get_fresh(RepoType::Cargo, url.as_ref());
+        let mut packages = match cached {
+            Some(val) => parse_search(url.as_str(), &val.content)?,
+            None => {
+                let content = self.get(url.as_str()).await?.check_status()?.text().await?;
+                // only worth keeping once we know it's a search result and not an error page
+                let packages = parse_search(url.as_str(), &content)?;
+                self.cache.read().await.save(
+                    CacheData::new(url.to_string(), String::new(), content)
+                        .with_kind(RepoType::Cargo, ResourceKind::Search),
+                )?;
+                packages
             }
+        };
+        for package in packages.iter_mut() {
+            package.other_metadata.insert(
+                "registry".to_string(),
+                Value::String(self.registry.name.clone()),
+            );
         }
 
         Ok(packages)
     }
 
-    fn get_cache_dir(&self) -> String {
-        "crates/".to_string()
-    }
-
     async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
-        let url = if name.is_empty() {
-            return Err(Errors::Generic("Specify a name!".to_string()));
-        } else if name.len() < 5 {
-            // https://index.crates.io/2/a-
-            format!("https://index.crates.io/{}/{}", name.len(), name)
-        } else {
-            let first_bits = &name[0..=1];
-            let second_bits = &name[2..=3];
-            format!(
-                "https://index.crates.io/{}/{}/{}",
-                first_bits, second_bits, name
-            )
-        };
-
-        let client = WebClient::default();
-        let resp = client.client.get(&url).send().await?;
-        println!("{:?}", resp);
+        if name.is_empty() {
+            return Err(Errors::InvalidInput("Specify a name!".to_string()));
+        }
+        let config = self.registry_config().await?;
         // finding base64 gets you to
         // https://index.crates.io/ba/se/base64
-
-        let res_text = resp.text().await?;
+        let url = format!("{}/{}", self.registry.index.file_base()?, index_path(name));
+
+        let res_text = self
+            .get(&url)
+            .await?
+            .check_status()
+            .map_err(|err| err.or_not_found(RepoType::Cargo, name))?
+            .text()
+            .await?;
         let mut res: Vec<Package> = Vec::new();
         for line in res_text.lines() {
             let val = serde_json::from_str::<IndexPackage>(line).ok();
             if let Some(val) = val {
-                res.push(val.into());
+                res.push(val.into_package(&self.registry, &config));
             }
         }
 
@@ -126,73 +326,12 @@ impl Repository for Cargo {
     }
 
     async fn cacheable(&self) -> bool {
-        true
+        false
     }
 
+    /// The sparse index can only be read a crate at a time, there's nothing to mirror
     async fn update_cache(&self, _min_age: Option<u64>) -> Result<(), Errors> {
-        make_cache_dir()?;
-        todo!();
-
-        // for crate_releases in index.crates() {
-        //     let _ = crate_releases.most_recent_version(); // newest version
-        //     let crate_version = crate_releases.highest_version(); // max version by semver
-        //     println!("crate name: {}", crate_version.name());
-        //     println!("crate version: {}", crate_version.version());
-        // }
-
-        // // check the file is older than min_age seconds
-        // if let Some(min_age) = min_age {
-        //     if file_older_than(&cache_path, min_age)? {
-        //         return Ok(());
-        //     }
-        // }
-
-        // let mut querystring = "".to_string();
-        // let mut cratecache: Vec<Crate> = Vec::new();
-        // loop {
-        //     let url = format!("{}?{}", CARGO_API_URL, querystring);
-
-        //     let resp = client.client.get(url).send().await?;
-        //     let resp_content = resp.text().await?;
-
-        //     let parsed: CratesResposne = match serde_json::from_str(&resp_content) {
-        //         Ok(val) => val,
-        //         Err(err) => {
-        //             println!("Got error parsing response: {:?}", err);
-        //             println!("Response: {}", resp_content);
-        //             return Err(err.into());
-        //         }
-        //     };
-
-        //     if let Some(errors) = parsed.errors {
-        //         println!("Got errors: {:?}", errors);
-        //         break;
-        //     }
-
-        //     if let Some(crates) = parsed.crates {
-        //         println!(
-        //             "Got another {} packages, up to {}",
-        //             crates.len(),
-        //             cratecache.len()
-        //         );
-        //         cratecache.extend(crates);
-        //     }
-
-        //     if !parsed.meta.has_more() {
-        //         break;
-        //     } else {
-        //         if let Some(next_page) = parsed.meta.next_page {
-        //             querystring = next_page;
-        //         }
-        //     }
-        // }
-
-        // let mut file = std::fs::File::create(cache_path)?;
-        // let file_contents = serde_json::to_string(&cratecache)?;
-        // file.write_all(file_contents.as_bytes())?;
-
-        // println!("done!");
-        // Ok(())
+        Err(self.capabilities().unsupported("mirroring"))
     }
 }
 
@@ -0,0 +1,390 @@
+//! Repository hooks for GitHub releases, treating `owner/repo` as a package
+//!
+//! GitHub API reference - <https://docs.github.com/en/rest/releases/releases>
+//!
+//! Gitea and Forgejo have a compatible API under `/api/v1` - <https://gitea.com/api/swagger>
+
+use std::str::FromStr;
+
+use reqwest::header::{ACCEPT, AUTHORIZATION, LINK};
+
+use super::compare_semver;
+use super::prelude::*;
+use crate::request::{next_link, parse_json, read_json};
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+/// Only sent to [GITHUB_API_URL], other servers need [GitHub::with_token]
+const GITHUB_TOKEN_ENV: &str = "GITHUB_TOKEN";
+
+/// Asset names that are probably a list of checksums for the other assets
+const CHECKSUM_ASSET_PATTERNS: [&str; 6] = [
+    "checksums",
+    "sha256sums",
+    "sha512sums",
+    ".sha256",
+    ".sha512",
+    ".sha256sum",
+];
+
+/// The search endpoints differ between the two
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ForgeKind {
+    #[default]
+    GitHub,
+    /// Gitea and Forgejo
+    Gitea,
+}
+
+impl FromStr for ForgeKind {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "github" => Ok(Self::GitHub),
+            "gitea" | "forgejo" => Ok(Self::Gitea),
+            _ => Err(Errors::Config(format!(
+                "{} isn't a kind of forge, it's github or gitea",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GitHub {
+    pub cache: Arc<RwLock<Cache>>,
+    pub api_url: String,
+    pub kind: ForgeKind,
+    pub token: Option<String>,
+}
+
+impl GitHub {
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Point at a Gitea/Forgejo server, eg `https://codeberg.org/api/v1`
+    pub fn with_gitea(mut self, api_url: &str) -> Self {
+        self.kind = ForgeKind::Gitea;
+        self.with_api_url(api_url)
+    }
+
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    fn token(&self) -> Option<String> {
+        match self.token.as_ref() {
+            Some(token) => Some(token.clone()),
+            None if self.api_url == GITHUB_API_URL => std::env::var(GITHUB_TOKEN_ENV).ok(),
+            None => None,
+        }
+    }
+
+    fn page_size_param(&self) -> &'static str {
+        match self.kind {
+            ForgeKind::GitHub => "per_page",
+            ForgeKind::Gitea => "limit",
+        }
+    }
+
+    async fn get(&self, url: &str) -> Result<reqwest::Response, Errors> {
+        let client = WebClient::for_repo_type(RepoType::GitHub);
+        let mut req = client.client.get(url).header(ACCEPT, "application/json");
+        if let Some(token) = self.token().filter(|_| self.is_api(url)) {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        client.send(req).await?.check_status()
+    }
+
+    /// Whether `url` is on the server we're configured for, rather than somewhere a `Link`
+    /// header pointed that shouldn't see the token
+    fn is_api(&self, url: &str) -> bool {
+        match (reqwest::Url::parse(url), reqwest::Url::parse(&self.api_url)) {
+            (Ok(url), Ok(api_url)) => url.origin() == api_url.origin(),
+            _ => false,
+        }
+    }
+
+    /// Get every page of a list endpoint, following `Link` headers
+    async fn get_all<T: for<'de> Deserialize<'de>>(&self, path: &str) -> Result<Vec<T>, Errors> {
+        let mut results = Vec::new();
+        let mut url = format!("{}/{}?{}=100", self.api_url, path, self.page_size_param());
+        loop {
+            let res = self.get(&url).await?;
+            let next = res
+                .headers()
+                .get(LINK)
+                .and_then(|h| h.to_str().ok())
+                .and_then(next_link);
+            let page: Vec<T> = parse_json(&url, &res.text().await?)?;
+            if page.is_empty() {
+                break;
+            }
+            results.extend(page);
+            match next {
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(results)
+    }
+
+    pub async fn releases(&self, repository: &str) -> Result<Vec<GitRelease>, Errors> {
+        self.get_all(&format!("repos/{}/releases", repository))
+            .await
+    }
+
+    pub async fn tags(&self, repository: &str) -> Result<Vec<GitTag>, Errors> {
+        self.get_all(&format!("repos/{}/tags", repository)).await
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GitUser {
+    pub login: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GitAsset {
+    pub name: String,
+    pub browser_download_url: String,
+    #[serde(default)]
+    pub size: u64,
+    pub content_type: Option<String>,
+    pub download_count: Option<u64>,
+    /// GitHub fills this in for newer uploads, as `sha256:<hex>`
+    pub digest: Option<String>,
+}
+
+impl GitAsset {
+    pub fn is_checksums(&self) -> bool {
+        let name = self.name.to_lowercase();
+        CHECKSUM_ASSET_PATTERNS
+            .iter()
+            .any(|pattern| name.contains(pattern))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GitRelease {
+    pub tag_name: String,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    pub html_url: Option<String>,
+    pub body: Option<String>,
+    pub author: Option<GitUser>,
+    pub created_at: Option<DateTime<chrono::Utc>>,
+    /// Drafts haven't been published
+    pub published_at: Option<DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub assets: Vec<GitAsset>,
+}
+
+impl GitRelease {
+    pub fn into_package(self, repository: &str) -> Package {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        other_metadata.insert("kind".to_string(), Value::String("release".to_string()));
+        other_metadata.insert("version".to_string(), Value::String(self.tag_name));
+        if let Some(name) = self.name.filter(|name| !name.is_empty()) {
+            other_metadata.insert("release_name".to_string(), Value::String(name));
+        }
+        other_metadata.insert("draft".to_string(), Value::Bool(self.draft));
+        other_metadata.insert("prerelease".to_string(), Value::Bool(self.prerelease));
+        if let Some(date) = self.published_at.or(self.created_at) {
+            other_metadata.insert("release_date".to_string(), Value::String(date.to_rfc3339()));
+        }
+        if let Some(checksums) = self.assets.iter().find(|asset| asset.is_checksums()) {
+            other_metadata.insert(
+                "checksums_url".to_string(),
+                Value::String(checksums.browser_download_url.clone()),
+            );
+        }
+        if !self.assets.is_empty() {
+            let assets: Vec<Value> = self
+                .assets
+                .iter()
+                .map(|asset| {
+                    serde_json::json!({
+                        "name": asset.name,
+                        "download_url": asset.browser_download_url,
+                        "size": asset.size,
+                        "content_type": asset.content_type,
+                        "checksum": asset.digest,
+                    })
+                })
+                .collect();
+            other_metadata.insert("assets".to_string(), Value::Array(assets));
+        }
+
+        Package {
+            name: repository.to_string(),
+            url: self.html_url,
+            owner: self.author.map(|author| author.login),
+            other_metadata,
+            repo_type: RepoType::GitHub,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GitCommit {
+    pub sha: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GitTag {
+    pub name: String,
+    pub commit: Option<GitCommit>,
+    pub tarball_url: Option<String>,
+    pub zipball_url: Option<String>,
+}
+
+impl GitTag {
+    pub fn into_package(self, repository: &str) -> Package {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        other_metadata.insert("kind".to_string(), Value::String("tag".to_string()));
+        other_metadata.insert("version".to_string(), Value::String(self.name));
+        if let Some(commit) = self.commit {
+            other_metadata.insert("commit".to_string(), Value::String(commit.sha));
+        }
+        if let Some(url) = self.tarball_url {
+            other_metadata.insert("download_url".to_string(), Value::String(url));
+        }
+
+        Package {
+            name: repository.to_string(),
+            url: None,
+            owner: repository.split('/').next().map(String::from),
+            other_metadata,
+            repo_type: RepoType::GitHub,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct GitRepository {
+    pub full_name: String,
+    pub description: Option<String>,
+    pub html_url: Option<String>,
+    pub owner: Option<GitUser>,
+    #[serde(alias = "stars_count")]
+    pub stargazers_count: Option<u64>,
+    #[serde(default)]
+    pub archived: bool,
+}
+
+impl From<GitRepository> for Package {
+    fn from(value: GitRepository) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        if let Some(description) = value.description {
+            other_metadata.insert("description".to_string(), Value::String(description));
+        }
+        if let Some(stars) = value.stargazers_count {
+            other_metadata.insert("stars".to_string(), Value::from(stars));
+        }
+        if value.archived {
+            other_metadata.insert(
+                "deprecated".to_string(),
+                Value::String("archived".to_string()),
+            );
+        }
+
+        Package {
+            name: value.full_name,
+            url: value.html_url,
+            owner: value.owner.map(|owner| owner.login),
+            other_metadata,
+            repo_type: RepoType::GitHub,
+        }
+    }
+}
+
+/// GitHub puts the results in `items`, Gitea in `data`
+#[derive(Deserialize, Debug)]
+struct SearchResponse {
+    #[serde(alias = "data")]
+    items: Vec<GitRepository>,
+}
+
+#[async_trait]
+impl Repository for GitHub {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            api_url: GITHUB_API_URL.to_string(),
+            kind: ForgeKind::default(),
+            token: None,
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::GitHub
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            downloads: true,
+            ..Capabilities::none(RepoType::GitHub)
+        }
+    }
+
+    /// Searches repositories, only the first page of results
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let path = match self.kind {
+            ForgeKind::GitHub => "search/repositories",
+            ForgeKind::Gitea => "repos/search",
+        };
+        let mut url = reqwest::Url::parse(&format!("{}/{}", self.api_url, path))?;
+        url.query_pairs_mut().append_pair("q", query);
+        let response: SearchResponse = read_json(self.get(url.as_str()).await?).await?;
+        Ok(response
+            .items
+            .into_iter()
+            .map(|repository| repository.into())
+            .collect())
+    }
+
+    /// Takes `owner/repo`, returning its releases and any tags without a release, oldest first
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let releases = self
+            .releases(name)
+            .await
+            .map_err(|err| err.or_not_found(RepoType::GitHub, name))?;
+        let tags: Vec<GitTag> = self
+            .tags(name)
+            .await?
+            .into_iter()
+            .filter(|tag| !releases.iter().any(|release| release.tag_name == tag.name))
+            .collect();
+
+        let mut packages: Vec<Package> = releases
+            .into_iter()
+            .map(|release| release.into_package(name))
+            .chain(tags.into_iter().map(|tag| tag.into_package(name)))
+            .collect();
+        packages.sort_by(|a, b| {
+            compare_semver(
+                a.other_metadata["version"].as_str().unwrap_or_default(),
+                b.other_metadata["version"].as_str().unwrap_or_default(),
+            )
+        });
+        Ok(packages)
+    }
+
+    async fn cacheable(&self) -> bool {
+        false
+    }
+
+    async fn update_cache(&self, _min_age: Option<u64>) -> Result<(), Errors> {
+        Err(self.capabilities().unsupported("mirroring"))
+    }
+}
@@ -0,0 +1,556 @@
+//! Repository hooks for Hackage, the Haskell package repository
+//!
+//! Reads the `01-index.tar` package index, which is append-only so it's kept up to date with
+//! Range requests - <https://hackage.haskell.org/api#core>
+
+use std::cmp::Ordering;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use reqwest::StatusCode;
+
+use super::prelude::*;
+use crate::cache::temp_path;
+use crate::file_older_than;
+
+const HACKAGE_URL: &str = "https://hackage.haskell.org";
+const INDEX_FILE: &str = "01-index.tar";
+/// Next to the index while it's being appended to, holding where the old content ended
+const APPEND_MARKER: &str = "01-index.tar.appending";
+/// A tar archive ends with two empty blocks, which we overwrite when appending
+const TAR_TRAILER_SIZE: u64 = 1024;
+
+#[derive(Debug)]
+pub struct Hackage {
+    pub cache: Arc<RwLock<Cache>>,
+    pub base_url: String,
+}
+
+impl Hackage {
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// In this ecosystem's directory of our cache
+    async fn index_path(&self) -> PathBuf {
+        self.cache
+            .read()
+            .await
+            .ecosystem_dir(Some(RepoType::Hackage))
+            .join(INDEX_FILE)
+    }
+
+    /// Grab the whole index, using the compressed version to save on bandwidth
+    async fn download_index(&self, path: &Path) -> Result<(), Errors> {
+        let client = WebClient::for_repo_type(RepoType::Hackage);
+        let mut res = client
+            .send(
+                client
+                    .client
+                    .get(format!("{}/01-index.tar.gz", self.base_url)),
+            )
+            .await?
+            .check_status()?;
+        // it's big, so it goes to disk next to where it's going rather than into memory, gets
+        // unpacked there and is moved into place when it's done
+        let compressed = temp_path(path);
+        let temp = temp_path(path);
+        let result = async {
+            write_body(&mut res, &mut File::create(&compressed)?).await?;
+            let mut decoder = GzDecoder::new(BufReader::new(File::open(&compressed)?));
+            std::io::copy(&mut decoder, &mut File::create(&temp)?)?;
+            Ok(std::fs::rename(&temp, path)?)
+        }
+        .await;
+        std::fs::remove_file(&compressed).ok();
+        if result.is_err() {
+            std::fs::remove_file(&temp).ok();
+        }
+        result
+    }
+
+    /// Fetch anything that's been appended to the index since we last grabbed it
+    ///
+    /// That's written over the end of the index we've got, so where it started is kept in
+    /// [APPEND_MARKER] until it's done, for [recover_index] to cut it back to if it doesn't finish.
+    async fn append_index(&self, path: &Path) -> Result<(), Errors> {
+        recover_index(path)?;
+        let offset = std::fs::metadata(path)?
+            .len()
+            .saturating_sub(TAR_TRAILER_SIZE);
+        let client = WebClient::for_repo_type(RepoType::Hackage);
+        let mut res = client
+            .send(
+                client
+                    .client
+                    .get(format!("{}/01-index.tar", self.base_url))
+                    .header(reqwest::header::RANGE, format!("bytes={}-", offset)),
+            )
+            .await?;
+
+        match res.status() {
+            // nothing new
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(()),
+            StatusCode::PARTIAL_CONTENT => {
+                let marker = marker_path(path);
+                std::fs::write(&marker, offset.to_string())?;
+                let mut file = OpenOptions::new().write(true).open(path)?;
+                file.set_len(offset)?;
+                file.seek(std::io::SeekFrom::Start(offset))?;
+                if let Err(err) = write_body(&mut res, &mut file).await {
+                    drop(file);
+                    recover_index(path)?;
+                    return Err(err);
+                }
+                std::fs::remove_file(marker)?;
+                Ok(())
+            }
+            // the server ignored the range, so we're getting the whole thing
+            StatusCode::OK => {
+                let temp = temp_path(path);
+                let mut file = File::create(&temp)?;
+                if let Err(err) = write_body(&mut res, &mut file).await {
+                    std::fs::remove_file(&temp).ok();
+                    return Err(err);
+                }
+                std::fs::rename(&temp, path)?;
+                Ok(())
+            }
+            _ => {
+                res.check_status()?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Open the local index, fetching it if we don't have it yet
+    async fn open_index(&self) -> Result<BufReader<File>, Errors> {
+        let path = self.index_path().await;
+        {
+            let _lock = self.cache.read().await.lock_ecosystem(RepoType::Hackage)?;
+            recover_index(&path)?;
+        }
+        if !path.exists() {
+            self.update_cache(None).await?;
+        }
+        Ok(BufReader::new(File::open(path)?))
+    }
+}
+
+fn marker_path(path: &Path) -> PathBuf {
+    path.with_file_name(APPEND_MARKER)
+}
+
+/// Cut the index back to how it was before an append that didn't finish, if there was one
+fn recover_index(path: &Path) -> Result<(), Errors> {
+    let marker = marker_path(path);
+    let Ok(offset) = std::fs::read_to_string(&marker) else {
+        return Ok(());
+    };
+    match (offset.trim().parse::<u64>(), path.exists()) {
+        (Ok(offset), true) => {
+            let file = OpenOptions::new().write(true).open(path)?;
+            file.set_len(offset)?;
+            // growing it again fills it with zeros, which is the trailer that was there
+            file.set_len(offset + TAR_TRAILER_SIZE)?;
+        }
+        // there's no telling what's good, so it's downloaded again
+        (Err(_), true) => std::fs::remove_file(path)?,
+        (_, false) => {}
+    }
+    std::fs::remove_file(marker)?;
+    Ok(())
+}
+
+async fn write_body(res: &mut reqwest::Response, file: &mut File) -> Result<(), Errors> {
+    while let Some(chunk) = res.chunk().await? {
+        file.write_all(&chunk)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Compare two PVP versions, which are dot separated numbers compared as a list
+pub fn compare_versions(left: &str, right: &str) -> Ordering {
+    parse_version(left).cmp(&parse_version(right))
+}
+
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .trim()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+/// Check a version against a Cabal version range, eg `>=1.2 && <1.3 || ^>=2.0`
+pub fn version_in_range(range: &str, version: &str) -> bool {
+    let version = parse_version(version);
+    range.split("||").any(|alternative| {
+        alternative.split("&&").all(|constraint| {
+            let constraint = constraint.trim().trim_matches(['(', ')']).trim();
+            match constraint {
+                "" | "-any" => return true,
+                "-none" => return false,
+                _ => {}
+            }
+            for op in ["^>=", ">=", "<=", "==", ">", "<"] {
+                let Some(target) = constraint.strip_prefix(op) else {
+                    continue;
+                };
+                let target = target.trim();
+                if let Some(prefix) = target.strip_suffix(".*") {
+                    let prefix = parse_version(prefix);
+                    return version.starts_with(&prefix);
+                }
+                let target = parse_version(target);
+                return match op {
+                    // major bound - at least this version, below the next A.B
+                    "^>=" => {
+                        let mut upper: Vec<u64> = target.iter().take(2).copied().collect();
+                        upper.resize(2, 0);
+                        upper[1] += 1;
+                        version >= target && version < upper
+                    }
+                    ">=" => version >= target,
+                    "<=" => version <= target,
+                    ">" => version > target,
+                    "<" => version < target,
+                    _ => version == target,
+                };
+            }
+            // a bare version means exactly that version
+            version == parse_version(constraint)
+        })
+    })
+}
+
+/// A `build-depends` entry
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CabalDependency {
+    pub name: String,
+    /// `None` for any version
+    pub range: Option<String>,
+}
+
+/// The parts of a `.cabal` file we care about
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct CabalFile {
+    pub name: String,
+    pub version: String,
+    pub license: Option<String>,
+    pub maintainer: Option<String>,
+    pub author: Option<String>,
+    pub synopsis: Option<String>,
+    pub homepage: Option<String>,
+    /// Keyed by component, eg `library`, `exe:foo` or `test:spec`
+    pub dependencies: HashMap<String, Vec<CabalDependency>>,
+}
+
+/// Name a section the way cabal's component syntax does, eg `exe:foo`
+fn component_name(header: &str) -> String {
+    let mut parts = header.split_whitespace();
+    let kind = parts.next().unwrap_or_default().to_lowercase();
+    let name = parts.next().unwrap_or_default();
+    let prefix = match kind.as_str() {
+        "library" if name.is_empty() => return "library".to_string(),
+        "library" => "lib",
+        "executable" => "exe",
+        "test-suite" => "test",
+        "benchmark" => "bench",
+        "foreign-library" => "flib",
+        other => other,
+    };
+    format!("{}:{}", prefix, name)
+}
+
+fn parse_build_depends(value: &str) -> Vec<CabalDependency> {
+    value
+        .split(',')
+        .filter_map(|dep| {
+            let dep = dep.trim();
+            let name_end = dep
+                .find(|c: char| c.is_whitespace() || "<>=^".contains(c))
+                .unwrap_or(dep.len());
+            let name = &dep[..name_end];
+            if name.is_empty() {
+                return None;
+            }
+            let range = dep[name_end..].trim();
+            Some(CabalDependency {
+                name: name.to_string(),
+                range: (!range.is_empty()).then(|| range.to_string()),
+            })
+        })
+        .collect()
+}
+
+impl CabalFile {
+    /// A (reasonably forgiving) parser for the layout based `.cabal` format
+    pub fn parse(content: &str) -> Self {
+        let mut cabal = CabalFile::default();
+        // (field name, indent, value, component)
+        let mut fields: Vec<(String, usize, String, Option<String>)> = Vec::new();
+        let mut component: Option<String> = None;
+
+        for line in content.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with("--") {
+                continue;
+            }
+            let indent = line.len() - line.trim_start().len();
+
+            // continuation of the previous field
+            if let Some(last) = fields.last_mut() {
+                if !last.0.is_empty() && indent > last.1 {
+                    last.2.push(' ');
+                    last.2.push_str(trimmed);
+                    continue;
+                }
+            }
+
+            match trimmed.split_once(':') {
+                Some((key, value)) if !key.contains(char::is_whitespace) => {
+                    fields.push((
+                        key.to_lowercase(),
+                        indent,
+                        value.trim().to_string(),
+                        component.clone(),
+                    ));
+                }
+                _ => {
+                    // a section header, `if`/`else` blocks stay in their component
+                    if indent == 0 {
+                        component = Some(component_name(trimmed));
+                    }
+                    fields.push((String::new(), indent, String::new(), None));
+                }
+            }
+        }
+
+        for (key, _, value, component) in fields {
+            let value = value.trim().to_string();
+            match (key.as_str(), component) {
+                ("build-depends", Some(component)) => cabal
+                    .dependencies
+                    .entry(component)
+                    .or_default()
+                    .extend(parse_build_depends(&value)),
+                ("name", None) => cabal.name = value,
+                ("version", None) => cabal.version = value,
+                ("license", None) => cabal.license = Some(value),
+                ("maintainer", None) => cabal.maintainer = Some(value),
+                ("author", None) => cabal.author = Some(value),
+                ("synopsis", None) => cabal.synopsis = Some(value),
+                ("homepage", None) => cabal.homepage = Some(value),
+                _ => {}
+            }
+        }
+        cabal
+    }
+}
+
+/// Everything the index knows about one package
+#[derive(Debug, Default)]
+pub struct HackagePackage {
+    pub name: String,
+    /// The latest revision of the `.cabal` file for each version
+    pub cabal_files: Vec<CabalFile>,
+    /// The contents of `preferred-versions`, eg `aeson <2.0.1.0 || >2.0.1.0`
+    pub preferred_versions: Option<String>,
+}
+
+impl HackagePackage {
+    /// The range of preferred versions, with the package name stripped off
+    pub fn preferred_range(&self) -> Option<&str> {
+        self.preferred_versions
+            .as_deref()?
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with("--"))?
+            .strip_prefix(self.name.as_str())
+            .map(str::trim)
+    }
+
+    /// One [Package] per version, oldest first, with non-preferred versions marked deprecated
+    pub fn into_packages(self) -> Vec<Package> {
+        let preferred = self.preferred_range().map(String::from);
+        let mut cabal_files = self.cabal_files;
+        cabal_files.sort_by(|a, b| compare_versions(&a.version, &b.version));
+        cabal_files
+            .into_iter()
+            .map(|cabal| {
+                let deprecated = preferred
+                    .as_ref()
+                    .is_some_and(|range| !version_in_range(range, &cabal.version));
+                let mut package: Package = cabal.into();
+                if deprecated {
+                    package.other_metadata.insert(
+                        "deprecated".to_string(),
+                        Value::String("not a preferred version".to_string()),
+                    );
+                }
+                package
+            })
+            .collect()
+    }
+}
+
+impl From<CabalFile> for Package {
+    fn from(value: CabalFile) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        other_metadata.insert("version".to_string(), Value::String(value.version));
+        if let Some(license) = value.license {
+            other_metadata.insert("license".to_string(), Value::String(license));
+        }
+        if let Some(synopsis) = value.synopsis {
+            other_metadata.insert("description".to_string(), Value::String(synopsis));
+        }
+        if let Some(author) = value.author {
+            other_metadata.insert("author".to_string(), Value::String(author));
+        }
+        if !value.dependencies.is_empty() {
+            other_metadata.insert(
+                "dependencies".to_string(),
+                serde_json::to_value(&value.dependencies).unwrap_or_default(),
+            );
+        }
+        Package {
+            url: value
+                .homepage
+                .filter(|h| !h.is_empty())
+                .or(Some(format!("{}/package/{}", HACKAGE_URL, value.name))),
+            name: value.name,
+            owner: value.maintainer,
+            other_metadata,
+            repo_type: RepoType::Hackage,
+        }
+    }
+}
+
+/// All the package names in an index
+pub fn read_index_names<R: Read + Seek>(reader: R) -> Result<Vec<String>, Errors> {
+    let mut archive = tar::Archive::new(reader);
+    let mut names: Vec<String> = Vec::new();
+    for entry in archive.entries_with_seek()? {
+        let entry = entry?;
+        let path = entry.path()?;
+        if let Some(name) = path.iter().next().and_then(|n| n.to_str()) {
+            if names.last().map(String::as_str) != Some(name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Pull a single package's `.cabal` files and `preferred-versions` out of an index
+pub fn read_index_package<R: Read + Seek>(reader: R, name: &str) -> Result<HackagePackage, Errors> {
+    let mut archive = tar::Archive::new(reader);
+    let mut package = HackagePackage {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    // later entries are newer revisions, so they replace what we've seen
+    let mut by_version: HashMap<String, CabalFile> = HashMap::new();
+    for entry in archive.entries_with_seek()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let mut parts = path.split('/');
+        if parts.next() != Some(name) {
+            continue;
+        }
+        match (parts.next(), parts.next()) {
+            (Some("preferred-versions"), None) => {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                package.preferred_versions = Some(content);
+            }
+            (Some(version), Some(filename)) if filename.ends_with(".cabal") => {
+                let mut content = String::new();
+                entry.read_to_string(&mut content)?;
+                let mut cabal = CabalFile::parse(&content);
+                cabal.version = version.to_string();
+                by_version.insert(version.to_string(), cabal);
+            }
+            _ => {}
+        }
+    }
+    package.cabal_files = by_version.into_values().collect();
+    Ok(package)
+}
+
+#[async_trait]
+impl Repository for Hackage {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            base_url: HACKAGE_URL.to_string(),
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::Hackage
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            mirror: true,
+            dependencies: true,
+            ..Capabilities::none(RepoType::Hackage)
+        }
+    }
+
+    /// Searches package names in the local index
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let query = query.to_lowercase();
+        Ok(read_index_names(self.open_index().await?)?
+            .into_iter()
+            .filter(|name| name.to_lowercase().contains(&query))
+            .map(|name| Package {
+                url: Some(format!("{}/package/{}", self.base_url, name)),
+                name,
+                owner: None,
+                other_metadata: HashMap::new(),
+                repo_type: RepoType::Hackage,
+            })
+            .collect())
+    }
+
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let packages = read_index_package(self.open_index().await?, name)?.into_packages();
+        if packages.is_empty() {
+            return Err(Errors::NotFound {
+                repo_type: RepoType::Hackage,
+                name: name.to_string(),
+            });
+        }
+        Ok(packages)
+    }
+
+    async fn cacheable(&self) -> bool {
+        true
+    }
+
+    async fn update_cache(&self, min_age: Option<u64>) -> Result<(), Errors> {
+        let _lock = self.cache.read().await.lock_ecosystem(RepoType::Hackage)?;
+        let path = self.index_path().await;
+        if let Some(min_age) = min_age {
+            if file_older_than(&path, min_age)? {
+                return Ok(());
+            }
+        }
+        match path.exists() {
+            true => self.append_index(&path).await,
+            false => self.download_index(&path).await,
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! Repository hooks for Helm chart repositories
+//!
+//! Reads the repository's `index.yaml` - <https://helm.sh/docs/topics/chart_repository/#the-index-file>
+//!
+//! There's no central Helm repository, so they come from [HELM_REPOSITORIES_ENV] as a comma-separated list of
+//! `name=url` (or bare URLs), or from [Helm::with_repository].
+
+use std::str::FromStr;
+
+use super::compare_semver;
+use super::prelude::*;
+
+pub const HELM_REPOSITORIES_ENV: &str = "TIDETRAWLER_HELM_REPOSITORIES";
+
+/// A chart repository, `name` is what you'd call it in `helm repo add`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HelmRepository {
+    pub name: String,
+    pub url: String,
+}
+
+impl FromStr for HelmRepository {
+    type Err = Errors;
+
+    /// Takes `name=url`, or just a URL in which case the host is the name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, url) = match s.split_once('=') {
+            Some((name, url)) if !name.contains('/') => (Some(name.trim()), url.trim()),
+            _ => (None, s.trim()),
+        };
+        let parsed = reqwest::Url::parse(url)
+            .map_err(|err| Errors::Config(format!("Invalid Helm repository {}: {}", s, err)))?;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => parsed.host_str().unwrap_or(url).to_string(),
+        };
+        Ok(Self {
+            name,
+            url: url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Helm {
+    pub cache: Arc<RwLock<Cache>>,
+    pub repositories: Vec<HelmRepository>,
+}
+
+impl Helm {
+    pub fn with_repository(mut self, name: &str, url: &str) -> Self {
+        self.repositories.push(HelmRepository {
+            name: name.to_string(),
+            url: url.trim_end_matches('/').to_string(),
+        });
+        self
+    }
+
+    fn index_url(repository: &HelmRepository) -> String {
+        format!("{}/index.yaml", repository.url)
+    }
+
+    /// Download an `index.yaml` and store it in the cache
+    async fn fetch_index(&self, url: &str) -> Result<String, Errors> {
+        let content = WebClient::for_repo_type(RepoType::Helm)
+            .get_text(url)
+            .await?;
+        self.cache.read().await.save(
+            CacheData::new(url.to_string(), String::new(), content.clone())
+                .with_kind(RepoType::Helm, ResourceKind::Index),
+        )?;
+        Ok(content)
+    }
+
+    /// Get a repository's index, from the cache if we've got it
+    async fn load_index(&self, repository: &HelmRepository) -> Result<HelmIndex, Errors> {
+        let url = Self::index_url(repository);
+        let cached = self
+            .cache
+            .read()
+            .await
+            .get_fresh_with(RepoType::Helm, &url, |reader| {
+                Ok(serde_yaml::from_reader(reader)?)
+            });
+        match cached {
+            Some(index) => Ok(index),
+            None => HelmIndex::from_yaml(&self.fetch_index(&url).await?),
+        }
+    }
+
+    /// Splits `repo/chart` into the repositories to look at and the chart name
+    fn select<'a>(&self, name: &'a str) -> (Vec<&HelmRepository>, &'a str) {
+        if let Some((repo, chart)) = name.split_once('/') {
+            let matching: Vec<&HelmRepository> = self
+                .repositories
+                .iter()
+                .filter(|r| r.name == repo)
+                .collect();
+            if !matching.is_empty() {
+                return (matching, chart);
+            }
+        }
+        (self.repositories.iter().collect(), name)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HelmMaintainer {
+    pub name: String,
+    pub email: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HelmDependency {
+    pub name: String,
+    pub version: Option<String>,
+    pub repository: Option<String>,
+    pub condition: Option<String>,
+    pub alias: Option<String>,
+}
+
+/// One version of a chart from `index.yaml`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartVersion {
+    pub name: String,
+    pub version: String,
+    pub app_version: Option<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    pub home: Option<String>,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub maintainers: Vec<HelmMaintainer>,
+    #[serde(default)]
+    pub dependencies: Vec<HelmDependency>,
+    #[serde(default)]
+    pub deprecated: bool,
+    pub digest: Option<String>,
+    /// Can be relative to the repository URL
+    #[serde(default)]
+    pub urls: Vec<String>,
+    pub created: Option<DateTime<chrono::Utc>>,
+    /// `application` or `library`
+    #[serde(rename = "type")]
+    pub chart_type: Option<String>,
+    pub kube_version: Option<String>,
+}
+
+impl ChartVersion {
+    fn matches(&self, query: &str) -> bool {
+        self.name.to_lowercase().contains(query)
+            || self
+                .description
+                .as_ref()
+                .is_some_and(|desc| desc.to_lowercase().contains(query))
+            || self
+                .keywords
+                .iter()
+                .any(|keyword| keyword.to_lowercase().contains(query))
+    }
+
+    /// Turn it into a [Package], resolving relative download URLs against the repository
+    pub fn into_package(self, repository: &HelmRepository) -> Package {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        other_metadata.insert("version".to_string(), Value::String(self.version));
+        other_metadata.insert(
+            "helm_repository".to_string(),
+            Value::String(repository.name.clone()),
+        );
+        if let Some(app_version) = self.app_version {
+            other_metadata.insert("app_version".to_string(), Value::String(app_version));
+        }
+        if let Some(description) = self.description {
+            other_metadata.insert("description".to_string(), Value::String(description));
+        }
+        if !self.keywords.is_empty() {
+            other_metadata.insert("keywords".to_string(), Value::from(self.keywords));
+        }
+        if !self.sources.is_empty() {
+            other_metadata.insert("sources".to_string(), Value::from(self.sources));
+        }
+        if !self.maintainers.is_empty() {
+            other_metadata.insert(
+                "maintainers".to_string(),
+                serde_json::to_value(&self.maintainers).unwrap_or_default(),
+            );
+        }
+        if !self.dependencies.is_empty() {
+            other_metadata.insert(
+                "dependencies".to_string(),
+                serde_json::to_value(&self.dependencies).unwrap_or_default(),
+            );
+        }
+        if self.deprecated {
+            other_metadata.insert(
+                "deprecated".to_string(),
+                Value::String("deprecated".to_string()),
+            );
+        }
+        if let Some(digest) = self.digest {
+            other_metadata.insert("checksum".to_string(), Value::String(digest));
+        }
+        if let Some(url) = self.urls.first() {
+            let url = match url.contains("://") {
+                true => url.clone(),
+                false => format!("{}/{}", repository.url, url.trim_start_matches('/')),
+            };
+            other_metadata.insert("download_url".to_string(), Value::String(url));
+        }
+        if let Some(created) = self.created {
+            other_metadata.insert(
+                "release_date".to_string(),
+                Value::String(created.to_rfc3339()),
+            );
+        }
+        if let Some(chart_type) = self.chart_type {
+            other_metadata.insert("chart_type".to_string(), Value::String(chart_type));
+        }
+        if let Some(kube_version) = self.kube_version {
+            other_metadata.insert("kube_version".to_string(), Value::String(kube_version));
+        }
+
+        Package {
+            owner: self.maintainers.first().map(|m| m.name.clone()),
+            name: self.name,
+            url: self.home,
+            other_metadata,
+            repo_type: RepoType::Helm,
+        }
+    }
+}
+
+/// A chart repository's `index.yaml`
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HelmIndex {
+    pub api_version: Option<String>,
+    /// Chart versions by chart name
+    #[serde(default)]
+    pub entries: HashMap<String, Vec<ChartVersion>>,
+    pub generated: Option<String>,
+}
+
+impl HelmIndex {
+    pub fn from_yaml(content: &str) -> Result<Self, Errors> {
+        Ok(serde_yaml::from_str(content)?)
+    }
+
+    /// The versions of a chart, oldest first
+    pub fn versions(&self, chart: &str) -> Vec<ChartVersion> {
+        let mut versions = self.entries.get(chart).cloned().unwrap_or_default();
+        versions.sort_by(|a, b| compare_semver(&a.version, &b.version));
+        versions
+    }
+
+    /// The newest version of every chart
+    pub fn latest(&self) -> Vec<ChartVersion> {
+        let mut charts: Vec<ChartVersion> = self
+            .entries
+            .values()
+            .filter_map(|versions| {
+                versions
+                    .iter()
+                    .max_by(|a, b| compare_semver(&a.version, &b.version))
+                    .cloned()
+            })
+            .collect();
+        charts.sort_by(|a, b| a.name.cmp(&b.name));
+        charts
+    }
+}
+
+#[async_trait]
+impl Repository for Helm {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        let repositories = std::env::var(HELM_REPOSITORIES_ENV)
+            .unwrap_or_default()
+            .split(',')
+            .filter(|repo| !repo.trim().is_empty())
+            .filter_map(|repo| HelmRepository::from_str(repo).ok())
+            .collect();
+        Self {
+            cache,
+            repositories,
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::Helm
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            mirror: true,
+            dependencies: true,
+            downloads: true,
+            ..Capabilities::none(RepoType::Helm)
+        }
+    }
+
+    /// Searches the latest version of each chart's name, description and keywords
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let query = query.to_lowercase();
+        let mut packages = Vec::new();
+        for repository in self.repositories.iter() {
+            let index = self.load_index(repository).await?;
+            packages.extend(
+                index
+                    .latest()
+                    .into_iter()
+                    .filter(|chart| chart.matches(&query))
+                    .map(|chart| chart.into_package(repository)),
+            );
+        }
+        Ok(packages)
+    }
+
+    /// Takes `chart`, or `repo/chart` to only look in one repository
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let (repositories, chart) = self.select(name);
+        let mut packages = Vec::new();
+        for repository in repositories {
+            let index = self.load_index(repository).await?;
+            packages.extend(
+                index
+                    .versions(chart)
+                    .into_iter()
+                    .map(|version| version.into_package(repository)),
+            );
+        }
+        if packages.is_empty() {
+            return Err(Errors::NotFound {
+                repo_type: RepoType::Helm,
+                name: name.to_string(),
+            });
+        }
+        Ok(packages)
+    }
+
+    async fn cacheable(&self) -> bool {
+        true
+    }
+
+    async fn update_cache(&self, min_age: Option<u64>) -> Result<(), Errors> {
+        let _lock = self.cache.read().await.lock_ecosystem(RepoType::Helm)?;
+        for repository in self.repositories.iter() {
+            let url = Self::index_url(repository);
+            if let Some(min_age) = min_age {
+                let max_age = chrono::Duration::seconds(min_age as i64);
+                if self
+                    .cache
+                    .read()
+                    .await
+                    .is_fresh(RepoType::Helm, &url, max_age)
+                {
+                    continue;
+                }
+            }
+            self.fetch_index(&url).await?;
+        }
+        Ok(())
+    }
+}
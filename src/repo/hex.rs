@@ -0,0 +1,588 @@
+//! Repository hooks for hex.pm, the Elixir/Erlang package manager
+//!
+//! HTTP API reference - <https://github.com/hexpm/specifications/blob/main/apiary.apib>
+//!
+//! Registry resources reference - <https://github.com/hexpm/specifications/blob/main/registry-v2.md>
+
+use std::io::Read;
+use std::str::FromStr;
+
+use flate2::read::GzDecoder;
+
+use super::compare_semver;
+use super::prelude::*;
+
+const HEX_API_URL: &str = "https://hex.pm/api";
+const HEX_REPO_URL: &str = "https://repo.hex.pm";
+
+#[derive(Debug)]
+pub struct Hex {
+    pub cache: Arc<RwLock<Cache>>,
+    pub api_url: String,
+    pub repo_url: String,
+}
+
+impl Hex {
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_repo_url(mut self, repo_url: &str) -> Self {
+        self.repo_url = repo_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Fetch a registry resource and strip off the gzip and signature wrapping
+    ///
+    /// The signature is returned alongside the payload but isn't verified.
+    async fn fetch_registry_resource(&self, path: &str) -> Result<Signed, Errors> {
+        let body = WebClient::for_repo_type(RepoType::Hex)
+            .get_bytes(&format!("{}/{}", self.repo_url, path))
+            .await?;
+        Signed::from_bytes(&body)
+    }
+
+    /// Every package name in the registry, from `/names`
+    pub async fn registry_names(&self) -> Result<Vec<RegistryName>, Errors> {
+        let signed = self.fetch_registry_resource("names").await?;
+        decode_names(&signed.payload)
+    }
+
+    /// Every package and its versions in the registry, from `/versions`
+    pub async fn registry_versions(&self) -> Result<Vec<RegistryVersions>, Errors> {
+        let signed = self.fetch_registry_resource("versions").await?;
+        decode_versions(&signed.payload)
+    }
+
+    /// The releases of a single package, from `/packages/<name>`
+    pub async fn registry_package(&self, name: &str) -> Result<Vec<RegistryRelease>, Errors> {
+        let signed = self
+            .fetch_registry_resource(&format!("packages/{}", name))
+            .await?;
+        decode_package(&signed.payload)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct HexMeta {
+    pub description: Option<String>,
+    #[serde(default)]
+    pub licenses: Vec<String>,
+    #[serde(default)]
+    pub links: HashMap<String, String>,
+    #[serde(default)]
+    pub maintainers: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct HexRelease {
+    pub version: String,
+    pub url: Option<String>,
+    pub inserted_at: Option<DateTime<chrono::Utc>>,
+    pub has_docs: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct HexRetirement {
+    pub reason: String,
+    pub message: Option<String>,
+}
+
+impl std::fmt::Display for HexRetirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.reason)?;
+        if let Some(message) = self.message.as_ref() {
+            write!(f, ": {}", message)?
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct HexOwner {
+    pub username: String,
+    pub email: Option<String>,
+}
+
+/// A package as returned by `/api/packages` and `/api/packages/<name>`
+#[derive(Deserialize, Serialize, Debug)]
+pub struct HexPackage {
+    pub name: String,
+    pub html_url: Option<String>,
+    pub docs_html_url: Option<String>,
+    #[serde(default)]
+    pub meta: HexMeta,
+    #[serde(default)]
+    pub downloads: HashMap<String, u64>,
+    #[serde(default)]
+    pub releases: Vec<HexRelease>,
+    #[serde(default)]
+    pub retirements: HashMap<String, HexRetirement>,
+    pub inserted_at: Option<DateTime<chrono::Utc>>,
+    pub updated_at: Option<DateTime<chrono::Utc>>,
+    pub latest_version: Option<String>,
+    pub latest_stable_version: Option<String>,
+    pub owners: Option<Vec<HexOwner>>,
+}
+
+impl HexPackage {
+    fn package_metadata(&self) -> HashMap<String, Value> {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        if let Some(description) = self.meta.description.as_ref() {
+            other_metadata.insert(
+                "description".to_string(),
+                Value::String(description.clone()),
+            );
+        }
+        if !self.meta.licenses.is_empty() {
+            other_metadata.insert(
+                "license".to_string(),
+                Value::String(self.meta.licenses.join(" OR ")),
+            );
+        }
+        for (link_name, link_value) in self.meta.links.iter() {
+            other_metadata.insert(link_name.to_lowercase(), Value::String(link_value.clone()));
+        }
+        for (period, count) in self.downloads.iter() {
+            other_metadata.insert(format!("downloads_{}", period), Value::from(*count));
+        }
+        if let Some(docs) = self.docs_html_url.as_ref() {
+            other_metadata.insert("documentation".to_string(), Value::String(docs.clone()));
+        }
+        other_metadata
+    }
+
+    fn owner(&self) -> Option<String> {
+        match self.owners.as_ref().and_then(|owners| owners.first()) {
+            Some(owner) => Some(owner.username.clone()),
+            None => self.meta.maintainers.first().cloned(),
+        }
+    }
+
+    /// One [Package] per release, oldest first, with retired releases marked as yanked
+    pub fn into_packages(mut self) -> Vec<Package> {
+        let metadata = self.package_metadata();
+        let owner = self.owner();
+        let mut releases = std::mem::take(&mut self.releases);
+        releases.sort_by(|a, b| compare_semver(&a.version, &b.version));
+
+        releases
+            .into_iter()
+            .map(|release| {
+                let mut other_metadata = metadata.clone();
+                if let Some(retirement) = self.retirements.get(&release.version) {
+                    other_metadata.insert("yanked".to_string(), Value::Bool(true));
+                    other_metadata.insert(
+                        "deprecated".to_string(),
+                        Value::String(retirement.to_string()),
+                    );
+                }
+                if let Some(inserted_at) = release.inserted_at {
+                    other_metadata.insert(
+                        "release_date".to_string(),
+                        Value::String(inserted_at.to_rfc3339()),
+                    );
+                }
+                other_metadata.insert("version".to_string(), Value::String(release.version));
+                Package {
+                    name: self.name.clone(),
+                    url: self.html_url.clone(),
+                    owner: owner.clone(),
+                    other_metadata,
+                    repo_type: RepoType::Hex,
+                }
+            })
+            .collect()
+    }
+}
+
+impl From<HexPackage> for Package {
+    fn from(value: HexPackage) -> Self {
+        let mut other_metadata = value.package_metadata();
+        if let Some(version) = value.latest_stable_version.clone() {
+            other_metadata.insert("version".to_string(), Value::String(version));
+        } else if let Some(version) = value.latest_version.clone() {
+            other_metadata.insert("version".to_string(), Value::String(version));
+        }
+        if let Some(updated_at) = value.updated_at {
+            other_metadata.insert(
+                "updated_at".to_string(),
+                Value::String(updated_at.to_rfc3339()),
+            );
+        }
+        Package {
+            owner: value.owner(),
+            name: value.name,
+            url: value.html_url,
+            other_metadata,
+            repo_type: RepoType::Hex,
+        }
+    }
+}
+
+/// The outer wrapper of every registry resource
+#[derive(Debug, Default)]
+pub struct Signed {
+    pub payload: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl Signed {
+    /// Takes the (possibly gzipped) body of a registry resource
+    pub fn from_bytes(body: &[u8]) -> Result<Self, Errors> {
+        let mut decompressed = Vec::new();
+        let body = match body.starts_with(&[0x1f, 0x8b]) {
+            true => {
+                GzDecoder::new(body).read_to_end(&mut decompressed)?;
+                decompressed.as_slice()
+            }
+            false => body,
+        };
+
+        let mut signed = Signed::default();
+        for (field, value) in protobuf::fields(body)? {
+            match (field, value) {
+                (1, protobuf::FieldValue::Bytes(payload)) => signed.payload = payload.to_vec(),
+                (2, protobuf::FieldValue::Bytes(signature)) => {
+                    signed.signature = signature.to_vec()
+                }
+                _ => {}
+            }
+        }
+        Ok(signed)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct RegistryName {
+    pub name: String,
+    pub updated_at: Option<DateTime<chrono::Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct RegistryVersions {
+    pub name: String,
+    pub versions: Vec<String>,
+    /// Indexes into `versions` of the retired releases
+    pub retired: Vec<u64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct RegistryDependency {
+    pub package: String,
+    pub requirement: String,
+    pub optional: bool,
+    pub app: Option<String>,
+    pub repository: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct RegistryRelease {
+    pub version: String,
+    pub inner_checksum: String,
+    pub outer_checksum: Option<String>,
+    pub dependencies: Vec<RegistryDependency>,
+    /// The retirement reason and message, if the release is retired
+    pub retired: Option<HexRetirement>,
+}
+
+/// Decode the payload of `/names`
+pub fn decode_names(payload: &[u8]) -> Result<Vec<RegistryName>, Errors> {
+    let mut names = Vec::new();
+    for (field, value) in protobuf::fields(payload)? {
+        if let (1, protobuf::FieldValue::Bytes(package)) = (field, value) {
+            let mut name = RegistryName::default();
+            for (field, value) in protobuf::fields(package)? {
+                match (field, value) {
+                    (1, protobuf::FieldValue::Bytes(val)) => name.name = protobuf::string(val)?,
+                    (2, protobuf::FieldValue::Bytes(timestamp)) => {
+                        name.updated_at = protobuf::timestamp(timestamp)?
+                    }
+                    _ => {}
+                }
+            }
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Decode the payload of `/versions`
+pub fn decode_versions(payload: &[u8]) -> Result<Vec<RegistryVersions>, Errors> {
+    let mut packages = Vec::new();
+    for (field, value) in protobuf::fields(payload)? {
+        if let (1, protobuf::FieldValue::Bytes(package)) = (field, value) {
+            let mut versions = RegistryVersions::default();
+            for (field, value) in protobuf::fields(package)? {
+                match (field, value) {
+                    (1, protobuf::FieldValue::Bytes(val)) => versions.name = protobuf::string(val)?,
+                    (2, protobuf::FieldValue::Bytes(val)) => {
+                        versions.versions.push(protobuf::string(val)?)
+                    }
+                    (3, protobuf::FieldValue::Varint(index)) => versions.retired.push(index),
+                    // packed repeated field
+                    (3, protobuf::FieldValue::Bytes(mut packed)) => {
+                        while !packed.is_empty() {
+                            versions.retired.push(protobuf::varint(&mut packed)?);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            packages.push(versions);
+        }
+    }
+    Ok(packages)
+}
+
+/// Decode the payload of `/packages/<name>`
+pub fn decode_package(payload: &[u8]) -> Result<Vec<RegistryRelease>, Errors> {
+    let mut releases = Vec::new();
+    for (field, value) in protobuf::fields(payload)? {
+        let (1, protobuf::FieldValue::Bytes(release_bytes)) = (field, value) else {
+            continue;
+        };
+        let mut release = RegistryRelease::default();
+        for (field, value) in protobuf::fields(release_bytes)? {
+            match (field, value) {
+                (1, protobuf::FieldValue::Bytes(val)) => release.version = protobuf::string(val)?,
+                (2, protobuf::FieldValue::Bytes(val)) => release.inner_checksum = hex_string(val),
+                (3, protobuf::FieldValue::Bytes(dependency_bytes)) => {
+                    let mut dependency = RegistryDependency::default();
+                    for (field, value) in protobuf::fields(dependency_bytes)? {
+                        match (field, value) {
+                            (1, protobuf::FieldValue::Bytes(val)) => {
+                                dependency.package = protobuf::string(val)?
+                            }
+                            (2, protobuf::FieldValue::Bytes(val)) => {
+                                dependency.requirement = protobuf::string(val)?
+                            }
+                            (3, protobuf::FieldValue::Varint(val)) => {
+                                dependency.optional = val != 0
+                            }
+                            (4, protobuf::FieldValue::Bytes(val)) => {
+                                dependency.app = Some(protobuf::string(val)?)
+                            }
+                            (5, protobuf::FieldValue::Bytes(val)) => {
+                                dependency.repository = Some(protobuf::string(val)?)
+                            }
+                            _ => {}
+                        }
+                    }
+                    release.dependencies.push(dependency);
+                }
+                (4, protobuf::FieldValue::Bytes(retirement_bytes)) => {
+                    let mut retirement = HexRetirement {
+                        reason: "other".to_string(),
+                        message: None,
+                    };
+                    for (field, value) in protobuf::fields(retirement_bytes)? {
+                        match (field, value) {
+                            (1, protobuf::FieldValue::Varint(reason)) => {
+                                retirement.reason = retirement_reason(reason).to_string()
+                            }
+                            (2, protobuf::FieldValue::Bytes(val)) => {
+                                retirement.message = Some(protobuf::string(val)?)
+                            }
+                            _ => {}
+                        }
+                    }
+                    release.retired = Some(retirement);
+                }
+                (5, protobuf::FieldValue::Bytes(val)) => {
+                    release.outer_checksum = Some(hex_string(val))
+                }
+                _ => {}
+            }
+        }
+        releases.push(release);
+    }
+    Ok(releases)
+}
+
+/// The `RetirementReason` enum from the registry spec, matching the names the HTTP API uses
+fn retirement_reason(reason: u64) -> &'static str {
+    match reason {
+        1 => "invalid",
+        2 => "security",
+        3 => "deprecated",
+        4 => "renamed",
+        _ => "other",
+    }
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Just enough protobuf decoding for the registry resources
+mod protobuf {
+    use chrono::DateTime;
+
+    use crate::Errors;
+
+    pub(super) enum FieldValue<'a> {
+        Varint(u64),
+        Bytes(&'a [u8]),
+    }
+
+    fn truncated() -> Errors {
+        Errors::parse(None, None, "Truncated protobuf message")
+    }
+
+    pub(super) fn varint(buf: &mut &[u8]) -> Result<u64, Errors> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = buf.split_first().ok_or_else(truncated)?;
+            *buf = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Errors::parse(None, None, "Invalid protobuf varint"))
+    }
+
+    fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], Errors> {
+        if buf.len() < len {
+            return Err(truncated());
+        }
+        let (head, rest) = buf.split_at(len);
+        *buf = rest;
+        Ok(head)
+    }
+
+    /// Split a message into its field numbers and values, skipping fixed width fields
+    pub(super) fn fields(mut buf: &[u8]) -> Result<Vec<(u64, FieldValue<'_>)>, Errors> {
+        let mut fields = Vec::new();
+        while !buf.is_empty() {
+            let key = varint(&mut buf)?;
+            let field = key >> 3;
+            match key & 0x7 {
+                0 => fields.push((field, FieldValue::Varint(varint(&mut buf)?))),
+                1 => {
+                    take(&mut buf, 8)?;
+                }
+                2 => {
+                    let len = varint(&mut buf)? as usize;
+                    fields.push((field, FieldValue::Bytes(take(&mut buf, len)?)));
+                }
+                5 => {
+                    take(&mut buf, 4)?;
+                }
+                wire_type => {
+                    return Err(Errors::parse(
+                        None,
+                        None,
+                        format!("Unsupported protobuf wire type {}", wire_type),
+                    ))
+                }
+            }
+        }
+        Ok(fields)
+    }
+
+    pub(super) fn string(bytes: &[u8]) -> Result<String, Errors> {
+        String::from_utf8(bytes.to_vec()).map_err(|err| Errors::Parse {
+            url: None,
+            offset: Some(err.utf8_error().valid_up_to()),
+            source: Box::new(err),
+        })
+    }
+
+    /// A `google.protobuf.Timestamp`
+    pub(super) fn timestamp(bytes: &[u8]) -> Result<Option<DateTime<chrono::Utc>>, Errors> {
+        let mut seconds = 0;
+        let mut nanos = 0;
+        for (field, value) in fields(bytes)? {
+            match (field, value) {
+                (1, FieldValue::Varint(val)) => seconds = val as i64,
+                (2, FieldValue::Varint(val)) => nanos = val as u32,
+                _ => {}
+            }
+        }
+        Ok(DateTime::from_timestamp(seconds, nanos))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct RegistryMirror {
+    names: Vec<RegistryName>,
+    versions: Vec<RegistryVersions>,
+}
+
+#[async_trait]
+impl Repository for Hex {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            api_url: HEX_API_URL.to_string(),
+            repo_url: HEX_REPO_URL.to_string(),
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::Hex
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            mirror: true,
+            ..Capabilities::none(RepoType::Hex)
+        }
+    }
+
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let mut url = reqwest::Url::from_str(&format!("{}/packages", self.api_url))?;
+        url.query_pairs_mut().append_pair("search", query);
+
+        let packages: Vec<HexPackage> = WebClient::for_repo_type(RepoType::Hex)
+            .get_json(url.as_ref())
+            .await?;
+        Ok(packages.into_iter().map(|package| package.into()).collect())
+    }
+
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let url = format!("{}/packages/{}", self.api_url, name);
+        let package: HexPackage = WebClient::for_repo_type(RepoType::Hex)
+            .get_json(&url)
+            .await
+            .map_err(|err| err.or_not_found(RepoType::Hex, name))?;
+        Ok(package.into_packages())
+    }
+
+    async fn cacheable(&self) -> bool {
+        true
+    }
+
+    /// Mirrors `/names` and `/versions` into the cache
+    async fn update_cache(&self, min_age: Option<u64>) -> Result<(), Errors> {
+        let _lock = self.cache.read().await.lock_ecosystem(RepoType::Hex)?;
+        let url = format!("{}/versions", self.repo_url);
+        if let Some(min_age) = min_age {
+            let max_age = chrono::Duration::seconds(min_age as i64);
+            if self
+                .cache
+                .read()
+                .await
+                .is_fresh(RepoType::Hex, &url, max_age)
+            {
+                return Ok(());
+            }
+        }
+
+        let mirror = RegistryMirror {
+            names: self.registry_names().await?,
+            versions: self.registry_versions().await?,
+        };
+        self.cache.read().await.save(
+            CacheData::new(url, String::new(), serde_json::to_string(&mirror)?)
+                .with_kind(RepoType::Hex, ResourceKind::Index),
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+//! Repository hooks for Homebrew formulae and casks
+//!
+//! Uses the bulk API files, reference - <https://formulae.brew.sh/docs/api/>
+
+use super::prelude::*;
+use crate::request::parse_json;
+use serde::de::DeserializeOwned;
+
+const HOMEBREW_API_URL: &str = "https://formulae.brew.sh/api";
+
+#[derive(Debug)]
+pub struct Homebrew {
+    pub cache: Arc<RwLock<Cache>>,
+    pub api_url: String,
+}
+
+impl Homebrew {
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    fn formula_url(&self) -> String {
+        format!("{}/formula.jws.json", self.api_url)
+    }
+
+    fn cask_url(&self) -> String {
+        format!("{}/cask.jws.json", self.api_url)
+    }
+
+    /// Get a bulk file, parsed, from the cache if we've got it
+    async fn load_bulk<T: DeserializeOwned>(&self, url: &str) -> Result<T, Errors> {
+        if let Some(bulk) = self
+            .cache
+            .read()
+            .await
+            .get_fresh_json(RepoType::Homebrew, url)
+        {
+            return Ok(bulk);
+        }
+        parse_json(url, &self.fetch_bulk(url).await?)
+    }
+
+    /// Download a bulk file and store the unwrapped JSON in the cache
+    async fn fetch_bulk(&self, url: &str) -> Result<String, Errors> {
+        let content = unwrap_jws(
+            url,
+            &WebClient::for_repo_type(RepoType::Homebrew)
+                .get_text(url)
+                .await?,
+        )?;
+        self.cache.read().await.save_reader(
+            url,
+            RepoType::Homebrew,
+            ResourceKind::Index,
+            content.as_bytes(),
+        )?;
+        Ok(content)
+    }
+
+    async fn formulae(&self) -> Result<Vec<Formula>, Errors> {
+        self.load_bulk(&self.formula_url()).await
+    }
+
+    async fn casks(&self) -> Result<Vec<Cask>, Errors> {
+        self.load_bulk(&self.cask_url()).await
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct JwsDocument {
+    payload: String,
+}
+
+/// The `.jws.json` files wrap the plain JSON as a string in `payload`, the plain endpoints don't
+pub fn unwrap_jws(url: &str, content: &str) -> Result<String, Errors> {
+    if !content.trim_start().starts_with('{') {
+        return Ok(content.to_string());
+    }
+    let document: JwsDocument = parse_json(url, content)?;
+    Ok(document.payload)
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct FormulaVersions {
+    pub stable: Option<String>,
+    pub head: Option<String>,
+    #[serde(default)]
+    pub bottle: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BottleFile {
+    pub cellar: Option<String>,
+    pub url: String,
+    pub sha256: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BottleSpec {
+    pub rebuild: Option<u64>,
+    pub root_url: Option<String>,
+    /// Keyed by platform, eg `arm64_sonoma` or `x86_64_linux`
+    #[serde(default)]
+    pub files: HashMap<String, BottleFile>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Formula {
+    pub name: String,
+    pub full_name: Option<String>,
+    pub tap: Option<String>,
+    #[serde(default)]
+    pub oldnames: Vec<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub desc: Option<String>,
+    pub license: Option<String>,
+    pub homepage: Option<String>,
+    #[serde(default)]
+    pub versions: FormulaVersions,
+    #[serde(default)]
+    pub revision: u64,
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    #[serde(default)]
+    pub build_dependencies: Vec<String>,
+    #[serde(default)]
+    pub bottle: HashMap<String, BottleSpec>,
+    #[serde(default)]
+    pub deprecated: bool,
+    pub deprecation_date: Option<String>,
+    pub deprecation_reason: Option<String>,
+    #[serde(default)]
+    pub disabled: bool,
+    pub disable_date: Option<String>,
+    pub disable_reason: Option<String>,
+}
+
+impl Formula {
+    fn matches(&self, query: &str) -> bool {
+        self.name.to_lowercase().contains(query)
+            || self.aliases.iter().any(|alias| alias.contains(query))
+            || self
+                .desc
+                .as_ref()
+                .is_some_and(|desc| desc.to_lowercase().contains(query))
+    }
+
+    /// The stable version with the revision on the end, the way `brew info` shows it
+    pub fn version(&self) -> Option<String> {
+        let stable = self.versions.stable.clone()?;
+        Some(match self.revision {
+            0 => stable,
+            revision => format!("{}_{}", stable, revision),
+        })
+    }
+}
+
+/// Shared deprecation/disable handling between formulae and casks
+fn insert_lifecycle(
+    other_metadata: &mut HashMap<String, Value>,
+    deprecated: bool,
+    deprecation_date: Option<String>,
+    deprecation_reason: Option<String>,
+    disabled: bool,
+    disable_date: Option<String>,
+    disable_reason: Option<String>,
+) {
+    if deprecated {
+        other_metadata.insert(
+            "deprecated".to_string(),
+            Value::String(deprecation_reason.unwrap_or_else(|| "deprecated".to_string())),
+        );
+    }
+    if let Some(date) = deprecation_date {
+        other_metadata.insert("deprecation_date".to_string(), Value::String(date));
+    }
+    if disabled {
+        other_metadata.insert(
+            "disabled".to_string(),
+            Value::String(disable_reason.unwrap_or_else(|| "disabled".to_string())),
+        );
+    }
+    if let Some(date) = disable_date {
+        other_metadata.insert("disable_date".to_string(), Value::String(date));
+    }
+}
+
+impl From<Formula> for Package {
+    fn from(value: Formula) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        other_metadata.insert("kind".to_string(), Value::String("formula".to_string()));
+        if let Some(version) = value.version() {
+            other_metadata.insert("version".to_string(), Value::String(version));
+        }
+        if let Some(head) = value.versions.head.clone() {
+            other_metadata.insert("head".to_string(), Value::String(head));
+        }
+        if let Some(desc) = value.desc {
+            other_metadata.insert("description".to_string(), Value::String(desc));
+        }
+        if let Some(license) = value.license {
+            other_metadata.insert("license".to_string(), Value::String(license));
+        }
+        if let Some(tap) = value.tap {
+            other_metadata.insert("tap".to_string(), Value::String(tap));
+        }
+        if !value.aliases.is_empty() {
+            other_metadata.insert("aliases".to_string(), Value::from(value.aliases));
+        }
+        if !value.dependencies.is_empty() {
+            other_metadata.insert("dependencies".to_string(), Value::from(value.dependencies));
+        }
+        if !value.build_dependencies.is_empty() {
+            other_metadata.insert(
+                "build_dependencies".to_string(),
+                Value::from(value.build_dependencies),
+            );
+        }
+        if let Some(stable) = value.bottle.get("stable") {
+            let bottles: serde_json::Map<String, Value> = stable
+                .files
+                .iter()
+                .map(|(platform, file)| {
+                    (
+                        platform.clone(),
+                        serde_json::json!({"url": file.url, "sha256": file.sha256}),
+                    )
+                })
+                .collect();
+            other_metadata.insert("bottles".to_string(), Value::Object(bottles));
+        }
+        insert_lifecycle(
+            &mut other_metadata,
+            value.deprecated,
+            value.deprecation_date,
+            value.deprecation_reason,
+            value.disabled,
+            value.disable_date,
+            value.disable_reason,
+        );
+
+        Package {
+            name: value.name,
+            url: value.homepage,
+            owner: None,
+            other_metadata,
+            repo_type: RepoType::Homebrew,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Cask {
+    pub token: String,
+    pub full_token: Option<String>,
+    pub tap: Option<String>,
+    #[serde(default)]
+    pub name: Vec<String>,
+    pub desc: Option<String>,
+    pub homepage: Option<String>,
+    pub url: Option<String>,
+    pub version: Option<String>,
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub old_tokens: Vec<String>,
+    pub depends_on: Option<Value>,
+    #[serde(default)]
+    pub deprecated: bool,
+    pub deprecation_date: Option<String>,
+    pub deprecation_reason: Option<String>,
+    #[serde(default)]
+    pub disabled: bool,
+    pub disable_date: Option<String>,
+    pub disable_reason: Option<String>,
+}
+
+impl Cask {
+    fn matches(&self, query: &str) -> bool {
+        self.token.contains(query)
+            || self
+                .name
+                .iter()
+                .any(|name| name.to_lowercase().contains(query))
+            || self
+                .desc
+                .as_ref()
+                .is_some_and(|desc| desc.to_lowercase().contains(query))
+    }
+}
+
+impl From<Cask> for Package {
+    fn from(value: Cask) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        other_metadata.insert("kind".to_string(), Value::String("cask".to_string()));
+        if let Some(version) = value.version {
+            other_metadata.insert("version".to_string(), Value::String(version));
+        }
+        if let Some(desc) = value.desc {
+            other_metadata.insert("description".to_string(), Value::String(desc));
+        }
+        if !value.name.is_empty() {
+            other_metadata.insert("names".to_string(), Value::from(value.name));
+        }
+        if let Some(tap) = value.tap {
+            other_metadata.insert("tap".to_string(), Value::String(tap));
+        }
+        if let Some(url) = value.url {
+            other_metadata.insert("download_url".to_string(), Value::String(url));
+        }
+        // "no_check" casks don't pin a checksum
+        if let Some(sha256) = value.sha256.filter(|sha| sha != "no_check") {
+            other_metadata.insert("checksum".to_string(), Value::String(sha256));
+        }
+        if let Some(depends_on) = value.depends_on.filter(|d| !d.is_null()) {
+            other_metadata.insert("dependencies".to_string(), depends_on);
+        }
+        insert_lifecycle(
+            &mut other_metadata,
+            value.deprecated,
+            value.deprecation_date,
+            value.deprecation_reason,
+            value.disabled,
+            value.disable_date,
+            value.disable_reason,
+        );
+
+        Package {
+            name: value.token,
+            url: value.homepage,
+            owner: None,
+            other_metadata,
+            repo_type: RepoType::Homebrew,
+        }
+    }
+}
+
+#[async_trait]
+impl Repository for Homebrew {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            api_url: HOMEBREW_API_URL.to_string(),
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::Homebrew
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            mirror: true,
+            dependencies: true,
+            downloads: true,
+            ..Capabilities::none(RepoType::Homebrew)
+        }
+    }
+
+    /// Searches formula names, aliases and descriptions, then the same for casks
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let query = query.to_lowercase();
+        let mut packages: Vec<Package> = self
+            .formulae()
+            .await?
+            .into_iter()
+            .filter(|formula| formula.matches(&query))
+            .map(|formula| formula.into())
+            .collect();
+        packages.extend(
+            self.casks()
+                .await?
+                .into_iter()
+                .filter(|cask| cask.matches(&query))
+                .map(|cask| -> Package { cask.into() }),
+        );
+        Ok(packages)
+    }
+
+    /// Looks up a formula by name or alias, falling back to a cask token
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        if let Some(formula) = self.formulae().await?.into_iter().find(|formula| {
+            formula.name == name
+                || formula.full_name.as_deref() == Some(name)
+                || formula.aliases.iter().any(|alias| alias == name)
+                || formula.oldnames.iter().any(|old| old == name)
+        }) {
+            return Ok(vec![formula.into()]);
+        }
+        self.casks()
+            .await?
+            .into_iter()
+            .find(|cask| {
+                cask.token == name
+                    || cask.full_token.as_deref() == Some(name)
+                    || cask.old_tokens.iter().any(|old| old == name)
+            })
+            .map(|cask| vec![cask.into()])
+            .ok_or_else(|| Errors::NotFound {
+                repo_type: RepoType::Homebrew,
+                name: name.to_string(),
+            })
+    }
+
+    async fn cacheable(&self) -> bool {
+        true
+    }
+
+    async fn update_cache(&self, min_age: Option<u64>) -> Result<(), Errors> {
+        let _lock = self.cache.read().await.lock_ecosystem(RepoType::Homebrew)?;
+        for url in [self.formula_url(), self.cask_url()] {
+            if let Some(min_age) = min_age {
+                let max_age = chrono::Duration::seconds(min_age as i64);
+                if self
+                    .cache
+                    .read()
+                    .await
+                    .is_fresh(RepoType::Homebrew, &url, max_age)
+                {
+                    continue;
+                }
+            }
+            self.fetch_bulk(&url).await?;
+        }
+        Ok(())
+    }
+}
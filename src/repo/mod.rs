@@ -1,19 +1,59 @@
-use crate::{get_cache_dir, Errors, RepoType};
+use std::cmp::Ordering;
+use std::str::FromStr;
 
+use crate::{Errors, RepoType};
+
+pub mod alpine;
+pub mod cargo_config;
+pub mod conda;
+pub mod cpan;
 pub mod crates;
+pub mod github;
+pub mod hackage;
+pub mod helm;
+pub mod hex;
+pub mod homebrew;
 pub mod npm;
+pub mod npmrc;
+pub mod oci;
+pub mod packagist;
+pub mod pip_config;
 pub(crate) mod prelude;
+pub mod pubdev;
 pub mod pypi;
+pub mod terraform;
 
 use prelude::*;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PackageVersion {
-    name: String,
-    url: String,
-    version: String,
-    owner: Option<String>,
-    release_date: DateTime<chrono::Utc>,
+    pub name: String,
+    pub url: Option<String>,
+    pub version: String,
+    pub owner: Option<String>,
+    /// Not every registry tells us when a version was published
+    pub release_date: Option<DateTime<chrono::Utc>>,
+}
+
+impl PackageVersion {
+    /// Turn it into a [Package], with the version and release date in `other_metadata`
+    pub fn into_package(self, repo_type: RepoType) -> Package {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        other_metadata.insert("version".to_string(), Value::String(self.version));
+        if let Some(release_date) = self.release_date {
+            other_metadata.insert(
+                "release_date".to_string(),
+                Value::String(release_date.to_rfc3339()),
+            );
+        }
+        Package {
+            name: self.name,
+            url: self.url,
+            owner: self.owner,
+            other_metadata,
+            repo_type,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -27,22 +67,86 @@ pub struct Package {
     pub repo_type: RepoType,
 }
 
+/// What a repository can do, so callers can skip the ones that can't do what they're asked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    pub repo_type: RepoType,
+    pub search: bool,
+    /// [Repository::get_package] works
+    pub package_detail: bool,
+    /// [Repository::get_package] returns every version, not just the current one
+    pub versions: bool,
+    /// [Repository::update_cache] mirrors the index locally
+    pub mirror: bool,
+    /// Packages list their `dependencies`
+    pub dependencies: bool,
+    /// Packages have a `download_url` or `assets`
+    pub downloads: bool,
+}
+
+impl Capabilities {
+    /// Nothing at all, turn on what the repository supports
+    pub fn none(repo_type: RepoType) -> Self {
+        Self {
+            repo_type,
+            search: false,
+            package_detail: false,
+            versions: false,
+            mirror: false,
+            dependencies: false,
+            downloads: false,
+        }
+    }
+
+    /// The error to give back for something it can't do
+    pub fn unsupported(&self, operation: &str) -> Errors {
+        Errors::Unsupported {
+            repo_type: self.repo_type,
+            operation: operation.to_string(),
+        }
+    }
+}
+
+/// Order versions by what `parse` makes of them, with the ones it can't parse before all the
+/// ones it can and in string order among themselves
+///
+/// Falling back to comparing strings only when one side doesn't parse isn't a consistent order,
+/// and sorting with one can panic.
+pub fn compare_versions_by<T: Ord>(
+    left: &str,
+    right: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Ordering {
+    match (parse(left), parse(right)) {
+        (Some(left), Some(right)) => left.cmp(&right),
+        (None, Some(_)) => Ordering::Less,
+        (Some(_), None) => Ordering::Greater,
+        (None, None) => left.cmp(right),
+    }
+}
+
+/// Semver, with or without a `v` on the front
+pub fn compare_semver(left: &str, right: &str) -> Ordering {
+    compare_versions_by(left, right, |version| {
+        semver::Version::from_str(version.strip_prefix('v').unwrap_or(version)).ok()
+    })
+}
+
 #[async_trait]
 pub trait Repository {
-    fn new(cache: Arc<RwLock<Cache>>) -> Self;
-    fn repo_type() -> RepoType;
+    fn new(cache: Arc<RwLock<Cache>>) -> Self
+    where
+        Self: Sized;
+    fn repo_type() -> RepoType
+    where
+        Self: Sized;
+    fn capabilities(&self) -> Capabilities;
     async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors>;
     async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors>;
     async fn cacheable(&self) -> bool;
     async fn update_cache(&self, min_age: Option<u64>) -> Result<(), Errors>;
-    fn get_cache_dir(&self) -> String;
-
-    fn make_cache_dir(&self) -> Result<(), Errors> {
-        let path = get_cache_dir().join(self.get_cache_dir());
-        if path.exists() {
-            Ok(())
-        } else {
-            std::fs::create_dir_all(path).map_err(|err| err.into())
-        }
+    /// Relative to the cache dir, where the [Cache] keeps this ecosystem's entries too
+    fn get_cache_dir(&self) -> String {
+        format!("{}/", self.capabilities().repo_type.cache_dir())
     }
 }
@@ -1,11 +1,52 @@
 use std::str::FromStr;
 
+use reqwest::header::{ACCEPT, AUTHORIZATION};
+
+use super::compare_semver;
+use super::npmrc::Npmrc;
 use super::prelude::*;
+use crate::request::read_json;
 
 #[derive(Debug)]
 pub struct Npm {
     #[allow(dead_code)]
     cache: Arc<RwLock<Cache>>,
+    pub npmrc: Npmrc,
+}
+
+impl Npm {
+    pub fn with_npmrc(mut self, npmrc: Npmrc) -> Self {
+        self.npmrc = npmrc;
+        self
+    }
+
+    /// Uses the registries and credentials from the project, user and global `.npmrc` files
+    pub fn from_config(cache: Arc<RwLock<Cache>>) -> Result<Self, Errors> {
+        Ok(Self::new(cache).with_npmrc(Npmrc::load()?))
+    }
+
+    /// GET something from a registry, with its credentials if we've got any
+    async fn get(&self, registry: &str, url: &str) -> Result<reqwest::Response, Errors> {
+        let client = WebClient::for_repo_type(RepoType::Npm);
+        let mut req = client.client.get(url).header(ACCEPT, "application/json");
+        if let Some(header) = self.npmrc.auth_for(registry).and_then(|auth| auth.header()) {
+            req = req.header(AUTHORIZATION, header);
+        }
+        client.send(req).await
+    }
+
+    /// Search a registry other than the public one through the registry API
+    async fn search_registry(&self, registry: &str, query: &str) -> Result<Vec<Package>, Errors> {
+        let mut url = reqwest::Url::from_str(&format!("{}/-/v1/search", registry))?;
+        url.query_pairs_mut().append_pair("text", query);
+        let data: NpmRegistrySearchResponse =
+            read_json(self.get(registry, url.as_str()).await?.check_status()?).await?;
+        Ok(data
+            .objects
+            .into_iter()
+            .map(|obj| obj.package.into())
+            .collect())
+    }
 }
 
 // {
@@ -118,10 +159,13 @@ pub struct NpmSearchScoreDetail {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NpmPackage {
     name: String,
+    #[serde(default)]
     scope: String,
     version: String,
+    #[serde(default)]
     description: String,
-    author: NpmPerson,
+    author: Option<NpmPerson>,
+    #[serde(default)]
     links: HashMap<String, String>,
 
     #[serde(alias = "searchScore")]
@@ -177,6 +221,121 @@ pub struct NpmSearchResponse {
     pub flags: Option<HashMap<String, Value>>,
 }
 
+/// What `/-/v1/search` returns from a registry
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NpmRegistrySearchResponse {
+    pub objects: Vec<NpmPackageObject>,
+    #[serde(default)]
+    pub total: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NpmDist {
+    pub tarball: String,
+    pub shasum: Option<String>,
+    /// Subresource integrity, eg `sha512-...`
+    pub integrity: Option<String>,
+}
+
+/// One version from a packument
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NpmVersion {
+    pub version: String,
+    pub description: Option<String>,
+    /// Usually an SPDX string, but old packages have objects
+    pub license: Option<Value>,
+    #[serde(default)]
+    pub dependencies: HashMap<String, String>,
+    pub deprecated: Option<String>,
+    pub dist: Option<NpmDist>,
+    pub homepage: Option<String>,
+    pub repository: Option<Value>,
+    #[serde(rename = "_npmUser")]
+    pub npm_user: Option<NpmPerson>,
+}
+
+/// Everything the registry knows about a package
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NpmPackument {
+    pub name: String,
+    #[serde(rename = "dist-tags", default)]
+    pub dist_tags: HashMap<String, String>,
+    #[serde(default)]
+    pub versions: HashMap<String, NpmVersion>,
+    /// Publish times by version, plus `created` and `modified`
+    #[serde(default)]
+    pub time: HashMap<String, String>,
+}
+
+impl NpmPackument {
+    /// One [Package] per version, oldest first
+    pub fn into_packages(self) -> Vec<Package> {
+        let mut versions: Vec<NpmVersion> = self.versions.into_values().collect();
+        versions.sort_by(|a, b| compare_semver(&a.version, &b.version));
+
+        versions
+            .into_iter()
+            .map(|version| {
+                let mut other_metadata: HashMap<String, Value> = HashMap::new();
+                let mut tags: Vec<String> = self
+                    .dist_tags
+                    .iter()
+                    .filter(|(_, tagged)| **tagged == version.version)
+                    .map(|(tag, _)| tag.clone())
+                    .collect();
+                tags.sort();
+                if !tags.is_empty() {
+                    other_metadata.insert("tags".to_string(), Value::from(tags));
+                }
+                if let Some(time) = self.time.get(&version.version) {
+                    other_metadata.insert("release_date".to_string(), Value::String(time.clone()));
+                }
+                if let Some(description) = version.description {
+                    other_metadata.insert("description".to_string(), Value::String(description));
+                }
+                match version.license {
+                    Some(Value::Object(license)) => {
+                        if let Some(license) = license.get("type") {
+                            other_metadata.insert("license".to_string(), license.clone());
+                        }
+                    }
+                    Some(license) => {
+                        other_metadata.insert("license".to_string(), license);
+                    }
+                    None => {}
+                }
+                if !version.dependencies.is_empty() {
+                    other_metadata.insert(
+                        "dependencies".to_string(),
+                        serde_json::to_value(&version.dependencies).unwrap_or_default(),
+                    );
+                }
+                if let Some(deprecated) = version.deprecated {
+                    other_metadata.insert("deprecated".to_string(), Value::String(deprecated));
+                }
+                if let Some(dist) = version.dist {
+                    other_metadata.insert("download_url".to_string(), Value::String(dist.tarball));
+                    if let Some(checksum) = dist.integrity.or(dist.shasum) {
+                        other_metadata.insert("checksum".to_string(), Value::String(checksum));
+                    }
+                }
+                if let Some(repository) = version.repository {
+                    other_metadata.insert("repository".to_string(), repository);
+                }
+                other_metadata.insert("version".to_string(), Value::String(version.version));
+
+                Package {
+                    name: self.name.clone(),
+                    url: version.homepage,
+                    owner: version.npm_user.map(|user| user.to_string()),
+                    other_metadata,
+                    repo_type: RepoType::Npm,
+                }
+            })
+            .collect()
+    }
+}
+
 impl From<NpmPackage> for Package {
     fn from(value: NpmPackage) -> Self {
         let mut other_metadata: HashMap<String, Value> = HashMap::new();
@@ -185,7 +344,7 @@ impl From<NpmPackage> for Package {
         Package {
             name: value.name.clone(),
             url: None,
-            owner: Some(value.author.to_string()),
+            owner: value.author.map(|author| author.to_string()),
             other_metadata,
             repo_type: RepoType::Npm,
         }
@@ -197,19 +356,33 @@ impl Repository for Npm {
     fn repo_type() -> RepoType {
         RepoType::Npm
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            dependencies: true,
+            downloads: true,
+            ..Capabilities::none(RepoType::Npm)
+        }
+    }
     fn new(cache: Arc<RwLock<Cache>>) -> Self {
-        Self { cache }
+        Self {
+            cache,
+            npmrc: Npmrc::default(),
+        }
     }
     async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
-        let mut url = reqwest::Url::from_str("https://www.npmjs.com/search")
-            .map_err(|err| Errors::Generic(format!("Error parsing url: {:?}", err)))?;
+        if let Some(registry) = self.npmrc.registry.as_ref() {
+            return self.search_registry(registry, query).await;
+        }
+        let mut url = reqwest::Url::from_str("https://www.npmjs.com/search")?;
         url.query_pairs_mut().append_pair("q", query);
-        let client = WebClient::default();
-
-        let res = client.client.get(url).send().await?;
-
-        let body = res.text().await?;
-        let data: NpmSearchResponse = serde_json::from_str(&body)?;
+        let body = WebClient::for_repo_type(RepoType::Npm)
+            .get_text(url.as_str())
+            .await?;
+        let data: NpmSearchResponse = crate::request::parse_json(url.as_str(), &body)?;
 
         let mut packages = Vec::new();
         data.objects.into_iter().for_each(|obj| {
@@ -219,16 +392,23 @@ impl Repository for Npm {
 
         Ok(packages)
     }
-    async fn get_package(&mut self, _name: &str) -> Result<Vec<Package>, Errors> {
-        todo!()
+    /// Goes to the registry for the package's scope, `@company/thing` can live somewhere private
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let registry = self.npmrc.registry_for(name);
+        // scoped names keep their @ but the slash is encoded
+        let url = format!("{}/{}", registry, name.replace('/', "%2f"));
+        let res = self
+            .get(&registry, &url)
+            .await?
+            .check_status()
+            .map_err(|err| err.or_not_found(RepoType::Npm, name))?;
+        let packument: NpmPackument = read_json(res).await?;
+        Ok(packument.into_packages())
     }
     async fn cacheable(&self) -> bool {
         false
     }
     async fn update_cache(&self, _min_age: Option<u64>) -> Result<(), Errors> {
-        todo!()
-    }
-    fn get_cache_dir(&self) -> String {
-        todo!()
+        Err(self.capabilities().unsupported("mirroring"))
     }
 }
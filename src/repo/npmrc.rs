@@ -0,0 +1,222 @@
+//! Finding npm registries and their credentials from `.npmrc` files
+//!
+//! Reference - <https://docs.npmjs.com/cli/v10/configuring-npm/npmrc>
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use base64::Engine;
+
+use super::prelude::*;
+
+pub const NPM_REGISTRY_URL: &str = "https://registry.npmjs.org";
+
+/// Credentials for one registry, keyed in `.npmrc` by its "nerf dart" - `//host/path/:_authToken=...`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NpmAuth {
+    pub token: Option<String>,
+    /// Already base64'd `user:password`
+    pub auth: Option<String>,
+    pub username: Option<String>,
+    /// base64'd, as npm stores it
+    pub password: Option<String>,
+}
+
+impl NpmAuth {
+    /// The `Authorization` header to send, if there's enough here to make one
+    pub fn header(&self) -> Option<String> {
+        if let Some(token) = self.token.as_ref() {
+            return Some(format!("Bearer {}", token));
+        }
+        if let Some(auth) = self.auth.as_ref() {
+            return Some(format!("Basic {}", auth));
+        }
+        let username = self.username.as_ref()?;
+        let password = base64::engine::general_purpose::STANDARD
+            .decode(self.password.as_ref()?)
+            .ok()?;
+        let credentials = format!("{}:{}", username, String::from_utf8_lossy(&password));
+        Some(format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        ))
+    }
+}
+
+/// The "nerf dart" npm keys credentials by, `https://host:8080/path` becomes `//host:8080/path/`
+pub fn nerf_dart(url: &str) -> String {
+    let without_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    format!("//{}/", without_scheme.trim_end_matches('/'))
+}
+
+/// Replace `${VAR}` with environment variables, `${VAR?}` is blank rather than left alone if it's unset
+pub fn interpolate_env(value: &str, env: &impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        // a backslash escapes the whole thing
+        if rest[..start].ends_with('\\') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str(&rest[start..start + end + 1]);
+            rest = &rest[start + end + 1..];
+            continue;
+        }
+        result.push_str(&rest[..start]);
+        let name = &rest[start + 2..start + end];
+        match (name.strip_suffix('?'), env(name.trim_end_matches('?'))) {
+            (_, Some(value)) => result.push_str(&value),
+            (Some(_), None) => {}
+            (None, None) => result.push_str(&rest[start..start + end + 1]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// The registry settings from any number of `.npmrc` files
+#[derive(Debug, Clone, Default)]
+pub struct Npmrc {
+    pub registry: Option<String>,
+    /// `@scope` to registry URL
+    pub scopes: HashMap<String, String>,
+    /// Nerf dart to credentials
+    pub auth: HashMap<String, NpmAuth>,
+}
+
+impl FromStr for Npmrc {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::parse(s, &|key| std::env::var(key).ok()))
+    }
+}
+
+impl Npmrc {
+    /// Parse an `.npmrc`, with `env` to look up the variables it refers to
+    pub fn parse(content: &str, env: &impl Fn(&str) -> Option<String>) -> Self {
+        let mut npmrc = Self::default();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = interpolate_env(key.trim(), env);
+            let value = interpolate_env(value.trim().trim_matches('"'), env);
+
+            if key == "registry" {
+                npmrc.registry = Some(value.trim_end_matches('/').to_string());
+            } else if let Some(scope) = key.strip_suffix(":registry") {
+                npmrc
+                    .scopes
+                    .insert(scope.to_string(), value.trim_end_matches('/').to_string());
+            } else if let Some((nerf, setting)) = key.rsplit_once(':') {
+                if !nerf.starts_with("//") {
+                    continue;
+                }
+                let entry = npmrc
+                    .auth
+                    .entry(format!("{}/", nerf.trim_end_matches('/')))
+                    .or_default();
+                match setting {
+                    "_authToken" => entry.token = Some(value),
+                    "_auth" => entry.auth = Some(value),
+                    "username" => entry.username = Some(value),
+                    "_password" => entry.password = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        npmrc
+    }
+
+    /// Fill in anything we don't have from a lower priority file
+    pub fn merge(&mut self, other: Npmrc) {
+        self.registry = self.registry.take().or(other.registry);
+        for (scope, url) in other.scopes {
+            self.scopes.entry(scope).or_insert(url);
+        }
+        for (nerf, auth) in other.auth {
+            self.auth.entry(nerf).or_insert(auth);
+        }
+    }
+
+    /// Which registry a package comes from, by its scope
+    pub fn registry_for(&self, package: &str) -> String {
+        package
+            .split_once('/')
+            .filter(|(scope, _)| scope.starts_with('@'))
+            .and_then(|(scope, _)| self.scopes.get(scope))
+            .or(self.registry.as_ref())
+            .cloned()
+            .unwrap_or_else(|| NPM_REGISTRY_URL.to_string())
+    }
+
+    /// The credentials for a registry, matching on the longest path like npm does
+    pub fn auth_for(&self, registry: &str) -> Option<&NpmAuth> {
+        let nerf = nerf_dart(registry);
+        self.auth
+            .iter()
+            .filter(|(key, _)| nerf.starts_with(key.as_str()))
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, auth)| auth)
+    }
+
+    fn read_file(path: &Path) -> Result<Option<Self>, Errors> {
+        match path.exists() {
+            true => Ok(Some(Self::from_str(&std::fs::read_to_string(path)?)?)),
+            false => Ok(None),
+        }
+    }
+
+    /// The project, user and global `.npmrc` files, then `NPM_CONFIG_REGISTRY` over the top
+    pub fn load_from(dir: &Path) -> Result<Self, Errors> {
+        let mut npmrc = Self {
+            registry: std::env::var("NPM_CONFIG_REGISTRY")
+                .or_else(|_| std::env::var("npm_config_registry"))
+                .ok()
+                .map(|registry| registry.trim_end_matches('/').to_string()),
+            ..Default::default()
+        };
+
+        let mut paths: Vec<PathBuf> = Vec::new();
+        // the project's is next to the nearest package.json
+        if let Some(project) = dir
+            .ancestors()
+            .find(|ancestor| ancestor.join("package.json").exists())
+        {
+            paths.push(project.join(".npmrc"));
+        }
+        match std::env::var_os("NPM_CONFIG_USERCONFIG") {
+            Some(path) => paths.push(PathBuf::from(path)),
+            None => paths.extend(dirs::home_dir().map(|home| home.join(".npmrc"))),
+        }
+        match std::env::var_os("NPM_CONFIG_GLOBALCONFIG") {
+            Some(path) => paths.push(PathBuf::from(path)),
+            None => {
+                let prefix = std::env::var_os("NPM_CONFIG_PREFIX")
+                    .or_else(|| std::env::var_os("PREFIX"))
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|| PathBuf::from("/usr/local"));
+                paths.push(prefix.join("etc").join("npmrc"));
+            }
+        }
+
+        for path in paths {
+            if let Some(found) = Self::read_file(&path)? {
+                npmrc.merge(found);
+            }
+        }
+        Ok(npmrc)
+    }
+
+    pub fn load() -> Result<Self, Errors> {
+        Self::load_from(&std::env::current_dir()?)
+    }
+}
@@ -0,0 +1,519 @@
+//! Repository hooks for OCI/Docker container registries
+//!
+//! Speaks the OCI distribution API - <https://github.com/opencontainers/distribution-spec/blob/main/spec.md>
+//!
+//! Token auth reference - <https://distribution.github.io/distribution/spec/auth/token/>
+
+use std::sync::Mutex;
+
+use reqwest::header::{ACCEPT, AUTHORIZATION, LINK, WWW_AUTHENTICATE};
+use reqwest::StatusCode;
+
+use super::compare_semver;
+use super::prelude::*;
+use crate::request::{next_link, read_json};
+
+const DOCKER_HUB_URL: &str = "https://registry-1.docker.io";
+
+const INDEX_MEDIA_TYPES: [&str; 2] = [
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+];
+const MANIFEST_MEDIA_TYPES: [&str; 2] = [
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
+
+#[derive(Debug)]
+pub struct Oci {
+    pub cache: Arc<RwLock<Cache>>,
+    pub registry: String,
+    /// Used to fetch bearer tokens, or for basic auth if that's what the registry wants
+    pub credentials: Option<(String, String)>,
+    /// How many tags to look up in detail when getting a package without a tag, the newest
+    /// by semver, or the last in string order when they aren't versions
+    pub max_tags: usize,
+    /// Bearer tokens we've been issued, by repository
+    tokens: Mutex<HashMap<String, String>>,
+}
+
+impl Oci {
+    pub fn with_registry(mut self, registry: &str) -> Self {
+        self.registry = match registry.contains("://") {
+            true => registry.trim_end_matches('/').to_string(),
+            false => format!("https://{}", registry.trim_end_matches('/')),
+        };
+        self
+    }
+
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    pub fn with_max_tags(mut self, max_tags: usize) -> Self {
+        self.max_tags = max_tags;
+        self
+    }
+
+    /// Docker Hub keeps the official images under `library/`
+    fn repository_name(&self, name: &str) -> String {
+        match self.registry == DOCKER_HUB_URL && !name.contains('/') {
+            true => format!("library/{}", name),
+            false => name.to_string(),
+        }
+    }
+
+    /// Follow a `WWW-Authenticate: Bearer ...` challenge to get a token
+    async fn fetch_token(&self, challenge: &AuthChallenge) -> Result<String, Errors> {
+        let realm = challenge
+            .params
+            .get("realm")
+            .ok_or_else(|| Errors::parse(None, None, "Auth challenge without a realm"))?;
+        let mut url = reqwest::Url::parse(realm)?;
+        let token_url = url.to_string();
+        for key in ["service", "scope"] {
+            if let Some(value) = challenge.params.get(key) {
+                url.query_pairs_mut().append_pair(key, value);
+            }
+        }
+
+        let client = WebClient::for_repo_type(RepoType::Oci);
+        let mut req = client.client.get(url);
+        if let Some((username, password)) = self.credentials.as_ref() {
+            req = req.basic_auth(username, Some(password));
+        }
+        let res: TokenResponse = read_json(client.send(req).await?.check_status()?).await?;
+        res.token
+            .or(res.access_token)
+            .ok_or_else(|| Errors::parse(Some(&token_url), None, "Token response without a token"))
+    }
+
+    /// GET something from the registry, dealing with auth challenges along the way
+    async fn get(
+        &self,
+        repository: &str,
+        url: &str,
+        accept: &[&str],
+    ) -> Result<reqwest::Response, Errors> {
+        let client = WebClient::for_repo_type(RepoType::Oci);
+        let build = |token: Option<String>| {
+            let mut req = client.client.get(url).header(ACCEPT, accept.join(", "));
+            if let Some(token) = token {
+                req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+            req
+        };
+
+        let token = self
+            .tokens
+            .lock()
+            .ok()
+            .and_then(|t| t.get(repository).cloned());
+        let res = client.send(build(token)).await?;
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return res.check_status();
+        }
+
+        let challenge = res
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|h| h.to_str().ok())
+            .map(AuthChallenge::parse);
+        let res = match challenge {
+            Some(challenge) if challenge.scheme.eq_ignore_ascii_case("bearer") => {
+                let token = self.fetch_token(&challenge).await?;
+                if let Ok(mut tokens) = self.tokens.lock() {
+                    tokens.insert(repository.to_string(), token.clone());
+                }
+                client.send(build(Some(token))).await?
+            }
+            Some(challenge) if challenge.scheme.eq_ignore_ascii_case("basic") => {
+                let (username, password) =
+                    self.credentials
+                        .as_ref()
+                        .ok_or_else(|| Errors::Unauthorized {
+                            url: url.to_string(),
+                        })?;
+                client
+                    .send(
+                        client
+                            .client
+                            .get(url)
+                            .header(ACCEPT, accept.join(", "))
+                            .basic_auth(username, Some(password)),
+                    )
+                    .await?
+            }
+            _ => res,
+        };
+        res.check_status()
+    }
+
+    /// All the tags for a repository, following `Link` headers for pagination
+    pub async fn list_tags(&self, repository: &str) -> Result<Vec<String>, Errors> {
+        let mut tags = Vec::new();
+        let mut url = format!("{}/v2/{}/tags/list", self.registry, repository);
+        loop {
+            let res = self.get(repository, &url, &["application/json"]).await?;
+            let next = res
+                .headers()
+                .get(LINK)
+                .and_then(|h| h.to_str().ok())
+                .and_then(next_link);
+            let list: TagList = read_json(res).await?;
+            tags.extend(list.tags.unwrap_or_default());
+            match next {
+                Some(next) if next.starts_with('/') => url = format!("{}{}", self.registry, next),
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(tags)
+    }
+
+    async fn get_manifest(
+        &self,
+        repository: &str,
+        reference: &str,
+    ) -> Result<(String, Manifest), Errors> {
+        let url = format!(
+            "{}/v2/{}/manifests/{}",
+            self.registry, repository, reference
+        );
+        let accept: Vec<&str> = INDEX_MEDIA_TYPES
+            .iter()
+            .chain(MANIFEST_MEDIA_TYPES.iter())
+            .copied()
+            .collect();
+        let res = self.get(repository, &url, &accept).await?;
+        let digest = res
+            .headers()
+            .get("Docker-Content-Digest")
+            .and_then(|h| h.to_str().ok())
+            .map(String::from)
+            .unwrap_or_default();
+        Ok((digest, read_json(res).await?))
+    }
+
+    async fn get_config(&self, repository: &str, digest: &str) -> Result<ImageConfig, Errors> {
+        let url = format!("{}/v2/{}/blobs/{}", self.registry, repository, digest);
+        read_json(self.get(repository, &url, &["*/*"]).await?).await
+    }
+
+    /// Look up a tag, following an index down to the first real image for the config
+    pub async fn get_tag(&self, repository: &str, tag: &str) -> Result<ImageTag, Errors> {
+        let (digest, manifest) = self.get_manifest(repository, tag).await?;
+        let mut image = ImageTag {
+            repository: repository.to_string(),
+            tag: tag.to_string(),
+            digest,
+            media_type: manifest.media_type.clone(),
+            ..Default::default()
+        };
+
+        let config_digest = match manifest.config {
+            Some(config) => Some(config.digest),
+            None => {
+                image.platforms = manifest
+                    .manifests
+                    .iter()
+                    .filter_map(|m| m.platform.as_ref())
+                    .filter(|p| p.os != "unknown")
+                    .map(|p| p.to_string())
+                    .collect();
+                match manifest
+                    .manifests
+                    .iter()
+                    .find(|m| m.platform.as_ref().is_some_and(|p| p.os != "unknown"))
+                {
+                    Some(child) => self
+                        .get_manifest(repository, &child.digest)
+                        .await?
+                        .1
+                        .config
+                        .map(|c| c.digest),
+                    None => None,
+                }
+            }
+        };
+
+        if let Some(config_digest) = config_digest {
+            let config = self.get_config(repository, &config_digest).await?;
+            if image.platforms.is_empty() {
+                if let (Some(os), Some(architecture)) =
+                    (config.os.clone(), config.architecture.clone())
+                {
+                    image.platforms.push(
+                        Platform {
+                            os,
+                            architecture,
+                            variant: config.variant.clone(),
+                        }
+                        .to_string(),
+                    );
+                }
+            }
+            image.created = config.created;
+            image.labels = config.config.and_then(|c| c.labels).unwrap_or_default();
+        }
+        Ok(image)
+    }
+}
+
+/// A parsed `WWW-Authenticate` header
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AuthChallenge {
+    pub scheme: String,
+    pub params: HashMap<String, String>,
+}
+
+impl AuthChallenge {
+    /// Parses `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`
+    pub fn parse(header: &str) -> Self {
+        let (scheme, rest) = header.trim().split_once(' ').unwrap_or((header.trim(), ""));
+        let mut params = HashMap::new();
+        let mut chars = rest.chars().peekable();
+        loop {
+            let key: String = chars
+                .by_ref()
+                .skip_while(|c| *c == ',' || c.is_whitespace())
+                .take_while(|c| *c != '=')
+                .collect();
+            if key.is_empty() {
+                break;
+            }
+            let mut value = String::new();
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next()),
+                        '"' => break,
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                value = chars.by_ref().take_while(|c| *c != ',').collect();
+            }
+            params.insert(key.trim().to_lowercase(), value.trim().to_string());
+        }
+        Self {
+            scheme: scheme.to_string(),
+            params,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TagList {
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Catalog {
+    repositories: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Platform {
+    pub os: String,
+    pub architecture: String,
+    pub variant: Option<String>,
+}
+
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = self.variant.as_ref() {
+            write!(f, "/{}", variant)?
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Descriptor {
+    #[serde(rename = "mediaType")]
+    pub media_type: Option<String>,
+    pub digest: String,
+    pub size: Option<u64>,
+    pub platform: Option<Platform>,
+}
+
+/// Either an image manifest (with `config`) or an index (with `manifests`)
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Manifest {
+    #[serde(rename = "mediaType")]
+    pub media_type: Option<String>,
+    pub config: Option<Descriptor>,
+    #[serde(default)]
+    pub manifests: Vec<Descriptor>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ImageConfigConfig {
+    #[serde(rename = "Labels")]
+    pub labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ImageConfig {
+    pub created: Option<DateTime<chrono::Utc>>,
+    pub os: Option<String>,
+    pub architecture: Option<String>,
+    pub variant: Option<String>,
+    pub config: Option<ImageConfigConfig>,
+}
+
+/// Everything we found out about a tag
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ImageTag {
+    pub repository: String,
+    pub tag: String,
+    pub digest: String,
+    pub media_type: Option<String>,
+    /// eg `linux/arm64/v8`
+    pub platforms: Vec<String>,
+    pub created: Option<DateTime<chrono::Utc>>,
+    pub labels: HashMap<String, String>,
+}
+
+impl From<ImageTag> for Package {
+    fn from(value: ImageTag) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        other_metadata.insert("version".to_string(), Value::String(value.tag));
+        other_metadata.insert("digest".to_string(), Value::String(value.digest));
+        if let Some(media_type) = value.media_type {
+            other_metadata.insert("media_type".to_string(), Value::String(media_type));
+        }
+        if !value.platforms.is_empty() {
+            other_metadata.insert("platforms".to_string(), Value::from(value.platforms));
+        }
+        if let Some(created) = value.created {
+            other_metadata.insert(
+                "release_date".to_string(),
+                Value::String(created.to_rfc3339()),
+            );
+        }
+        let url = value
+            .labels
+            .get("org.opencontainers.image.source")
+            .or(value.labels.get("org.opencontainers.image.url"))
+            .cloned();
+        if let Some(license) = value.labels.get("org.opencontainers.image.licenses") {
+            other_metadata.insert("license".to_string(), Value::String(license.clone()));
+        }
+        if let Some(description) = value.labels.get("org.opencontainers.image.description") {
+            other_metadata.insert(
+                "description".to_string(),
+                Value::String(description.clone()),
+            );
+        }
+        let owner = value.labels.get("org.opencontainers.image.vendor").cloned();
+        if !value.labels.is_empty() {
+            other_metadata.insert(
+                "labels".to_string(),
+                serde_json::to_value(value.labels).unwrap_or_default(),
+            );
+        }
+
+        Package {
+            name: value.repository,
+            url,
+            owner,
+            other_metadata,
+            repo_type: RepoType::Oci,
+        }
+    }
+}
+
+#[async_trait]
+impl Repository for Oci {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            registry: DOCKER_HUB_URL.to_string(),
+            credentials: None,
+            max_tags: 25,
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::Oci
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            ..Capabilities::none(RepoType::Oci)
+        }
+    }
+
+    /// Searches the registry's `_catalog`, which not every registry offers
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let url = format!("{}/v2/_catalog", self.registry);
+        let catalog: Catalog = read_json(self.get("", &url, &["application/json"]).await?).await?;
+        Ok(catalog
+            .repositories
+            .into_iter()
+            .filter(|repo| repo.contains(query))
+            .map(|name| Package {
+                name,
+                url: None,
+                owner: None,
+                other_metadata: HashMap::new(),
+                repo_type: RepoType::Oci,
+            })
+            .collect())
+    }
+
+    /// Takes `name` for the newest `max_tags` tags, or `name:tag` for one of them
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let (name, tag) = match name.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, Some(tag)),
+            _ => (name, None),
+        };
+        let repository = self.repository_name(name);
+        let tags = match tag {
+            Some(tag) => vec![tag.to_string()],
+            None => {
+                let mut tags = self
+                    .list_tags(&repository)
+                    .await
+                    .map_err(|err| err.or_not_found(RepoType::Oci, name))?;
+                // registries list them in string order, which puts 1.10 before 1.9
+                tags.sort_by(|a, b| compare_semver(a, b));
+                let skip = tags.len().saturating_sub(self.max_tags);
+                tags.into_iter().skip(skip).collect()
+            }
+        };
+
+        let mut packages = Vec::new();
+        for tag in tags {
+            let image = self
+                .get_tag(&repository, &tag)
+                .await
+                .map_err(|err| err.or_not_found(RepoType::Oci, &format!("{}:{}", name, tag)))?;
+            packages.push(image.into());
+        }
+        Ok(packages)
+    }
+
+    async fn cacheable(&self) -> bool {
+        false
+    }
+
+    async fn update_cache(&self, _min_age: Option<u64>) -> Result<(), Errors> {
+        Err(self.capabilities().unsupported("mirroring"))
+    }
+}
@@ -0,0 +1,374 @@
+//! Repository hooks for Packagist, the PHP/Composer package repository
+//!
+//! API reference - <https://packagist.org/apidoc>
+
+use std::str::FromStr;
+
+use super::prelude::*;
+
+const PACKAGIST_REPO_URL: &str = "https://repo.packagist.org";
+const PACKAGIST_URL: &str = "https://packagist.org";
+
+#[derive(Debug)]
+pub struct Packagist {
+    pub cache: Arc<RwLock<Cache>>,
+    /// Serves the `p2/` metadata
+    pub repo_url: String,
+    /// Serves the search API and `packages/list.json`
+    pub api_url: String,
+}
+
+impl Packagist {
+    pub fn with_repo_url(mut self, repo_url: &str) -> Self {
+        self.repo_url = repo_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    fn list_url(&self) -> String {
+        format!("{}/packages/list.json", self.api_url)
+    }
+
+    async fn search_api(&self, query: &str) -> Result<Vec<Package>, Errors> {
+        let mut url = reqwest::Url::from_str(&format!("{}/search.json", self.api_url))?;
+        url.query_pairs_mut().append_pair("q", query);
+
+        let data: PackagistSearchResponse = WebClient::for_repo_type(RepoType::Packagist)
+            .get_json(url.as_ref())
+            .await?;
+        Ok(data.results.into_iter().map(|res| res.into()).collect())
+    }
+}
+
+/// Expand the `composer/2.0` minified format, where each version only lists the keys which
+/// changed from the one before it and `__unset` removes a key.
+pub fn expand_minified(versions: Vec<Value>) -> Vec<Value> {
+    let mut expanded = Vec::new();
+    let mut current: Option<serde_json::Map<String, Value>> = None;
+    for version in versions {
+        let Value::Object(version) = version else {
+            continue;
+        };
+        let next = match current.take() {
+            None => version,
+            Some(mut previous) => {
+                for (key, value) in version {
+                    match value.as_str() == Some("__unset") {
+                        true => {
+                            previous.remove(&key);
+                        }
+                        false => {
+                            previous.insert(key, value);
+                        }
+                    }
+                }
+                previous
+            }
+        };
+        expanded.push(Value::Object(next.clone()));
+        current = Some(next);
+    }
+    expanded
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PackagistMetadata {
+    pub packages: HashMap<String, Vec<Value>>,
+    pub minified: Option<String>,
+}
+
+impl PackagistMetadata {
+    /// All the versions of all the packages in the document, expanded if need be
+    pub fn versions(self) -> Result<Vec<PackagistVersion>, Errors> {
+        let minified = self.minified.is_some();
+        let mut res = Vec::new();
+        for (name, versions) in self.packages {
+            let versions = match minified {
+                true => expand_minified(versions),
+                false => versions,
+            };
+            for version in versions {
+                let mut version: PackagistVersion = serde_json::from_value(version)?;
+                if version.name.is_none() {
+                    version.name = Some(name.clone());
+                }
+                res.push(version);
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PackagistAuthor {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub homepage: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PackagistSource {
+    #[serde(rename = "type")]
+    pub source_type: Option<String>,
+    pub url: Option<String>,
+    pub reference: Option<String>,
+    pub shasum: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PackagistVersion {
+    pub name: Option<String>,
+    pub version: String,
+    pub version_normalized: Option<String>,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub license: Vec<String>,
+    #[serde(default)]
+    pub authors: Vec<PackagistAuthor>,
+    pub source: Option<PackagistSource>,
+    pub dist: Option<PackagistSource>,
+    /// Composer sends an empty array rather than an empty object
+    pub require: Option<Value>,
+    #[serde(rename = "require-dev")]
+    pub require_dev: Option<Value>,
+    pub time: Option<DateTime<chrono::Utc>>,
+    /// Either `true` or the name of the suggested replacement
+    pub abandoned: Option<Value>,
+}
+
+/// Turn Packagist's `abandoned` field into a message, if it's set
+fn abandoned_message(abandoned: Option<&Value>) -> Option<String> {
+    match abandoned? {
+        Value::Bool(true) => Some("abandoned".to_string()),
+        Value::String(replacement) => Some(format!("abandoned, use {} instead", replacement)),
+        _ => None,
+    }
+}
+
+impl From<PackagistVersion> for Package {
+    fn from(value: PackagistVersion) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        let name = value.name.unwrap_or_default();
+        other_metadata.insert("version".to_string(), Value::String(value.version));
+        if let Some(description) = value.description {
+            other_metadata.insert("description".to_string(), Value::String(description));
+        }
+        if let Some(homepage) = value.homepage {
+            other_metadata.insert("homepage".to_string(), Value::String(homepage));
+        }
+        if !value.keywords.is_empty() {
+            other_metadata.insert(
+                "keywords".to_string(),
+                Value::String(value.keywords.join(",")),
+            );
+        }
+        if !value.license.is_empty() {
+            other_metadata.insert(
+                "license".to_string(),
+                Value::String(value.license.join(" OR ")),
+            );
+        }
+        if let Some(require) = value.require.filter(|r| r.is_object()) {
+            other_metadata.insert("dependencies".to_string(), require);
+        }
+        if let Some(require_dev) = value.require_dev.filter(|r| r.is_object()) {
+            other_metadata.insert("dev_dependencies".to_string(), require_dev);
+        }
+        if let Some(source) = value.source {
+            if let Some(url) = source.url {
+                other_metadata.insert("repository".to_string(), Value::String(url));
+            }
+            if let Some(reference) = source.reference {
+                other_metadata.insert("source_reference".to_string(), Value::String(reference));
+            }
+        }
+        if let Some(dist) = value.dist {
+            if let Some(url) = dist.url {
+                other_metadata.insert("download_url".to_string(), Value::String(url));
+            }
+            if let Some(shasum) = dist.shasum.filter(|s| !s.is_empty()) {
+                other_metadata.insert("checksum".to_string(), Value::String(shasum));
+            }
+        }
+        if let Some(time) = value.time {
+            other_metadata.insert("release_date".to_string(), Value::String(time.to_rfc3339()));
+        }
+        if let Some(message) = abandoned_message(value.abandoned.as_ref()) {
+            other_metadata.insert("deprecated".to_string(), Value::String(message));
+        }
+
+        let owner = value.authors.into_iter().find_map(|author| author.name);
+        Package {
+            url: Some(format!("{}/packages/{}", PACKAGIST_URL, name)),
+            name,
+            owner,
+            other_metadata,
+            repo_type: RepoType::Packagist,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PackagistSearchResult {
+    pub name: String,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    pub repository: Option<String>,
+    pub downloads: Option<u64>,
+    pub favers: Option<u64>,
+    pub abandoned: Option<Value>,
+}
+
+impl From<PackagistSearchResult> for Package {
+    fn from(value: PackagistSearchResult) -> Self {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        if let Some(description) = value.description.filter(|d| !d.is_empty()) {
+            other_metadata.insert("description".to_string(), Value::String(description));
+        }
+        if let Some(repository) = value.repository {
+            other_metadata.insert("repository".to_string(), Value::String(repository));
+        }
+        if let Some(downloads) = value.downloads {
+            other_metadata.insert("downloads".to_string(), Value::from(downloads));
+        }
+        if let Some(favers) = value.favers {
+            other_metadata.insert("favers".to_string(), Value::from(favers));
+        }
+        if let Some(message) = abandoned_message(value.abandoned.as_ref()) {
+            other_metadata.insert("deprecated".to_string(), Value::String(message));
+        }
+        Package {
+            name: value.name,
+            url: value.url,
+            owner: None,
+            other_metadata,
+            repo_type: RepoType::Packagist,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PackagistSearchResponse {
+    pub results: Vec<PackagistSearchResult>,
+    pub total: u64,
+    pub next: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PackagistList {
+    #[serde(rename = "packageNames")]
+    pub package_names: Vec<String>,
+}
+
+#[async_trait]
+impl Repository for Packagist {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            repo_url: PACKAGIST_REPO_URL.to_string(),
+            api_url: PACKAGIST_URL.to_string(),
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::Packagist
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            mirror: true,
+            dependencies: true,
+            downloads: true,
+            ..Capabilities::none(RepoType::Packagist)
+        }
+    }
+
+    /// Searches the cached name list if there is one, otherwise uses the search API
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let cached = self
+            .cache
+            .read()
+            .await
+            .get_fresh_json::<PackagistList>(RepoType::Packagist, &self.list_url());
+        let Some(list) = cached else {
+            return self.search_api(query).await;
+        };
+
+        let query = query.to_lowercase();
+        Ok(list
+            .package_names
+            .into_iter()
+            .filter(|name| name.contains(&query))
+            .map(|name| Package {
+                url: Some(format!("{}/packages/{}", PACKAGIST_URL, name)),
+                name,
+                owner: None,
+                other_metadata: HashMap::new(),
+                repo_type: RepoType::Packagist,
+            })
+            .collect())
+    }
+
+    /// Takes a `vendor/package` name, returns the tagged versions oldest first
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        if !name.contains('/') {
+            return Err(Errors::InvalidInput(format!(
+                "Packagist names are vendor/package, got {}",
+                name
+            )));
+        }
+        let url = format!("{}/p2/{}.json", self.repo_url, name.to_lowercase());
+        let metadata: PackagistMetadata = WebClient::for_repo_type(RepoType::Packagist)
+            .get_json(&url)
+            .await
+            .map_err(|err| err.or_not_found(RepoType::Packagist, name))?;
+
+        // p2 lists the newest version first
+        let mut versions = metadata.versions()?;
+        versions.reverse();
+        Ok(versions.into_iter().map(|version| version.into()).collect())
+    }
+
+    async fn cacheable(&self) -> bool {
+        true
+    }
+
+    /// Downloads the full package name list for local searching
+    async fn update_cache(&self, min_age: Option<u64>) -> Result<(), Errors> {
+        let _lock = self
+            .cache
+            .read()
+            .await
+            .lock_ecosystem(RepoType::Packagist)?;
+        let url = self.list_url();
+        if let Some(min_age) = min_age {
+            let max_age = chrono::Duration::seconds(min_age as i64);
+            if self
+                .cache
+                .read()
+                .await
+                .is_fresh(RepoType::Packagist, &url, max_age)
+            {
+                return Ok(());
+            }
+        }
+        let content = WebClient::for_repo_type(RepoType::Packagist)
+            .get_text(&url)
+            .await?;
+        self.cache.read().await.save(
+            CacheData::new(url, String::new(), content)
+                .with_kind(RepoType::Packagist, ResourceKind::Index),
+        )?;
+        Ok(())
+    }
+}
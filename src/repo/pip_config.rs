@@ -0,0 +1,412 @@
+//! Finding Python package indexes the way pip and uv do
+//!
+//! pip config reference - <https://pip.pypa.io/en/stable/topics/configuration/>
+//!
+//! uv config reference - <https://docs.astral.sh/uv/reference/settings/>
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::prelude::*;
+
+pub const PYPI_INDEX_URL: &str = "https://pypi.org/simple";
+
+/// An index to look for packages in, credentials split out of the URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PyPiIndex {
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Default for PyPiIndex {
+    fn default() -> Self {
+        Self {
+            url: PYPI_INDEX_URL.to_string(),
+            username: None,
+            password: None,
+        }
+    }
+}
+
+impl FromStr for PyPiIndex {
+    type Err = Errors;
+
+    /// Takes an index URL, which can have `user:password@` in it
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut url = reqwest::Url::parse(s.trim())
+            .map_err(|err| Errors::Config(format!("Invalid index URL {}: {}", s, err)))?;
+        let username = Some(url.username().to_string()).filter(|u| !u.is_empty());
+        let password = url.password().map(String::from);
+        // neither of these can fail on an http(s) URL
+        url.set_username("").ok();
+        url.set_password(None).ok();
+        Ok(Self {
+            url: url.as_str().trim_end_matches('/').to_string(),
+            username: username.map(|u| unescape_userinfo(&u)),
+            password: password.map(|p| unescape_userinfo(&p)),
+        })
+    }
+}
+
+impl PyPiIndex {
+    pub fn host(&self) -> Option<String> {
+        reqwest::Url::parse(&self.url)
+            .ok()?
+            .host_str()
+            .map(String::from)
+    }
+
+    /// Fill in credentials from `.netrc` if the URL didn't have any
+    pub fn with_netrc(mut self, netrc: &Netrc) -> Self {
+        if self.username.is_none() {
+            if let Some(entry) = self.host().and_then(|host| netrc.get(&host)) {
+                self.username = Some(entry.login.clone());
+                self.password = entry.password.clone();
+            }
+        }
+        self
+    }
+}
+
+/// `%40` and friends are allowed in the userinfo part of a URL
+fn unescape_userinfo(value: &str) -> String {
+    let mut result = Vec::new();
+    let bytes = value.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) => match u8::from_str_radix(&String::from_utf8_lossy(hex), 16) {
+                Ok(byte) => {
+                    result.push(byte);
+                    i += 3;
+                    continue;
+                }
+                Err(_) => result.push(b'%'),
+            },
+            (byte, _) => result.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetrcEntry {
+    pub login: String,
+    pub password: Option<String>,
+}
+
+/// The machines and logins from a `.netrc` file
+#[derive(Debug, Clone, Default)]
+pub struct Netrc {
+    pub machines: HashMap<String, NetrcEntry>,
+    pub default: Option<NetrcEntry>,
+}
+
+impl FromStr for Netrc {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut netrc = Self::default();
+        let mut tokens = s
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split_whitespace());
+
+        // (machine name, None meaning the default entry) and what we've seen so far
+        let mut current: Option<(Option<String>, Option<String>, Option<String>)> = None;
+        let mut finish = |current: Option<(Option<String>, Option<String>, Option<String>)>| {
+            if let Some((machine, Some(login), password)) = current {
+                let entry = NetrcEntry { login, password };
+                match machine {
+                    Some(machine) => {
+                        netrc.machines.entry(machine).or_insert(entry);
+                    }
+                    None => netrc.default = Some(entry),
+                }
+            }
+        };
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" => {
+                    finish(current.take());
+                    current = Some((tokens.next().map(String::from), None, None));
+                }
+                "default" => {
+                    finish(current.take());
+                    current = Some((None, None, None));
+                }
+                "login" => {
+                    if let Some(entry) = current.as_mut() {
+                        entry.1 = tokens.next().map(String::from);
+                    }
+                }
+                "password" => {
+                    if let Some(entry) = current.as_mut() {
+                        entry.2 = tokens.next().map(String::from);
+                    }
+                }
+                "account" => {
+                    tokens.next();
+                }
+                // macros run to the end of the file as far as we're concerned
+                "macdef" => break,
+                _ => {}
+            }
+        }
+        finish(current);
+        Ok(netrc)
+    }
+}
+
+impl Netrc {
+    pub fn get(&self, host: &str) -> Option<&NetrcEntry> {
+        self.machines.get(host).or(self.default.as_ref())
+    }
+
+    /// Reads `$NETRC`, or `~/.netrc`, an empty one if there isn't one
+    pub fn load() -> Result<Self, Errors> {
+        let path = match std::env::var_os("NETRC") {
+            Some(path) => PathBuf::from(path),
+            None => match dirs::home_dir() {
+                Some(home) => home.join(".netrc"),
+                None => return Ok(Self::default()),
+            },
+        };
+        match path.exists() {
+            true => Self::from_str(&std::fs::read_to_string(path)?),
+            false => Ok(Self::default()),
+        }
+    }
+}
+
+/// Pull `index-url` and `extra-index-url` out of a `pip.conf`, `[install]` overrides `[global]`
+pub fn parse_pip_conf(content: &str) -> (Option<String>, Vec<String>) {
+    let mut values: HashMap<(String, String), String> = HashMap::new();
+    let mut section = String::new();
+    let mut last_key: Option<(String, String)> = None;
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = trimmed
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            section = name.trim().to_lowercase();
+            last_key = None;
+            continue;
+        }
+        // indented lines carry on the previous value
+        if line.starts_with(char::is_whitespace) {
+            if let Some(key) = last_key.as_ref() {
+                if let Some(value) = values.get_mut(key) {
+                    value.push('\n');
+                    value.push_str(trimmed);
+                }
+            }
+            continue;
+        }
+        if let Some((key, value)) = trimmed.split_once(['=', ':']) {
+            let key = (section.clone(), key.trim().to_lowercase().replace('_', "-"));
+            values.insert(key.clone(), value.trim().to_string());
+            last_key = Some(key);
+        }
+    }
+
+    let get = |key: &str| {
+        values
+            .get(&("install".to_string(), key.to_string()))
+            .or(values.get(&("global".to_string(), key.to_string())))
+    };
+    let index_url = get("index-url").cloned();
+    let extra_index_urls = get("extra-index-url")
+        .map(|value| value.split_whitespace().map(String::from).collect())
+        .unwrap_or_default();
+    (index_url, extra_index_urls)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct UvIndex {
+    pub name: Option<String>,
+    pub url: String,
+    #[serde(default)]
+    pub default: bool,
+}
+
+/// The index settings from `uv.toml`, or `[tool.uv]` in `pyproject.toml`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct UvConfig {
+    pub index_url: Option<String>,
+    #[serde(default)]
+    pub extra_index_url: Vec<String>,
+    #[serde(default)]
+    pub index: Vec<UvIndex>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct PyProjectTool {
+    uv: Option<UvConfig>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct PyProject {
+    tool: Option<PyProjectTool>,
+}
+
+impl UvConfig {
+    pub fn from_uv_toml(content: &str) -> Result<Self, Errors> {
+        Ok(toml::from_str(content)?)
+    }
+
+    pub fn from_pyproject(content: &str) -> Result<Option<Self>, Errors> {
+        let pyproject: PyProject = toml::from_str(content)?;
+        Ok(pyproject.tool.and_then(|tool| tool.uv))
+    }
+
+    /// The default index, then the others in the order uv would try them
+    fn into_urls(self) -> (Option<String>, Vec<String>) {
+        let mut default = self.index_url;
+        let mut extra = Vec::new();
+        for index in self.index {
+            match index.default {
+                true => default = default.or(Some(index.url)),
+                false => extra.push(index.url),
+            }
+        }
+        extra.extend(self.extra_index_url);
+        (default, extra)
+    }
+}
+
+/// Where to look for packages, from pip and uv's config files and environment variables
+#[derive(Debug, Clone, Default)]
+pub struct PipConfig {
+    pub index_url: Option<String>,
+    pub extra_index_urls: Vec<String>,
+}
+
+impl PipConfig {
+    /// Add a lower priority source of settings
+    pub fn merge(&mut self, index_url: Option<String>, extra_index_urls: Vec<String>) {
+        self.index_url = self.index_url.take().or(index_url);
+        for url in extra_index_urls {
+            if !self.extra_index_urls.contains(&url) {
+                self.extra_index_urls.push(url);
+            }
+        }
+    }
+
+    /// Everything in priority order, the default index first
+    pub fn indexes(&self, netrc: &Netrc) -> Result<Vec<PyPiIndex>, Errors> {
+        let mut indexes = vec![match self.index_url.as_ref() {
+            Some(url) => PyPiIndex::from_str(url)?,
+            None => PyPiIndex::default(),
+        }];
+        for url in self.extra_index_urls.iter() {
+            indexes.push(PyPiIndex::from_str(url)?);
+        }
+        Ok(indexes
+            .into_iter()
+            .map(|index| index.with_netrc(netrc))
+            .collect())
+    }
+
+    /// The pip config files, lowest priority first
+    fn pip_conf_paths() -> Vec<PathBuf> {
+        let mut paths = vec![PathBuf::from("/etc/pip.conf")];
+        if let Some(config) = dirs::config_dir() {
+            paths.push(config.join("pip").join("pip.conf"));
+        }
+        if let Some(home) = dirs::home_dir() {
+            paths.push(home.join(".pip").join("pip.conf"));
+        }
+        if let Some(venv) = std::env::var_os("VIRTUAL_ENV") {
+            paths.push(PathBuf::from(venv).join("pip.conf"));
+        }
+        if let Some(path) = std::env::var_os("PIP_CONFIG_FILE") {
+            paths.push(PathBuf::from(path));
+        }
+        paths
+    }
+
+    /// The uv config files, highest priority first
+    fn uv_configs(dir: &Path) -> Result<Vec<UvConfig>, Errors> {
+        let mut configs = Vec::new();
+        if let Some(path) = std::env::var_os("UV_CONFIG_FILE") {
+            configs.push(UvConfig::from_uv_toml(&std::fs::read_to_string(path)?)?);
+            return Ok(configs);
+        }
+        for ancestor in dir.ancestors() {
+            let uv_toml = ancestor.join("uv.toml");
+            let pyproject = ancestor.join("pyproject.toml");
+            if uv_toml.exists() {
+                configs.push(UvConfig::from_uv_toml(&std::fs::read_to_string(uv_toml)?)?);
+                break;
+            }
+            if pyproject.exists() {
+                if let Some(config) =
+                    UvConfig::from_pyproject(&std::fs::read_to_string(pyproject)?)?
+                {
+                    configs.push(config);
+                    break;
+                }
+            }
+        }
+        if let Some(config) = dirs::config_dir() {
+            let path = config.join("uv").join("uv.toml");
+            if path.exists() {
+                configs.push(UvConfig::from_uv_toml(&std::fs::read_to_string(path)?)?);
+            }
+        }
+        Ok(configs)
+    }
+
+    fn env_list(key: &str) -> Vec<String> {
+        std::env::var(key)
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(String::from)
+            .collect()
+    }
+
+    /// Environment variables, then uv's config, then pip's
+    pub fn load() -> Result<Self, Errors> {
+        let mut config = Self::default();
+        // uv lets you name these, as `name=url`
+        let strip_name = |value: String| match value.split_once('=') {
+            Some((name, url)) if !name.contains(':') => url.to_string(),
+            _ => value,
+        };
+        config.merge(
+            std::env::var("UV_DEFAULT_INDEX").ok().map(strip_name),
+            Self::env_list("UV_INDEX")
+                .into_iter()
+                .map(strip_name)
+                .collect(),
+        );
+        config.merge(
+            std::env::var("UV_INDEX_URL").ok(),
+            Self::env_list("UV_EXTRA_INDEX_URL"),
+        );
+        config.merge(
+            std::env::var("PIP_INDEX_URL").ok(),
+            Self::env_list("PIP_EXTRA_INDEX_URL"),
+        );
+
+        for uv in Self::uv_configs(&std::env::current_dir()?)? {
+            let (index_url, extra) = uv.into_urls();
+            config.merge(index_url, extra);
+        }
+        for path in Self::pip_conf_paths().into_iter().rev() {
+            if path.exists() {
+                let (index_url, extra) = parse_pip_conf(&std::fs::read_to_string(path)?);
+                config.merge(index_url, extra);
+            }
+        }
+        Ok(config)
+    }
+}
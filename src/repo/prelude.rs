@@ -1,8 +1,8 @@
-pub(crate) use super::{Package, Repository};
-pub(crate) use crate::cache::Cache;
-pub(crate) use crate::request::WebClient;
+pub(crate) use super::{Capabilities, Package, Repository};
+pub(crate) use crate::cache::{Cache, CacheData, ResourceKind};
+pub(crate) use crate::request::{CheckStatus, WebClient};
+pub(crate) use crate::Errors;
 pub(crate) use crate::RepoType;
-pub(crate) use crate::{make_cache_dir, Errors};
 pub(crate) use async_trait::async_trait;
 pub(crate) use chrono::DateTime;
 pub(crate) use serde::{Deserialize, Serialize};
@@ -0,0 +1,428 @@
+//! Repository hooks for pub.dev, the Dart and Flutter package repository
+//!
+//! API reference - <https://github.com/dart-lang/pub/blob/master/doc/repository-spec-v2.md>
+
+use std::str::FromStr;
+
+use semver::Version;
+
+use super::compare_semver;
+use super::prelude::*;
+
+const PUBDEV_URL: &str = "https://pub.dev";
+
+#[derive(Debug)]
+pub struct PubDev {
+    pub cache: Arc<RwLock<Cache>>,
+    pub base_url: String,
+}
+
+impl PubDev {
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Likes, pub points and popularity for a package
+    pub async fn get_score(&self, name: &str) -> Result<PubScore, Errors> {
+        let url = format!("{}/api/packages/{}/score", self.base_url, name);
+        WebClient::for_repo_type(RepoType::PubDev)
+            .get_json(&url)
+            .await
+    }
+}
+
+/// A pub version constraint, eg `^1.2.3`, `>=1.0.0 <2.0.0` or `any`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionConstraint {
+    /// All of these have to match, an empty list allows anything
+    pub bounds: Vec<(String, Version)>,
+}
+
+impl FromStr for VersionConstraint {
+    type Err = Errors;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut bounds = Vec::new();
+        let parse = |version: &str| {
+            Version::from_str(version.trim()).map_err(|err| {
+                Errors::InvalidInput(format!("Invalid version {:?}: {}", version, err))
+            })
+        };
+
+        // the space between an operator and its version is optional
+        let mut tokens = value.split_whitespace();
+        while let Some(token) = tokens.next() {
+            if token == "any" {
+                continue;
+            }
+            let (op, version) = match ["^", ">=", "<=", ">", "<"]
+                .iter()
+                .find(|op| token.starts_with(**op))
+            {
+                Some(op) if token.len() == op.len() => (
+                    *op,
+                    tokens.next().ok_or_else(|| {
+                        Errors::InvalidInput(format!("Missing version after {} in {:?}", op, value))
+                    })?,
+                ),
+                Some(op) => (*op, &token[op.len()..]),
+                None => ("=", token),
+            };
+            let version = parse(version)?;
+            match op {
+                "^" => {
+                    // the next breaking version, which is the next minor for 0.x
+                    let upper = match version.major {
+                        0 => Version::new(0, version.minor + 1, 0),
+                        major => Version::new(major + 1, 0, 0),
+                    };
+                    bounds.push((">=".to_string(), version));
+                    bounds.push(("<".to_string(), upper));
+                }
+                op => bounds.push((op.to_string(), version)),
+            }
+        }
+        Ok(Self { bounds })
+    }
+}
+
+impl std::fmt::Display for VersionConstraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.bounds.is_empty() {
+            return write!(f, "any");
+        }
+        let bounds: Vec<String> = self
+            .bounds
+            .iter()
+            .map(|(op, version)| match op.as_str() {
+                "=" => version.to_string(),
+                op => format!("{}{}", op, version),
+            })
+            .collect();
+        write!(f, "{}", bounds.join(" "))
+    }
+}
+
+impl VersionConstraint {
+    pub fn allows(&self, version: &Version) -> bool {
+        self.bounds.iter().all(|(op, bound)| match op.as_str() {
+            ">=" => version >= bound,
+            "<=" => version <= bound,
+            ">" => version > bound,
+            "<" => version < bound,
+            _ => version == bound,
+        })
+    }
+}
+
+/// Where a dependency comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PubDependencySource {
+    Hosted(Option<String>),
+    Sdk(String),
+    Git(String),
+    Path(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct PubDependency {
+    pub name: String,
+    pub constraint: VersionConstraint,
+    pub source: PubDependencySource,
+}
+
+impl PubDependency {
+    /// Parse a pubspec dependency, which is either a constraint string or a map with a source
+    pub fn parse(name: &str, value: &Value) -> Result<Self, Errors> {
+        let (constraint, source) = match value {
+            Value::Null => (None, PubDependencySource::Hosted(None)),
+            Value::String(constraint) => {
+                (Some(constraint.as_str()), PubDependencySource::Hosted(None))
+            }
+            Value::Object(map) => {
+                let source = if let Some(sdk) = map.get("sdk").and_then(|v| v.as_str()) {
+                    PubDependencySource::Sdk(sdk.to_string())
+                } else if let Some(git) = map.get("git") {
+                    let url = match git {
+                        Value::Object(git) => git.get("url").and_then(|v| v.as_str()),
+                        git => git.as_str(),
+                    };
+                    PubDependencySource::Git(url.unwrap_or_default().to_string())
+                } else if let Some(path) = map.get("path").and_then(|v| v.as_str()) {
+                    PubDependencySource::Path(path.to_string())
+                } else {
+                    let hosted = match map.get("hosted") {
+                        Some(Value::Object(hosted)) => hosted.get("url").and_then(|v| v.as_str()),
+                        Some(hosted) => hosted.as_str(),
+                        None => None,
+                    };
+                    PubDependencySource::Hosted(hosted.map(String::from))
+                };
+                (map.get("version").and_then(|v| v.as_str()), source)
+            }
+            other => {
+                return Err(Errors::InvalidInput(format!(
+                    "Invalid dependency {} {:?}",
+                    name, other
+                )))
+            }
+        };
+        Ok(Self {
+            name: name.to_string(),
+            constraint: VersionConstraint::from_str(constraint.unwrap_or("any"))?,
+            source,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PubSpec {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub documentation: Option<String>,
+    #[serde(default)]
+    pub environment: HashMap<String, Value>,
+    pub dependencies: Option<HashMap<String, Value>>,
+    pub dev_dependencies: Option<HashMap<String, Value>>,
+}
+
+impl PubSpec {
+    pub fn dependencies(&self) -> Result<Vec<PubDependency>, Errors> {
+        let mut dependencies = self
+            .dependencies
+            .iter()
+            .flatten()
+            .map(|(name, value)| PubDependency::parse(name, value))
+            .collect::<Result<Vec<_>, _>>()?;
+        dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(dependencies)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PubVersion {
+    pub version: String,
+    pub pubspec: PubSpec,
+    pub archive_url: Option<String>,
+    pub archive_sha256: Option<String>,
+    pub published: Option<DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub retracted: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PubPackage {
+    pub name: String,
+    pub latest: PubVersion,
+    pub versions: Vec<PubVersion>,
+    #[serde(default, rename = "isDiscontinued")]
+    pub is_discontinued: bool,
+    #[serde(rename = "replacedBy")]
+    pub replaced_by: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct PubScore {
+    #[serde(rename = "grantedPoints")]
+    pub granted_points: Option<u64>,
+    #[serde(rename = "maxPoints")]
+    pub max_points: Option<u64>,
+    #[serde(rename = "likeCount")]
+    pub like_count: Option<u64>,
+    #[serde(rename = "popularityScore")]
+    pub popularity_score: Option<f64>,
+    #[serde(rename = "downloadCount30Days")]
+    pub download_count_30_days: Option<u64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl PubScore {
+    fn metadata(&self) -> HashMap<String, Value> {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        if let Some(likes) = self.like_count {
+            other_metadata.insert("likes".to_string(), Value::from(likes));
+        }
+        if let Some(points) = self.granted_points {
+            other_metadata.insert("pub_points".to_string(), Value::from(points));
+        }
+        if let Some(max_points) = self.max_points {
+            other_metadata.insert("max_pub_points".to_string(), Value::from(max_points));
+        }
+        if let Some(popularity) = self.popularity_score {
+            other_metadata.insert("popularity".to_string(), Value::from(popularity));
+        }
+        if let Some(downloads) = self.download_count_30_days {
+            other_metadata.insert("downloads_30_days".to_string(), Value::from(downloads));
+        }
+        other_metadata
+    }
+}
+
+impl PubPackage {
+    /// One [Package] per version, oldest first, with the score merged into the metadata.
+    /// `base_url` is the repository it came from, for versions without a homepage
+    pub fn into_packages(
+        self,
+        base_url: &str,
+        score: Option<&PubScore>,
+    ) -> Result<Vec<Package>, Errors> {
+        let mut metadata = score.map(|score| score.metadata()).unwrap_or_default();
+        if self.is_discontinued {
+            let message = match self.replaced_by.as_ref() {
+                Some(replacement) => format!("discontinued, replaced by {}", replacement),
+                None => "discontinued".to_string(),
+            };
+            metadata.insert("deprecated".to_string(), Value::String(message));
+        }
+
+        let mut versions = self.versions;
+        versions.sort_by(|a, b| compare_semver(&a.version, &b.version));
+        versions
+            .into_iter()
+            .map(|version| {
+                let mut package = version.into_package(base_url)?;
+                package.other_metadata.extend(metadata.clone());
+                Ok(package)
+            })
+            .collect()
+    }
+}
+
+impl PubVersion {
+    /// The [Package] for this version, linking to `base_url` if there's no homepage
+    fn into_package(self, base_url: &str) -> Result<Package, Errors> {
+        let mut other_metadata: HashMap<String, Value> = HashMap::new();
+        let dependencies: serde_json::Map<String, Value> = self
+            .pubspec
+            .dependencies()?
+            .into_iter()
+            .map(|dep| (dep.name, Value::String(dep.constraint.to_string())))
+            .collect();
+        if !dependencies.is_empty() {
+            other_metadata.insert("dependencies".to_string(), Value::Object(dependencies));
+        }
+        if let Some(sdk) = self.pubspec.environment.get("sdk") {
+            other_metadata.insert("sdk".to_string(), sdk.clone());
+        }
+        if let Some(flutter) = self.pubspec.environment.get("flutter") {
+            other_metadata.insert("flutter".to_string(), flutter.clone());
+        }
+        other_metadata.insert("version".to_string(), Value::String(self.version));
+        if let Some(description) = self.pubspec.description {
+            other_metadata.insert("description".to_string(), Value::String(description));
+        }
+        if let Some(repository) = self.pubspec.repository {
+            other_metadata.insert("repository".to_string(), Value::String(repository));
+        }
+        if let Some(documentation) = self.pubspec.documentation {
+            other_metadata.insert("documentation".to_string(), Value::String(documentation));
+        }
+        if let Some(archive_url) = self.archive_url {
+            other_metadata.insert("download_url".to_string(), Value::String(archive_url));
+        }
+        if let Some(archive_sha256) = self.archive_sha256 {
+            other_metadata.insert("checksum".to_string(), Value::String(archive_sha256));
+        }
+        if let Some(published) = self.published {
+            other_metadata.insert(
+                "release_date".to_string(),
+                Value::String(published.to_rfc3339()),
+            );
+        }
+        if self.retracted {
+            other_metadata.insert("yanked".to_string(), Value::Bool(true));
+        }
+
+        Ok(Package {
+            url: self
+                .pubspec
+                .homepage
+                .or(Some(format!("{}/packages/{}", base_url, self.pubspec.name))),
+            name: self.pubspec.name,
+            owner: None,
+            other_metadata,
+            repo_type: RepoType::PubDev,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PubSearchResult {
+    pub package: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PubSearchResponse {
+    pub packages: Vec<PubSearchResult>,
+    pub next: Option<String>,
+}
+
+#[async_trait]
+impl Repository for PubDev {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            base_url: PUBDEV_URL.to_string(),
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::PubDev
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            dependencies: true,
+            downloads: true,
+            ..Capabilities::none(RepoType::PubDev)
+        }
+    }
+
+    /// The search API only returns names, so that's all we give back
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let mut url = reqwest::Url::from_str(&format!("{}/api/search", self.base_url))?;
+        url.query_pairs_mut().append_pair("q", query);
+
+        let data: PubSearchResponse = WebClient::for_repo_type(RepoType::PubDev)
+            .get_json(url.as_ref())
+            .await?;
+        Ok(data
+            .packages
+            .into_iter()
+            .map(|res| Package {
+                url: Some(format!("{}/packages/{}", self.base_url, res.package)),
+                name: res.package,
+                owner: None,
+                other_metadata: HashMap::new(),
+                repo_type: RepoType::PubDev,
+            })
+            .collect())
+    }
+
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let url = format!("{}/api/packages/{}", self.base_url, name);
+        let package: PubPackage = WebClient::for_repo_type(RepoType::PubDev)
+            .get_json(&url)
+            .await
+            .map_err(|err| err.or_not_found(RepoType::PubDev, name))?;
+        // the score's nice to have, don't fail the lookup without it
+        let score = self.get_score(name).await.ok();
+        package.into_packages(&self.base_url, score.as_ref())
+    }
+
+    async fn cacheable(&self) -> bool {
+        false
+    }
+
+    async fn update_cache(&self, _min_age: Option<u64>) -> Result<(), Errors> {
+        Err(self.capabilities().unsupported("mirroring"))
+    }
+}
@@ -1,12 +1,392 @@
 //! Repository hooks for PyPi
 //!
 //! API reference for PyPi - <https://warehouse.pypa.io/api-reference/>
+//!
+//! Indexes without the JSON API are read through the simple API, as HTML - <https://peps.python.org/pep-0503/>
+//! or JSON - <https://peps.python.org/pep-0691/>
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
 
+use super::compare_versions_by;
+use super::pip_config::{Netrc, PipConfig, PyPiIndex};
 use super::prelude::*;
+use crate::request::{parse_json, read_json};
+
+const SIMPLE_ACCEPT: &str =
+    "application/vnd.pypi.simple.v1+json, application/vnd.pypi.simple.v1+html;q=0.2, text/html;q=0.1";
 
 #[derive(Debug)]
 pub struct PyPi {
     pub cache: Arc<RwLock<Cache>>,
+    /// Searched in order, the first one with the package wins
+    pub indexes: Vec<PyPiIndex>,
+}
+
+impl PyPi {
+    /// Replaces the default index, which can have credentials in it
+    pub fn with_index_url(mut self, url: &str) -> Result<Self, Errors> {
+        self.indexes[0] = PyPiIndex::from_str(url)?;
+        Ok(self)
+    }
+
+    pub fn with_extra_index_url(mut self, url: &str) -> Result<Self, Errors> {
+        self.indexes.push(PyPiIndex::from_str(url)?);
+        Ok(self)
+    }
+
+    /// Uses the indexes from pip and uv's config, with credentials from `.netrc`
+    pub fn from_config(cache: Arc<RwLock<Cache>>) -> Result<Self, Errors> {
+        let indexes = PipConfig::load()?.indexes(&Netrc::load()?)?;
+        Ok(Self { cache, indexes })
+    }
+
+    async fn get(
+        &self,
+        index: &PyPiIndex,
+        url: &str,
+        accept: &str,
+    ) -> Result<reqwest::Response, Errors> {
+        let client = WebClient::for_repo_type(RepoType::PyPi);
+        let mut req = client.client.get(url).header(ACCEPT, accept);
+        if let Some(username) = index.username.as_ref() {
+            req = req.basic_auth(username, index.password.as_ref());
+        }
+        client.send(req).await
+    }
+
+    /// Try the JSON API, which lives next to `/simple` on PyPI and Warehouse-alikes. None
+    /// when the index hasn't got one, or it 404s, and the simple API's worth a go
+    async fn get_json_api(&self, index: &PyPiIndex, name: &str) -> Result<Option<Package>, Errors> {
+        let Some(base) = index.url.strip_suffix("/simple") else {
+            return Ok(None);
+        };
+        let url = format!("{}/pypi/{}/json", base, normalize_name(name));
+        match self
+            .get(index, &url, "application/json")
+            .await?
+            .check_status()
+        {
+            Ok(res) => Ok(Some(read_json::<PyPiPackage>(res).await?.into())),
+            Err(err) if err.is_not_found() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Read the project page from the simple API, one [Package] per version
+    async fn get_simple(&self, index: &PyPiIndex, name: &str) -> Result<Vec<Package>, Errors> {
+        let url = format!("{}/{}/", index.url, normalize_name(name));
+        let res = self
+            .get(index, &url, SIMPLE_ACCEPT)
+            .await?
+            .check_status()
+            .map_err(|err| err.or_not_found(RepoType::PyPi, name))?;
+        let is_json = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|content_type| content_type.contains("json"));
+        let page_url = res.url().clone();
+        let body = res.text().await?;
+        let files = match is_json {
+            true => parse_json::<SimpleProject>(page_url.as_str(), &body)?.files,
+            false => parse_simple_html(&body),
+        };
+        let files = files
+            .into_iter()
+            .map(|mut file| {
+                // hrefs can be relative to the project page
+                if let Ok(url) = page_url.join(&file.url) {
+                    file.url = url.to_string();
+                }
+                file
+            })
+            .collect();
+        Ok(simple_files_into_packages(name, &index.url, files))
+    }
+}
+
+/// PEP 503 normalisation, runs of `-`, `_` and `.` become a single `-`
+pub fn normalize_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '-' | '_' | '.' => {
+                if !normalized.ends_with('-') {
+                    normalized.push('-')
+                }
+            }
+            c => normalized.push(c.to_ascii_lowercase()),
+        }
+    }
+    normalized
+}
+
+/// A file from a simple API project page
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct SimpleFile {
+    pub filename: String,
+    pub url: String,
+    #[serde(default)]
+    pub hashes: HashMap<String, String>,
+    pub requires_python: Option<String>,
+    /// Either `true` or the reason, in the JSON form
+    pub yanked: Option<Value>,
+    pub upload_time: Option<DateTime<chrono::Utc>>,
+}
+
+impl SimpleFile {
+    fn is_yanked(&self) -> bool {
+        match self.yanked.as_ref() {
+            Some(Value::Bool(yanked)) => *yanked,
+            Some(Value::String(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Wheels are `name-version-...whl`, sdists are `name-version.tar.gz`
+    pub fn version(&self, project: &str) -> Option<String> {
+        if let Some(stem) = self.filename.strip_suffix(".whl") {
+            return stem.split('-').nth(1).map(String::from);
+        }
+        let stem = [".tar.gz", ".tar.bz2", ".tar.xz", ".zip", ".tgz", ".egg"]
+            .iter()
+            .find_map(|ext| self.filename.strip_suffix(ext))?;
+        let project = normalize_name(project);
+        // the name part can be spelled a few ways, so count its dashes once normalised
+        let name_parts = project.split('-').count();
+        let mut parts = stem.splitn(name_parts + 1, '-');
+        let name: Vec<&str> = parts.by_ref().take(name_parts).collect();
+        match normalize_name(&name.join("-")) == project {
+            true => parts
+                .next()
+                .map(|version| version.split('-').next().unwrap_or(version).to_string()),
+            false => stem
+                .rsplit_once('-')
+                .map(|(_, version)| version.to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SimpleProject {
+    files: Vec<SimpleFile>,
+}
+
+fn unescape_html(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Pull an attribute's value out of the inside of a tag
+fn html_attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!("{}=", name))? + name.len() + 1;
+    let rest = &tag[start..];
+    let value = match rest.chars().next()? {
+        quote @ ('"' | '\'') => rest[1..].split(quote).next()?,
+        _ => rest.split(|c: char| c.is_whitespace() || c == '>').next()?,
+    };
+    Some(unescape_html(value))
+}
+
+/// Pull the links out of a PEP 503 project page
+pub fn parse_simple_html(body: &str) -> Vec<SimpleFile> {
+    let mut files = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("<a ") {
+        rest = &rest[start + 3..];
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..tag_end];
+        let text_end = rest.find("</a>").unwrap_or(rest.len());
+        let text = rest.get(tag_end + 1..text_end).unwrap_or_default().trim();
+        let Some(href) = html_attribute(tag, "href") else {
+            continue;
+        };
+        let (url, fragment) = href.split_once('#').unwrap_or((&href, ""));
+        let hashes = fragment
+            .split_once('=')
+            .map(|(algo, hash)| HashMap::from([(algo.to_string(), hash.to_string())]))
+            .unwrap_or_default();
+        files.push(SimpleFile {
+            filename: unescape_html(text),
+            url: url.to_string(),
+            hashes,
+            requires_python: html_attribute(tag, "data-requires-python"),
+            yanked: html_attribute(tag, "data-yanked").map(Value::String),
+            upload_time: None,
+        });
+    }
+    files
+}
+
+/// Group a project's files by version, oldest first
+pub fn simple_files_into_packages(
+    name: &str,
+    index_url: &str,
+    files: Vec<SimpleFile>,
+) -> Vec<Package> {
+    let mut versions: HashMap<String, Vec<SimpleFile>> = HashMap::new();
+    for file in files {
+        if let Some(version) = file.version(name) {
+            versions.entry(version).or_default().push(file);
+        }
+    }
+    let mut versions: Vec<(String, Vec<SimpleFile>)> = versions.into_iter().collect();
+    versions.sort_by(|a, b| compare_versions(&a.0, &b.0));
+
+    versions
+        .into_iter()
+        .map(|(version, files)| {
+            let mut other_metadata: HashMap<String, Value> = HashMap::new();
+            other_metadata.insert("version".to_string(), Value::String(version));
+            other_metadata.insert("index".to_string(), Value::String(index_url.to_string()));
+            if files.iter().all(|file| file.is_yanked()) {
+                other_metadata.insert("yanked".to_string(), Value::Bool(true));
+            }
+            if let Some(requires_python) = files.iter().find_map(|f| f.requires_python.clone()) {
+                other_metadata.insert(
+                    "requires_python".to_string(),
+                    Value::String(requires_python),
+                );
+            }
+            if let Some(uploaded) = files.iter().filter_map(|f| f.upload_time).min() {
+                other_metadata.insert(
+                    "release_date".to_string(),
+                    Value::String(uploaded.to_rfc3339()),
+                );
+            }
+            let files: Vec<Value> = files
+                .iter()
+                .map(|file| {
+                    serde_json::json!({
+                        "filename": file.filename,
+                        "download_url": file.url,
+                        "checksum": file.hashes.get("sha256"),
+                    })
+                })
+                .collect();
+            other_metadata.insert("files".to_string(), Value::Array(files));
+
+            Package {
+                name: name.to_string(),
+                url: None,
+                owner: None,
+                other_metadata,
+                repo_type: RepoType::PyPi,
+            }
+        })
+        .collect()
+}
+
+/// Enough of PEP 440 to put versions in order, the field order is the sort order
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ParsedVersion {
+    epoch: u64,
+    release: Vec<u64>,
+    /// `1.0.dev1`, then alpha, beta and rc, then the release itself
+    pre: (u8, u64),
+    post: Option<u64>,
+    /// Dev releases come before whatever they're a dev release of
+    dev: (u8, u64),
+    local: String,
+}
+
+/// Take the number off the front of `rest`, `0` if there isn't one
+fn take_number(rest: &mut &str) -> u64 {
+    let trimmed = rest.trim_start_matches(['.', '-', '_']);
+    let end = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let number = trimmed[..end].parse().unwrap_or(0);
+    *rest = trimmed[end..].trim_start_matches(['.', '-', '_']);
+    number
+}
+
+fn parse_version(version: &str) -> Option<ParsedVersion> {
+    let version = version.trim().to_lowercase();
+    let version = version.strip_prefix('v').unwrap_or(&version);
+    let (version, local) = version.split_once('+').unwrap_or((version, ""));
+    let (epoch, version) = match version.split_once('!') {
+        Some((epoch, rest)) => (epoch.parse().ok()?, rest),
+        None => (0, version),
+    };
+
+    let release_end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+    let mut release = version[..release_end]
+        .trim_end_matches('.')
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    // 1.0 and 1.0.0 are the same version
+    while release.len() > 1 && release.last() == Some(&0) {
+        release.pop();
+    }
+
+    let mut rest = version[release_end..].trim_start_matches(['.', '-', '_']);
+    let mut pre = None;
+    for (prefix, rank) in [
+        ("alpha", 1),
+        ("beta", 2),
+        ("preview", 3),
+        ("pre", 3),
+        ("rc", 3),
+        ("a", 1),
+        ("b", 2),
+        ("c", 3),
+    ] {
+        if let Some(after) = rest.strip_prefix(prefix) {
+            rest = after;
+            pre = Some((rank, take_number(&mut rest)));
+            break;
+        }
+    }
+    let mut post = None;
+    for prefix in ["post", "rev", "r"] {
+        if let Some(after) = rest.strip_prefix(prefix) {
+            rest = after;
+            post = Some(take_number(&mut rest));
+            break;
+        }
+    }
+    let mut dev = (1, 0);
+    if let Some(after) = rest.strip_prefix("dev") {
+        rest = after;
+        dev = (0, take_number(&mut rest));
+    }
+    if !rest.is_empty() {
+        return None;
+    }
+
+    let pre = match (pre, post, dev.0) {
+        (Some(pre), _, _) => pre,
+        (None, None, 0) => (0, 0),
+        _ => (4, 0),
+    };
+    Some(ParsedVersion {
+        epoch,
+        release,
+        pre,
+        post,
+        dev,
+        local: local.to_string(),
+    })
+}
+
+/// PEP 440 ordering, with legacy versions it can't parse before all the ones it can
+pub fn compare_versions(left: &str, right: &str) -> Ordering {
+    compare_versions_by(left, right, parse_version)
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -86,34 +466,52 @@ impl Repository for PyPi {
     fn repo_type() -> RepoType {
         RepoType::PyPi
     }
-    fn new(cache: Arc<RwLock<Cache>>) -> Self {
-        Self { cache }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            package_detail: true,
+            versions: true,
+            downloads: true,
+            ..Capabilities::none(RepoType::PyPi)
+        }
     }
-    fn get_cache_dir(&self) -> String {
-        "pypi/".to_string()
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            indexes: vec![PyPiIndex::default()],
+        }
     }
 
+    /// PyPI turned its search API off, and the simple API doesn't have one
     async fn search(&mut self, _query: &str) -> Result<Vec<Package>, Errors> {
-        // make_cache_dir()?;
-        todo!()
+        Err(self.capabilities().unsupported("search"))
     }
 
+    /// Uses the JSON API where the index has it, otherwise the simple API
     async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
-        let client = WebClient::default();
-        let url = format!("https://pypi.org/pypi/{}/json", name);
-        let res = client.client.get(&url).send().await?;
-        let res_text = res.text().await?;
-        let package: PyPiPackage = serde_json::from_str(&res_text)?;
-
-        Ok(vec![package.into()])
+        for index in self.indexes.iter() {
+            if let Some(package) = self.get_json_api(index, name).await? {
+                return Ok(vec![package]);
+            }
+            match self.get_simple(index, name).await {
+                Ok(packages) if !packages.is_empty() => return Ok(packages),
+                Ok(_) => {}
+                // it might be on the next index
+                Err(err) if err.is_not_found() => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Err(Errors::NotFound {
+            repo_type: RepoType::PyPi,
+            name: name.to_string(),
+        })
     }
 
     async fn cacheable(&self) -> bool {
-        true
+        false
     }
 
     async fn update_cache(&self, _min_age: Option<u64>) -> Result<(), Errors> {
-        make_cache_dir()?;
-        todo!()
+        Err(self.capabilities().unsupported("mirroring"))
     }
 }
@@ -0,0 +1,408 @@
+//! Repository hooks for Terraform/OpenTofu registries, for modules and providers
+//!
+//! Service discovery - <https://developer.hashicorp.com/terraform/internals/remote-service-discovery>
+//!
+//! Module registry protocol - <https://developer.hashicorp.com/terraform/internals/module-registry-protocol>
+//!
+//! Provider registry protocol - <https://developer.hashicorp.com/terraform/internals/provider-registry-protocol>
+
+use std::str::FromStr;
+
+use super::compare_semver;
+use super::prelude::*;
+use super::PackageVersion;
+use crate::request::parse_json;
+
+const TERRAFORM_REGISTRY_URL: &str = "https://registry.terraform.io";
+pub const OPENTOFU_REGISTRY_URL: &str = "https://registry.opentofu.org";
+
+#[derive(Debug)]
+pub struct Terraform {
+    pub cache: Arc<RwLock<Cache>>,
+    pub registry: String,
+}
+
+impl Terraform {
+    /// Takes a hostname like `registry.opentofu.org`, or a full base URL
+    pub fn with_registry(mut self, registry: &str) -> Self {
+        self.registry = match registry.contains("://") {
+            true => registry.trim_end_matches('/').to_string(),
+            false => format!("https://{}", registry.trim_end_matches('/')),
+        };
+        self
+    }
+
+    fn discovery_url(&self) -> String {
+        format!("{}/.well-known/terraform.json", self.registry)
+    }
+
+    async fn fetch_discovery(&self, url: &str) -> Result<String, Errors> {
+        let content = WebClient::for_repo_type(RepoType::Terraform)
+            .get_text(url)
+            .await?;
+        self.cache.read().await.save(
+            CacheData::new(url.to_string(), String::new(), content.clone())
+                .with_kind(RepoType::Terraform, ResourceKind::Metadata),
+        )?;
+        Ok(content)
+    }
+
+    /// Find where the registry keeps a service, eg `modules.v1`, from the cache if we've got it
+    async fn service_url(&self, service: &str) -> Result<reqwest::Url, Errors> {
+        let url = self.discovery_url();
+        let cached = self
+            .cache
+            .read()
+            .await
+            .get_fresh_json(RepoType::Terraform, &url);
+        let discovery: HashMap<String, Value> = match cached {
+            Some(discovery) => discovery,
+            None => parse_json(&url, &self.fetch_discovery(&url).await?)?,
+        };
+        let base = discovery
+            .get(service)
+            .and_then(|value| value.as_str())
+            .ok_or_else(|| Errors::Unsupported {
+                repo_type: RepoType::Terraform,
+                operation: format!("{} on {}", service, self.registry),
+            })?;
+        // service URLs can be relative to the discovery document
+        let base = match base.ends_with('/') {
+            true => base.to_string(),
+            false => format!("{}/", base),
+        };
+        Ok(reqwest::Url::parse(&url).and_then(|url| url.join(&base))?)
+    }
+
+    async fn get_json<T: for<'de> Deserialize<'de>>(
+        &self,
+        service: &str,
+        path: &str,
+    ) -> Result<T, Errors> {
+        let url = self.service_url(service).await?.join(path)?;
+        WebClient::for_repo_type(RepoType::Terraform)
+            .get_json(url.as_str())
+            .await
+    }
+
+    /// All the versions of a module, oldest first
+    pub async fn module_versions(&self, address: &ModuleAddress) -> Result<Vec<Package>, Errors> {
+        let response: ModuleVersionsResponse = self
+            .get_json("modules.v1", &format!("{}/versions", address))
+            .await?;
+        let mut versions: Vec<ModuleVersion> = response
+            .modules
+            .into_iter()
+            .flat_map(|module| module.versions)
+            .collect();
+        versions.sort_by(|a, b| compare_semver(&a.version, &b.version));
+        Ok(versions
+            .into_iter()
+            .map(|version| version.into_package(address))
+            .collect())
+    }
+
+    /// All the versions of a provider, oldest first
+    pub async fn provider_versions(
+        &self,
+        address: &ProviderAddress,
+    ) -> Result<Vec<Package>, Errors> {
+        let mut response: ProviderVersionsResponse = self
+            .get_json("providers.v1", &format!("{}/versions", address))
+            .await?;
+        response
+            .versions
+            .sort_by(|a, b| compare_semver(&a.version, &b.version));
+        Ok(response
+            .versions
+            .into_iter()
+            .map(|version| version.into_package(address))
+            .collect())
+    }
+}
+
+/// `namespace/name/provider`, eg `terraform-aws-modules/vpc/aws`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleAddress {
+    pub namespace: String,
+    pub name: String,
+    pub provider: String,
+}
+
+impl std::fmt::Display for ModuleAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.namespace, self.name, self.provider)
+    }
+}
+
+/// `namespace/type`, eg `hashicorp/aws`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderAddress {
+    pub namespace: String,
+    pub provider_type: String,
+}
+
+impl std::fmt::Display for ProviderAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.namespace, self.provider_type)
+    }
+}
+
+/// What a name passed to [Terraform::get_package] refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerraformAddress {
+    Module(ModuleAddress),
+    Provider(ProviderAddress),
+}
+
+impl FromStr for TerraformAddress {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('/').collect();
+        if parts.iter().any(|part| part.is_empty()) {
+            return Err(Errors::InvalidInput(format!(
+                "Invalid registry address: {}",
+                s
+            )));
+        }
+        match parts.as_slice() {
+            [namespace, name, provider] => Ok(Self::Module(ModuleAddress {
+                namespace: namespace.to_string(),
+                name: name.to_string(),
+                provider: provider.to_string(),
+            })),
+            [namespace, provider_type] => Ok(Self::Provider(ProviderAddress {
+                namespace: namespace.to_string(),
+                provider_type: provider_type.to_string(),
+            })),
+            _ => Err(Errors::InvalidInput(format!(
+                "Expected namespace/name/provider or namespace/type, got {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ModuleProviderRequirement {
+    pub name: String,
+    #[serde(default)]
+    pub namespace: String,
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ModuleRoot {
+    #[serde(default)]
+    pub providers: Vec<ModuleProviderRequirement>,
+    #[serde(default)]
+    pub dependencies: Vec<Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ModuleVersion {
+    pub version: String,
+    /// Only the public registry fills this in
+    pub root: Option<ModuleRoot>,
+    #[serde(default)]
+    pub submodules: Vec<Value>,
+}
+
+impl ModuleVersion {
+    pub fn into_package(self, address: &ModuleAddress) -> Package {
+        let mut package = PackageVersion {
+            name: address.to_string(),
+            url: None,
+            version: self.version,
+            owner: Some(address.namespace.clone()),
+            release_date: None,
+        }
+        .into_package(RepoType::Terraform);
+
+        let metadata = &mut package.other_metadata;
+        metadata.insert("kind".to_string(), Value::String("module".to_string()));
+        if let Some(root) = self.root {
+            if !root.providers.is_empty() {
+                metadata.insert(
+                    "dependencies".to_string(),
+                    serde_json::to_value(&root.providers).unwrap_or_default(),
+                );
+            }
+            if !root.dependencies.is_empty() {
+                metadata.insert(
+                    "module_dependencies".to_string(),
+                    Value::from(root.dependencies),
+                );
+            }
+        }
+        if !self.submodules.is_empty() {
+            metadata.insert("submodules".to_string(), Value::from(self.submodules));
+        }
+        package
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ModuleVersionsEntry {
+    #[serde(default)]
+    versions: Vec<ModuleVersion>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ModuleVersionsResponse {
+    modules: Vec<ModuleVersionsEntry>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ProviderPlatform {
+    pub os: String,
+    pub arch: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ProviderVersion {
+    pub version: String,
+    /// Plugin protocol versions, eg `5.0`
+    #[serde(default)]
+    pub protocols: Vec<String>,
+    #[serde(default)]
+    pub platforms: Vec<ProviderPlatform>,
+}
+
+impl ProviderVersion {
+    pub fn into_package(self, address: &ProviderAddress) -> Package {
+        let mut package = PackageVersion {
+            name: address.to_string(),
+            url: None,
+            version: self.version,
+            owner: Some(address.namespace.clone()),
+            release_date: None,
+        }
+        .into_package(RepoType::Terraform);
+
+        let metadata = &mut package.other_metadata;
+        metadata.insert("kind".to_string(), Value::String("provider".to_string()));
+        if !self.protocols.is_empty() {
+            metadata.insert("protocols".to_string(), Value::from(self.protocols));
+        }
+        if !self.platforms.is_empty() {
+            let platforms: Vec<String> = self
+                .platforms
+                .iter()
+                .map(|platform| format!("{}_{}", platform.os, platform.arch))
+                .collect();
+            metadata.insert("platforms".to_string(), Value::from(platforms));
+        }
+        package
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ProviderVersionsResponse {
+    #[serde(default)]
+    versions: Vec<ProviderVersion>,
+}
+
+/// A result from the module search endpoint
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ModuleSearchResult {
+    pub namespace: String,
+    pub name: String,
+    pub provider: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub source: Option<String>,
+    pub published_at: Option<DateTime<chrono::Utc>>,
+    pub downloads: Option<u64>,
+    #[serde(default)]
+    pub verified: bool,
+}
+
+impl From<ModuleSearchResult> for Package {
+    fn from(value: ModuleSearchResult) -> Self {
+        let mut package = PackageVersion {
+            name: format!("{}/{}/{}", value.namespace, value.name, value.provider),
+            url: value.source,
+            version: value.version,
+            owner: Some(value.namespace),
+            release_date: value.published_at,
+        }
+        .into_package(RepoType::Terraform);
+
+        let metadata = &mut package.other_metadata;
+        metadata.insert("kind".to_string(), Value::String("module".to_string()));
+        if let Some(description) = value.description {
+            metadata.insert("description".to_string(), Value::String(description));
+        }
+        if let Some(downloads) = value.downloads {
+            metadata.insert("downloads".to_string(), Value::from(downloads));
+        }
+        metadata.insert("verified".to_string(), Value::Bool(value.verified));
+        package
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ModuleSearchResponse {
+    modules: Vec<ModuleSearchResult>,
+}
+
+#[async_trait]
+impl Repository for Terraform {
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        Self {
+            cache,
+            registry: TERRAFORM_REGISTRY_URL.to_string(),
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        RepoType::Terraform
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search: true,
+            package_detail: true,
+            versions: true,
+            dependencies: true,
+            ..Capabilities::none(RepoType::Terraform)
+        }
+    }
+
+    /// Searches modules, the registry protocol doesn't have a provider search
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        // service URLs always end in a slash
+        let mut url = self.service_url("modules.v1").await?.join("search")?;
+        url.query_pairs_mut().append_pair("q", query);
+        let response: ModuleSearchResponse = WebClient::for_repo_type(RepoType::Terraform)
+            .get_json(url.as_str())
+            .await?;
+        Ok(response
+            .modules
+            .into_iter()
+            .map(|module| module.into())
+            .collect())
+    }
+
+    /// Takes `namespace/name/provider` for a module, or `namespace/type` for a provider
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        match TerraformAddress::from_str(name)? {
+            TerraformAddress::Module(address) => self.module_versions(&address).await,
+            TerraformAddress::Provider(address) => self.provider_versions(&address).await,
+        }
+        .map_err(|err| err.or_not_found(RepoType::Terraform, name))
+    }
+
+    async fn cacheable(&self) -> bool {
+        false
+    }
+
+    async fn update_cache(&self, _min_age: Option<u64>) -> Result<(), Errors> {
+        Err(self.capabilities().unsupported("mirroring"))
+    }
+}
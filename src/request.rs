@@ -1,18 +1,465 @@
-use reqwest::Client;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{Errors, RepoType};
 
 const USER_AGENT: &str = concat!("tidetrawler/", env!("CARGO_PKG_VERSION"));
 
+/// The client everything shares unless it asks for its own, so connections get reused
+static SHARED_CLIENT: OnceLock<WebClient> = OnceLock::new();
+
+/// How hard to try before giving up on a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, zero turns retrying off
+    pub max_retries: u32,
+    /// The first backoff is up to this, doubling each time
+    pub base_delay: Duration,
+    /// The most we'll wait between attempts, a `Retry-After` longer than this isn't worth waiting for
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// "Full jitter" - somewhere between nothing and `base_delay * 2^attempt`, capped at `max_delay`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        // RandomState is seeded randomly, which is all the randomness we need here
+        let random = RandomState::new().build_hasher().finish();
+        ceiling.mul_f64((random % 1_000_000) as f64 / 1_000_000.0)
+    }
+}
+
+/// How often we'll hit one host, as a token bucket
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// How fast the bucket refills
+    pub per_second: f64,
+    /// How many requests can go at once after a quiet spell
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            per_second: 5.0,
+            burst: 10,
+        }
+    }
+}
+
+impl RateLimit {
+    /// Whether the bucket ever refills, anything else can't be waited out
+    pub fn is_valid(&self) -> bool {
+        self.per_second.is_finite() && self.per_second > 0.0
+    }
+
+    /// What each registry asks for, or what seems polite where they don't say
+    pub fn for_repo_type(repo_type: RepoType) -> Self {
+        match repo_type {
+            // https://crates.io/data-access#api
+            RepoType::Cargo => Self {
+                per_second: 1.0,
+                burst: 1,
+            },
+            // the API allows 5000 an hour with a token, 60 without
+            RepoType::GitHub => Self {
+                per_second: 1.0,
+                burst: 5,
+            },
+            // Docker Hub counts manifest requests against pull limits
+            RepoType::Oci => Self {
+                per_second: 2.0,
+                burst: 5,
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    /// Goes negative when requests are queued up waiting
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        // settings can be made by hand without going through the config's checks
+        let limit = match limit.is_valid() {
+            true => limit,
+            false => RateLimit::default(),
+        };
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Take a token, returning how long to wait until it's actually ours
+    fn take(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.updated = now;
+        self.tokens -= 1.0;
+        match self.tokens >= 0.0 {
+            true => Duration::ZERO,
+            false => Duration::try_from_secs_f64(-self.tokens / self.limit.per_second)
+                .unwrap_or(Duration::MAX),
+        }
+    }
+}
+
+/// Per-host token buckets, shared by every [WebClient] cloned from the same one
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+    /// By host, these win over what the client asks for
+    overrides: HashMap<String, RateLimit>,
+    /// By ecosystem, for whichever host that ecosystem's client talks to
+    ecosystems: HashMap<RepoType, RateLimit>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        overrides: HashMap<String, RateLimit>,
+        ecosystems: HashMap<RepoType, RateLimit>,
+    ) -> Self {
+        Self {
+            buckets: Mutex::default(),
+            overrides,
+            ecosystems,
+        }
+    }
+
+    /// Wait for our turn to talk to `host`, `limit` is used if it's the first we've heard of it
+    pub async fn acquire(&self, host: &str, limit: RateLimit) {
+        let wait = match self.buckets.lock() {
+            Ok(mut buckets) => buckets
+                .entry(host.to_string())
+                .or_insert_with(|| {
+                    TokenBucket::new(self.overrides.get(host).copied().unwrap_or(limit))
+                })
+                .take(Instant::now()),
+            Err(_) => Duration::ZERO,
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Timeouts, retries and rate limits for building a [WebClient]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientSettings {
+    /// For the whole request, including reading the body
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    pub retry: RetryPolicy,
+    /// By host, over the top of the per-[RepoType] defaults
+    pub rate_limits: HashMap<String, RateLimit>,
+    /// By ecosystem, over the top of [RateLimit::for_repo_type], and under `rate_limits`
+    pub ecosystem_rate_limits: HashMap<RepoType, RateLimit>,
+    /// For everything, otherwise reqwest uses `HTTPS_PROXY` and friends
+    pub proxy: Option<String>,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            // some of the indexes we mirror are big
+            timeout: Duration::from_secs(300),
+            connect_timeout: Duration::from_secs(10),
+            retry: RetryPolicy::default(),
+            rate_limits: HashMap::new(),
+            ecosystem_rate_limits: HashMap::new(),
+            proxy: None,
+        }
+    }
+}
+
+impl ClientSettings {
+    fn build_client(&self) -> Result<Client, Errors> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .gzip(true)
+            .timeout(self.timeout)
+            .connect_timeout(self.connect_timeout);
+        if let Some(proxy) = self.proxy.as_ref() {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct WebClient {
     pub client: Client,
+    pub retry: RetryPolicy,
+    pub limiter: Arc<RateLimiter>,
+    /// For hosts without an override
+    pub rate_limit: RateLimit,
 }
 
 impl Default for WebClient {
+    /// The shared client, cheap to make as many of as you like
     fn default() -> Self {
-        let client = reqwest::Client::builder()
-            .user_agent(USER_AGENT)
-            .gzip(true)
-            .build()
-            .unwrap();
-        Self { client }
+        SHARED_CLIENT
+            .get_or_init(|| {
+                Self::new(&ClientSettings::default()).expect("the default settings are valid")
+            })
+            .clone()
+    }
+}
+
+impl WebClient {
+    /// A client of its own, not sharing connections or rate limits with [WebClient::default]
+    pub fn new(settings: &ClientSettings) -> Result<Self, Errors> {
+        Ok(Self {
+            client: settings.build_client()?,
+            retry: settings.retry,
+            limiter: Arc::new(RateLimiter::new(
+                settings.rate_limits.clone(),
+                settings.ecosystem_rate_limits.clone(),
+            )),
+            rate_limit: RateLimit::default(),
+        })
+    }
+
+    /// The shared client, limited to what's polite for that kind of registry
+    pub fn for_repo_type(repo_type: RepoType) -> Self {
+        Self::default().with_repo_type(repo_type)
+    }
+
+    /// Limited to what the settings say for that kind of registry, or what's polite for it
+    pub fn with_repo_type(self, repo_type: RepoType) -> Self {
+        let rate_limit = self
+            .limiter
+            .ecosystems
+            .get(&repo_type)
+            .copied()
+            .unwrap_or_else(|| RateLimit::for_repo_type(repo_type));
+        self.with_rate_limit(rate_limit)
+    }
+
+    /// Set up the shared client, which only works before anything has used it
+    pub fn configure(settings: &ClientSettings) -> Result<bool, Errors> {
+        Ok(SHARED_CLIENT.set(Self::new(settings)?).is_ok())
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Wait for the rate limiter, then send it
+    async fn execute(&self, req: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let req = req.build()?;
+        if let Some(host) = req.url().host_str() {
+            let host = match req.url().port() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host.to_string(),
+            };
+            self.limiter.acquire(&host, self.rate_limit).await;
+        }
+        self.client.execute(req).await
+    }
+
+    /// Send a request, retrying connection failures, timeouts, 5xx and 429s
+    ///
+    /// Doesn't check the final status, so callers can deal with 404s and the like.
+    pub async fn send(&self, req: RequestBuilder) -> Result<Response, Errors> {
+        let mut attempt = 0;
+        loop {
+            // bodies that are streams can't be cloned, so they only get one go
+            let Some(this_try) = req.try_clone().filter(|_| attempt < self.retry.max_retries)
+            else {
+                return Ok(self.execute(req).await?);
+            };
+            let (delay, reason) = match self.execute(this_try).await {
+                Ok(res) => match retry_delay(&res, &self.retry, attempt) {
+                    Some(delay) => (delay, format!("{} from {}", res.status(), res.url())),
+                    None => return Ok(res),
+                },
+                Err(err) if err.is_connect() || err.is_timeout() => {
+                    (self.retry.backoff(attempt), err.to_string())
+                }
+                Err(err) => return Err(err.into()),
+            };
+            attempt += 1;
+            eprintln!(
+                "Retrying ({}/{}) in {:.1}s after {}",
+                attempt,
+                self.retry.max_retries,
+                delay.as_secs_f64(),
+                reason
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// GET a URL and return the body, erroring on a non-success status
+    pub async fn get_bytes(&self, url: &str) -> Result<Vec<u8>, Errors> {
+        let res = self.send(self.client.get(url)).await?.check_status()?;
+        Ok(res.bytes().await?.to_vec())
+    }
+
+    /// GET a URL and return the body as text, erroring on a non-success status
+    pub async fn get_text(&self, url: &str) -> Result<String, Errors> {
+        let res = self.send(self.client.get(url)).await?.check_status()?;
+        Ok(res.text().await?)
+    }
+
+    /// GET a URL and parse the body as JSON, parse errors say where they came from
+    pub async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, Errors> {
+        parse_json(url, &self.get_text(url).await?)
+    }
+}
+
+/// Pull the `rel="next"` URL out of a `Link` header
+pub fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let (url, params) = link.split_once(';')?;
+        params
+            .split(';')
+            .any(|p| p.trim().replace(' ', "") == "rel=\"next\"")
+            .then(|| {
+                url.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+/// How long to wait before trying again, if the response is worth trying again
+fn retry_delay(res: &Response, policy: &RetryPolicy, attempt: u32) -> Option<Duration> {
+    let status = res.status();
+    if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+        return None;
+    }
+    let retry_after = res
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|h| h.to_str().ok())
+        .and_then(parse_retry_after);
+    match retry_after {
+        // no point waiting ages, let the caller see the RateLimited
+        Some(retry_after) if retry_after > policy.max_delay => None,
+        Some(retry_after) => Some(retry_after),
+        None => Some(policy.backoff(attempt)),
+    }
+}
+
+/// The error for an unsuccessful status, `retry_after` being the header if there was one
+pub fn status_error(url: String, status: StatusCode, retry_after: Option<&str>) -> Errors {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Errors::Unauthorized { url },
+        StatusCode::TOO_MANY_REQUESTS => Errors::RateLimited {
+            url,
+            retry_after: retry_after.and_then(parse_retry_after),
+        },
+        // a 503 with Retry-After is the server asking us to back off too
+        StatusCode::SERVICE_UNAVAILABLE if retry_after.is_some() => Errors::RateLimited {
+            url,
+            retry_after: retry_after.and_then(parse_retry_after),
+        },
+        status => Errors::Http { url, status },
     }
 }
+
+/// `Retry-After` is either a number of seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // a date in the past means go ahead now
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Like [Response::error_for_status], but keeps the details we need to pick the right [Errors]
+pub trait CheckStatus: Sized {
+    fn check_status(self) -> Result<Self, Errors>;
+}
+
+impl CheckStatus for Response {
+    fn check_status(self) -> Result<Self, Errors> {
+        let status = self.status();
+        if status.is_success() {
+            return Ok(self);
+        }
+        let retry_after = self
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|h| h.to_str().ok());
+        Err(status_error(self.url().to_string(), status, retry_after))
+    }
+}
+
+/// Read a response's body as JSON, through [parse_json] so errors say where it came from
+pub async fn read_json<T: DeserializeOwned>(res: Response) -> Result<T, Errors> {
+    let url = res.url().to_string();
+    parse_json(&url, &res.text().await?)
+}
+
+/// Parse JSON fetched from `url`, turning serde's line and column into a byte offset
+pub fn parse_json<T: DeserializeOwned>(url: &str, body: &str) -> Result<T, Errors> {
+    serde_json::from_str(body).map_err(|err| {
+        let offset = match err.line() {
+            0 => None,
+            line => Some(
+                body.split_inclusive('\n')
+                    .take(line - 1)
+                    .map(str::len)
+                    .sum::<usize>()
+                    + err.column().saturating_sub(1),
+            ),
+        };
+        Errors::Parse {
+            url: Some(url.to_string()),
+            offset,
+            source: Box::new(err),
+        }
+    })
+}
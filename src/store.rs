@@ -0,0 +1,305 @@
+//! Every package we've fetched, in SQLite, so they can be queried across ecosystems
+//!
+//! The cache only knows URLs, this knows packages. Each [Package] record becomes a row in
+//! `versions`, under a row in `packages` for its name, and [Store::query] filters on the newest
+//! version of each package. Only built with the `store` feature.
+//!
+//! The [Orchestrator](crate::orchestrator::Orchestrator) records what it gets back once it's given
+//! a store with `with_store`. A backend used on its own records nothing unless it's wrapped in a
+//! [Recorded].
+
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection};
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use crate::cache::Cache;
+use crate::repo::{Capabilities, Package, Repository};
+use crate::{get_cache_dir, Errors, RepoType};
+
+/// In the cache dir, next to the ecosystem directories
+pub const STORE_FILE: &str = "metadata.sqlite";
+/// Bumped when the tables change, an older database gets them dropped and recreated
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE packages (
+    ecosystem TEXT NOT NULL,
+    name TEXT NOT NULL,
+    url TEXT,
+    owner TEXT,
+    description TEXT,
+    fetched TEXT NOT NULL,
+    PRIMARY KEY (ecosystem, name)
+);
+CREATE TABLE versions (
+    ecosystem TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- empty when the record didn't say
+    version TEXT NOT NULL,
+    release_date TEXT,
+    license TEXT,
+    -- the rest of the record's other_metadata, as JSON
+    metadata TEXT NOT NULL,
+    fetched TEXT NOT NULL,
+    PRIMARY KEY (ecosystem, name, version),
+    FOREIGN KEY (ecosystem, name) REFERENCES packages (ecosystem, name) ON DELETE CASCADE
+);
+CREATE INDEX versions_release_date ON versions (release_date);
+-- the most recently released version of each package, or the last fetched when there are no dates
+CREATE VIEW latest AS
+SELECT v.* FROM versions v
+WHERE v.rowid = (
+    SELECT w.rowid FROM versions w
+    WHERE w.ecosystem = v.ecosystem AND w.name = v.name
+    ORDER BY w.release_date IS NULL, w.release_date DESC, w.fetched DESC
+    LIMIT 1
+);
+";
+
+/// What to look for, everything matching when nothing's set
+#[derive(Debug, Default, Clone)]
+pub struct StoreQuery {
+    pub ecosystem: Option<RepoType>,
+    /// Part of the name
+    pub name: Option<String>,
+    /// Part of the license, ignoring case, so `GPL` finds `GPL-3.0-only` and `LGPL-2.1` alike
+    pub license: Option<String>,
+    /// Newest version released on or after this
+    pub since: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    /// Open it, creating the tables the first time
+    pub fn open(path: &Path) -> Result<Self, Errors> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Self::setup(Connection::open(path)?)
+    }
+
+    /// Nothing on disk, for tests
+    pub fn in_memory() -> Result<Self, Errors> {
+        Self::setup(Connection::open_in_memory()?)
+    }
+
+    /// [STORE_FILE] in the cache dir
+    pub fn open_default() -> Result<Self, Errors> {
+        Self::open(&get_cache_dir().join(STORE_FILE))
+    }
+
+    fn setup(conn: Connection) -> Result<Self, Errors> {
+        // several processes can be writing at once, the same as with the cache
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version != SCHEMA_VERSION {
+            // it's all fetched again easily enough, so there's nothing to migrate
+            conn.execute_batch(
+                "DROP VIEW IF EXISTS latest;
+                 DROP TABLE IF EXISTS versions;
+                 DROP TABLE IF EXISTS packages;",
+            )?;
+            conn.execute_batch(SCHEMA)?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Save what a backend handed back, replacing what was there for the same versions
+    pub fn record(&self, packages: &[Package]) -> Result<(), Errors> {
+        let mut conn = self.conn.lock().unwrap_or_else(|err| err.into_inner());
+        let tx = conn.transaction()?;
+        let fetched = Utc::now().to_rfc3339();
+        for package in packages {
+            let ecosystem = package.repo_type.name();
+            let field = |key: &str| package.other_metadata.get(key).and_then(as_text);
+            tx.execute(
+                "INSERT INTO packages (ecosystem, name, url, owner, description, fetched)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (ecosystem, name) DO UPDATE SET
+                     url = coalesce(excluded.url, url),
+                     owner = coalesce(excluded.owner, owner),
+                     description = coalesce(excluded.description, description),
+                     fetched = excluded.fetched",
+                params![
+                    ecosystem,
+                    package.name,
+                    package.url,
+                    package.owner,
+                    field("description"),
+                    fetched
+                ],
+            )?;
+            let release_date = field("release_date")
+                .and_then(|date| DateTime::parse_from_rfc3339(&date).ok())
+                .map(|date| sortable(date.with_timezone(&Utc)));
+            tx.execute(
+                "INSERT OR REPLACE INTO versions
+                     (ecosystem, name, version, release_date, license, metadata, fetched)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    ecosystem,
+                    package.name,
+                    field("version").unwrap_or_default(),
+                    release_date,
+                    field("license"),
+                    serde_json::to_string(&package.other_metadata)?,
+                    fetched
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The newest version of each matching package, by ecosystem and then name
+    pub fn query(&self, query: &StoreQuery) -> Result<Vec<Package>, Errors> {
+        let conn = self.conn.lock().unwrap_or_else(|err| err.into_inner());
+        let mut statement = conn.prepare(
+            "SELECT p.ecosystem, p.name, p.url, p.owner, l.metadata
+             FROM packages p JOIN latest l ON l.ecosystem = p.ecosystem AND l.name = p.name
+             WHERE (?1 IS NULL OR p.ecosystem = ?1)
+               AND (?2 IS NULL OR p.name LIKE '%' || ?2 || '%')
+               AND (?3 IS NULL OR l.license LIKE '%' || ?3 || '%')
+               AND (?4 IS NULL OR l.release_date >= ?4)
+             ORDER BY p.ecosystem, p.name
+             LIMIT ?5",
+        )?;
+        let rows = statement.query_map(
+            params![
+                query.ecosystem.map(|repo_type| repo_type.name()),
+                query.name,
+                query.license,
+                query.since.map(sortable),
+                query.limit.map_or(-1, |limit| limit as i64)
+            ],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            },
+        )?;
+        let mut packages = Vec::new();
+        for row in rows {
+            let (ecosystem, name, url, owner, metadata) = row?;
+            packages.push(Package {
+                name,
+                url,
+                owner,
+                other_metadata: serde_json::from_str(&metadata)?,
+                repo_type: RepoType::from_str(&ecosystem)?,
+            });
+        }
+        Ok(packages)
+    }
+}
+
+/// A backend that puts what it finds in a [Store], for using one without an orchestrator
+pub struct Recorded<R> {
+    pub repository: R,
+    pub store: Option<Arc<Store>>,
+}
+
+impl<R: Repository> Recorded<R> {
+    pub fn wrap(repository: R, store: Arc<Store>) -> Self {
+        Self {
+            repository,
+            store: Some(store),
+        }
+    }
+
+    /// A store that can't be written to doesn't stop the backend answering
+    fn record(&self, packages: &[Package]) {
+        if let Some(store) = self.store.as_ref() {
+            if let Err(err) = store.record(packages) {
+                eprintln!("Error recording packages in the metadata store: {}", err);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<R: Repository + Send + Sync> Repository for Recorded<R> {
+    /// With the store next to the cache's entries
+    fn new(cache: Arc<RwLock<Cache>>) -> Self {
+        let path = match cache.try_read() {
+            Ok(cache) => cache.cache_dir.join(STORE_FILE),
+            Err(_) => get_cache_dir().join(STORE_FILE),
+        };
+        let store = match Store::open(&path) {
+            Ok(store) => Some(Arc::new(store)),
+            Err(err) => {
+                eprintln!("Error opening the metadata store: {}", err);
+                None
+            }
+        };
+        Self {
+            repository: R::new(cache),
+            store,
+        }
+    }
+
+    fn repo_type() -> RepoType {
+        R::repo_type()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.repository.capabilities()
+    }
+
+    async fn search(&mut self, query: &str) -> Result<Vec<Package>, Errors> {
+        let packages = self.repository.search(query).await?;
+        self.record(&packages);
+        Ok(packages)
+    }
+
+    async fn get_package(&mut self, name: &str) -> Result<Vec<Package>, Errors> {
+        let packages = self.repository.get_package(name).await?;
+        self.record(&packages);
+        Ok(packages)
+    }
+
+    async fn cacheable(&self) -> bool {
+        self.repository.cacheable().await
+    }
+
+    async fn update_cache(&self, min_age: Option<u64>) -> Result<(), Errors> {
+        self.repository.update_cache(min_age).await
+    }
+
+    fn get_cache_dir(&self) -> String {
+        self.repository.get_cache_dir()
+    }
+}
+
+/// The same length and timezone every time, so dates compare as strings
+fn sortable(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Strings as they are, anything else that isn't null as JSON
+fn as_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(value) => Some(value.clone()),
+        value => Some(value.to_string()),
+    }
+}
@@ -1,2 +1,24 @@
+mod test_alpine;
+mod test_cache;
+mod test_cargo_config;
+mod test_conda;
+mod test_config;
+mod test_cpan;
+mod test_errors;
+mod test_github;
+mod test_hackage;
+mod test_helm;
+mod test_hex;
+mod test_homebrew;
 mod test_npm;
+mod test_npmrc;
+mod test_oci;
+mod test_orchestrator;
+mod test_packagist;
+mod test_pip_config;
+mod test_pubdev;
 mod test_pypi;
+mod test_request;
+#[cfg(feature = "store")]
+mod test_store;
+mod test_terraform;
@@ -0,0 +1,67 @@
+use std::cmp::Ordering;
+
+use crate::repo::alpine::{compare_versions, extract_apkindex, parse_apkindex};
+use crate::repo::Package;
+
+#[test]
+fn test_parse_apkindex() {
+    let entries = parse_apkindex(include_str!("data/alpine-APKINDEX").as_bytes()).unwrap();
+    assert_eq!(entries.len(), 4);
+
+    let curl = &entries[1];
+    assert_eq!(curl.name, "curl");
+    assert_eq!(curl.version, "8.5.0-r0");
+    assert_eq!(curl.license.as_deref(), Some("curl"));
+    assert_eq!(curl.depends.len(), 4);
+    assert_eq!(curl.provides, vec!["cmd:curl=8.5.0-r0".to_string()]);
+    assert!(curl.build_time.is_some());
+
+    let package: Package = curl.clone().into();
+    assert_eq!(
+        package.owner.as_deref(),
+        Some("Natanael Copa <ncopa@alpinelinux.org>")
+    );
+}
+
+#[test]
+fn test_extract_apkindex() {
+    let index = include_bytes!("data/alpine-APKINDEX");
+
+    // signature and index are separate gzip streams, concatenated
+    let mut body = Vec::new();
+    for (name, content) in [
+        (".SIGN.RSA.alpine.rsa.pub", &b"sig"[..]),
+        ("APKINDEX", index),
+    ] {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, name, content).unwrap();
+        let tarball = builder.into_inner().unwrap();
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &tarball).unwrap();
+        body.extend(encoder.finish().unwrap());
+    }
+
+    let content = extract_apkindex(&body).unwrap();
+    assert_eq!(parse_apkindex(content.as_bytes()).unwrap().len(), 4);
+}
+
+#[test]
+fn test_alpine_version_ordering() {
+    assert_eq!(compare_versions("1.2.3-r0", "1.2.3-r1"), Ordering::Less);
+    assert_eq!(compare_versions("1.2.3_rc1-r0", "1.2.3-r0"), Ordering::Less);
+    assert_eq!(
+        compare_versions("1.2.3_p1-r0", "1.2.3-r0"),
+        Ordering::Greater
+    );
+    assert_eq!(
+        compare_versions("1.2.3_alpha2", "1.2.3_beta1"),
+        Ordering::Less
+    );
+    assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+    assert_eq!(compare_versions("1.2a", "1.2"), Ordering::Greater);
+    assert_eq!(compare_versions("1.2.3-r2", "1.2.3-r2"), Ordering::Equal);
+}
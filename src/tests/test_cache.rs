@@ -0,0 +1,530 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use chrono::Duration;
+use sha2::Digest;
+
+use crate::cache::{Cache, CacheData, CachePolicy, ResourceKind};
+use crate::RepoType;
+
+fn temp_cache(name: &str) -> (PathBuf, Cache) {
+    let dir = std::env::temp_dir().join(format!("tidetrawler-{}-{}", name, std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    (dir.clone(), Cache::new(dir))
+}
+
+fn aged(url: &str, repo_type: RepoType, kind: ResourceKind, age: Duration) -> CacheData {
+    let mut data =
+        CacheData::new(url.to_string(), String::new(), "{}".to_string()).with_kind(repo_type, kind);
+    data.updated -= age;
+    data
+}
+
+#[test]
+fn test_policy() {
+    let mut policy = CachePolicy::default();
+    policy
+        .overrides
+        .insert((RepoType::Npm, ResourceKind::Search), Duration::seconds(30));
+    assert_eq!(
+        policy.max_age(Some(RepoType::Npm), ResourceKind::Search),
+        Some(Duration::seconds(30))
+    );
+    assert_eq!(
+        policy.max_age(Some(RepoType::Cargo), ResourceKind::Search),
+        Some(Duration::minutes(15))
+    );
+    assert_eq!(
+        policy.max_age(Some(RepoType::Npm), ResourceKind::Immutable),
+        None
+    );
+    assert!(policy.is_expired(
+        &aged(
+            "https://example.com",
+            RepoType::Npm,
+            ResourceKind::Search,
+            Duration::minutes(1)
+        )
+        .meta()
+    ));
+    assert!(!policy.is_expired(
+        &aged(
+            "https://example.com",
+            RepoType::Npm,
+            ResourceKind::Immutable,
+            Duration::days(1000)
+        )
+        .meta()
+    ));
+}
+
+#[test]
+fn test_get_fresh() {
+    let (dir, cache) = temp_cache("cache-fresh");
+    let search = "https://crates.io/api/v1/crates?q=serde";
+    let index = "https://dl-cdn.alpinelinux.org/alpine/v3.19/main/x86_64/APKINDEX.tar.gz";
+    cache
+        .save(aged(
+            search,
+            RepoType::Cargo,
+            ResourceKind::Search,
+            Duration::hours(1),
+        ))
+        .unwrap();
+    cache
+        .save(aged(
+            index,
+            RepoType::Alpine,
+            ResourceKind::Index,
+            Duration::hours(1),
+        ))
+        .unwrap();
+    assert!(cache.get_fresh(RepoType::Cargo, search).is_none());
+    assert!(cache
+        .get_cache(RepoType::Cargo, search, None, None)
+        .is_some());
+    assert!(cache.get_fresh(RepoType::Alpine, index).is_some());
+
+    // written before entries knew what they were, or had their own directories
+    std::fs::write(
+        dir.join(format!("{}.json", sha256::digest("https://old.example.com"))),
+        r#"{"cache_id": "", "updated": "2020-01-01T00:00:00Z", "url": "https://old.example.com", "content": "{}"}"#,
+    )
+    .unwrap();
+    let old = cache
+        .get_cache(RepoType::Npm, "https://old.example.com", None, None)
+        .unwrap();
+    assert_eq!(old.kind, ResourceKind::Metadata);
+    assert!(cache
+        .get_fresh(RepoType::Npm, "https://old.example.com")
+        .is_none());
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_stats_inspect_purge() {
+    let (dir, mut cache) = temp_cache("cache-stats");
+    for (url, repo_type, kind, age) in [
+        (
+            "https://a.example.com",
+            RepoType::Npm,
+            ResourceKind::Search,
+            Duration::hours(2),
+        ),
+        (
+            "https://b.example.com",
+            RepoType::Npm,
+            ResourceKind::Metadata,
+            Duration::minutes(5),
+        ),
+        (
+            "https://c.example.com",
+            RepoType::Cpan,
+            ResourceKind::Immutable,
+            Duration::days(60),
+        ),
+    ] {
+        cache.save(aged(url, repo_type, kind, age)).unwrap();
+    }
+    std::fs::write(dir.join("broken.json"), "{").unwrap();
+
+    let stats = cache.stats().unwrap();
+    let npm = &stats.ecosystems["npm"];
+    assert_eq!(npm.entries, 2);
+    assert_eq!(npm.expired, 1);
+    assert_eq!(npm.ages, [1, 1, 0, 0, 0]);
+    assert_eq!(stats.ecosystems["cpan"].ages, [0, 0, 0, 0, 1]);
+    assert_eq!(stats.ecosystems["cpan"].expired, 0);
+    assert_eq!(stats.ecosystems["corrupt"].entries, 1);
+    assert!(stats.to_string().contains("npm"));
+
+    let (path, data) = cache.inspect("https://b.example.com").unwrap();
+    assert!(path.starts_with(&dir));
+    assert_eq!(data.repo_type, Some(RepoType::Npm));
+    assert!(cache.inspect("https://nothing.example.com").is_none());
+
+    assert_eq!(
+        cache
+            .purge(Some(RepoType::Npm), Some(ResourceKind::Search))
+            .unwrap(),
+        1
+    );
+    assert_eq!(cache.purge(Some(RepoType::Npm), None).unwrap(), 1);
+    assert!(cache.inspect("https://c.example.com").is_some());
+    assert_eq!(cache.purge(None, None).unwrap(), 2);
+    assert!(cache.stats().unwrap().ecosystems.is_empty());
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_layout_and_prune() {
+    let (dir, mut cache) = temp_cache("cache-prune");
+    let old_url = "https://registry.npmjs.org/left-pad";
+    let new_url = "https://registry.npmjs.org/is-odd";
+    cache
+        .save(aged(
+            old_url,
+            RepoType::Npm,
+            ResourceKind::Metadata,
+            Duration::days(10),
+        ))
+        .unwrap();
+    cache
+        .save(aged(
+            new_url,
+            RepoType::Npm,
+            ResourceKind::Metadata,
+            Duration::minutes(1),
+        ))
+        .unwrap();
+    let key = format!("{}.entry", sha256::digest(new_url));
+    assert!(dir.join("npm").join(&key).exists());
+    assert_eq!(
+        cache.manifest(Some(RepoType::Npm)).unwrap()[&key].url,
+        new_url
+    );
+
+    // from the old flat layout, which gets moved into place
+    let legacy = aged(
+        "https://crates.io/api/v1/crates?q=rand",
+        RepoType::Cargo,
+        ResourceKind::Search,
+        Duration::minutes(2),
+    );
+    let legacy_path = dir.join(format!("{}.json", legacy.get_hash()));
+    std::fs::write(&legacy_path, serde_json::to_string(&legacy).unwrap()).unwrap();
+    std::fs::write(dir.join("notes.txt"), "not an entry").unwrap();
+    std::fs::write(dir.join("npm").join("broken.json"), "{").unwrap();
+
+    let report = cache.clean_cache(Duration::days(1)).unwrap();
+    assert_eq!(report.removed, 1);
+    assert_eq!(report.kept, 2);
+    assert_eq!(report.quarantined, 1);
+    assert!(!legacy_path.exists());
+    assert!(dir
+        .join("crates")
+        .join(legacy.get_hash() + ".entry")
+        .exists());
+    assert!(dir.join("notes.txt").exists());
+    assert!(!dir.join("npm").join("broken.json").exists());
+    assert_eq!(
+        std::fs::read_dir(dir.join("quarantine")).unwrap().count(),
+        1
+    );
+    assert!(cache
+        .get_cache(RepoType::Npm, old_url, None, None)
+        .is_none());
+    assert!(!cache
+        .manifest(Some(RepoType::Npm))
+        .unwrap()
+        .contains_key(&format!("{}.entry", sha256::digest(old_url))));
+    // keyed by the hash of the URL, not the hash of the file name
+    assert!(cache.data.contains_key(&sha256::digest(new_url)));
+    assert_eq!(cache.data.len(), 2);
+
+    // the crates entry is the oldest, so it's the one to go
+    let budget = report.kept_bytes - 1;
+    let report = cache.prune(None, Some(budget)).unwrap();
+    assert_eq!(report.removed, 1);
+    assert!(report.kept_bytes <= budget);
+    assert!(cache
+        .get_cache(RepoType::Npm, new_url, None, None)
+        .is_some());
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_concurrent_writes() {
+    let (dir, cache) = temp_cache("cache-concurrent");
+    let cache = std::sync::Arc::new(cache);
+    let url = "https://pypi.org/pypi/requests/json";
+    let writers: Vec<_> = (0..8)
+        .map(|n| {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                for _ in 0..20 {
+                    let content = n.to_string().repeat(10_000);
+                    cache
+                        .save(
+                            CacheData::new(url.to_string(), String::new(), content)
+                                .with_kind(RepoType::PyPi, ResourceKind::Metadata),
+                        )
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for _ in 0..100 {
+        if let Some(data) = cache.get_cache(RepoType::PyPi, url, None, None) {
+            assert_eq!(data.content.len(), 10_000);
+        }
+    }
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert!(!dir.join("quarantine").exists());
+    assert_eq!(cache.manifest(Some(RepoType::PyPi)).unwrap().len(), 1);
+    let leftovers = std::fs::read_dir(dir.join("pypi"))
+        .unwrap()
+        .filter(|file| file.as_ref().unwrap().path().extension().unwrap() == "tmp")
+        .count();
+    assert_eq!(leftovers, 0);
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_quarantine_and_lock() {
+    let (dir, cache) = temp_cache("cache-quarantine");
+    let url = "https://repo.packagist.org/packages/list.json";
+    std::fs::create_dir_all(dir.join("packagist")).unwrap();
+    let path = dir
+        .join("packagist")
+        .join(format!("{}.json", sha256::digest(url)));
+    std::fs::write(&path, r#"{"cache_id": "", "updated": "#).unwrap();
+
+    assert!(cache
+        .get_cache(RepoType::Packagist, url, None, None)
+        .is_none());
+    assert!(!path.exists());
+    assert_eq!(cache.stats().unwrap().ecosystems["quarantine"].entries, 1);
+
+    {
+        let _lock = cache.lock().unwrap();
+        let other = std::fs::File::open(dir.join(".lock")).unwrap();
+        assert!(other.try_lock_shared().is_err());
+    }
+    let _lock = cache.lock_ecosystem(RepoType::Packagist).unwrap();
+    let other = std::fs::File::open(dir.join(".lock")).unwrap();
+    assert!(other.try_lock_shared().is_ok());
+    other.unlock().unwrap();
+    assert!(other.try_lock().is_err());
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_compressed_blobs() {
+    let (dir, mut cache) = temp_cache("cache-blobs");
+    let packument = format!("{{\"versions\": \"{}\"}}", "1.0.0,".repeat(20_000));
+    let urls = [
+        "https://registry.npmjs.org/left-pad",
+        "https://registry.npmjs.org/@scope/left-pad",
+    ];
+    for url in urls {
+        cache
+            .save(
+                CacheData::new(url.to_string(), String::new(), packument.clone())
+                    .with_kind(RepoType::Npm, ResourceKind::Metadata),
+            )
+            .unwrap();
+    }
+    let blobs = &cache.stats().unwrap().ecosystems["blobs"];
+    assert_eq!(blobs.entries, 1);
+    assert!(blobs.bytes < packument.len() as u64 / 10);
+
+    let (meta, mut reader) = cache.open(RepoType::Npm, urls[0]).unwrap();
+    assert_eq!(meta.size, packument.len() as u64);
+    let mut content = String::new();
+    reader.read_to_string(&mut content).unwrap();
+    assert_eq!(content, packument);
+
+    // small content stays in the entry file
+    cache
+        .save(
+            CacheData::new(
+                "https://example.com".to_string(),
+                String::new(),
+                "{}".into(),
+            )
+            .with_kind(RepoType::Npm, ResourceKind::Search),
+        )
+        .unwrap();
+    let (_, small) = cache.inspect("https://example.com").unwrap();
+    assert!(small.blob.is_none());
+
+    // the blob stays until nothing points at it
+    cache
+        .purge(Some(RepoType::Npm), Some(ResourceKind::Search))
+        .unwrap();
+    std::fs::remove_file(cache.inspect(urls[0]).unwrap().0).unwrap();
+    cache.purge(Some(RepoType::Cargo), None).unwrap();
+    assert_eq!(cache.stats().unwrap().ecosystems["blobs"].entries, 1);
+    assert_eq!(
+        cache
+            .get_cache(RepoType::Npm, urls[1], None, None)
+            .unwrap()
+            .content,
+        packument
+    );
+
+    // an entry that can't be read out of a good blob goes, but the blob stays for the others
+    cache
+        .save(
+            CacheData::new(urls[0].to_string(), String::new(), packument.clone())
+                .with_kind(RepoType::Npm, ResourceKind::Metadata),
+        )
+        .unwrap();
+    let (entry, meta) = cache.inspect(urls[0]).unwrap();
+    let invalid_utf8 = vec![0xff; 100_000];
+    let other = format!("{:x}", sha2::Sha256::digest(&invalid_utf8));
+    let other_blob = dir
+        .join("blobs")
+        .join(&other[..2])
+        .join(other.clone() + ".zst");
+    std::fs::create_dir_all(other_blob.parent().unwrap()).unwrap();
+    zstd::stream::copy_encode(
+        &invalid_utf8[..],
+        std::fs::File::create(&other_blob).unwrap(),
+        3,
+    )
+    .unwrap();
+    // the digests are the same length, so the header stays the same size
+    let mut header = std::fs::read(&entry).unwrap();
+    let digest = meta.blob.unwrap();
+    let at = header
+        .windows(digest.len())
+        .position(|window| window == digest.as_bytes())
+        .unwrap();
+    header[at..at + digest.len()].copy_from_slice(other.as_bytes());
+    std::fs::write(&entry, header).unwrap();
+    assert!(cache
+        .get_cache(RepoType::Npm, urls[0], None, None)
+        .is_none());
+    assert!(!entry.exists());
+    assert!(other_blob.exists());
+
+    // a blob that doesn't match its name is quarantined along with its entry
+    let (entry, meta) = cache.inspect(urls[1]).unwrap();
+    let digest = meta.blob.unwrap();
+    let blob = dir.join("blobs").join(&digest[..2]).join(digest + ".zst");
+    let mut encoder = zstd::Encoder::new(std::fs::File::create(&blob).unwrap(), 3).unwrap();
+    encoder.write_all(b"tampered").unwrap();
+    encoder.finish().unwrap();
+    assert!(cache
+        .get_cache(RepoType::Npm, urls[1], None, None)
+        .is_none());
+    assert!(!entry.exists());
+    assert!(!blob.exists());
+
+    cache.purge(None, None).unwrap();
+    assert!(!cache.stats().unwrap().ecosystems.contains_key("blobs"));
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_streamed_reads() {
+    let (dir, cache) = temp_cache("cache-streamed");
+    let url = "https://conda.anaconda.org/conda-forge/noarch/repodata.json";
+    let repodata = serde_json::json!({
+        "packages": (0..5_000)
+            .map(|n| (format!("pkg-{}-1.0-0.tar.bz2", n), serde_json::json!({"name": n})))
+            .collect::<serde_json::Map<_, _>>()
+    });
+    let mut data = CacheData::new(url.to_string(), String::new(), repodata.to_string())
+        .with_kind(RepoType::Conda, ResourceKind::Index);
+    data.updated -= Duration::hours(1);
+    cache.save(data).unwrap();
+
+    assert!(cache.is_fresh(RepoType::Conda, url, Duration::hours(2)));
+    assert!(!cache.is_fresh(RepoType::Conda, url, Duration::minutes(1)));
+    assert!(!cache.is_fresh(RepoType::Conda, "https://example.com", Duration::hours(2)));
+    assert_eq!(
+        cache.get_fresh_json::<serde_json::Value>(RepoType::Conda, url),
+        Some(repodata)
+    );
+
+    // content that doesn't parse as what was asked for is only a miss
+    let (entry, meta) = cache.inspect(url).unwrap();
+    assert!(cache
+        .get_fresh_json::<Vec<String>>(RepoType::Conda, url)
+        .is_none());
+    assert!(entry.exists());
+
+    // but content that can't be read is quarantined
+    let digest = meta.blob.unwrap();
+    let blob = dir.join("blobs").join(&digest[..2]).join(digest + ".zst");
+    let mut encoder = zstd::Encoder::new(std::fs::File::create(&blob).unwrap(), 3).unwrap();
+    encoder.write_all(b"{}").unwrap();
+    encoder.finish().unwrap();
+    assert!(cache
+        .get_fresh_json::<serde_json::Value>(RepoType::Conda, url)
+        .is_none());
+    assert!(!entry.exists());
+    assert!(!blob.exists());
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_saved_from_reader() {
+    let (dir, cache) = temp_cache("cache-reader");
+    let url = "https://www.cpan.org/modules/02packages.details.txt.gz";
+    let line = "Some::Module  1.00  A/AU/AUTHOR/Some-Module-1.00.tar.gz\n";
+    let index = line.repeat(5_000);
+    cache
+        .save_reader(url, RepoType::Cpan, ResourceKind::Index, index.as_bytes())
+        .unwrap();
+    let (meta, mut reader) = cache.open(RepoType::Cpan, url).unwrap();
+    assert_eq!(meta.size, index.len() as u64);
+    assert!(meta.blob.is_some());
+    let mut content = String::new();
+    reader.read_to_string(&mut content).unwrap();
+    assert_eq!(content, index);
+    // nothing's left behind from writing the blob before it had a name
+    let leftovers = std::fs::read_dir(dir.join("blobs"))
+        .unwrap()
+        .filter(|file| file.as_ref().unwrap().path().is_file())
+        .count();
+    assert_eq!(leftovers, 0);
+
+    // the same content saved again shares the blob, and small content stays inline
+    let other = "https://cpan.example.com/modules/02packages.details.txt.gz";
+    cache
+        .save_reader(other, RepoType::Cpan, ResourceKind::Index, index.as_bytes())
+        .unwrap();
+    assert_eq!(cache.stats().unwrap().ecosystems["blobs"].entries, 1);
+    cache
+        .save_reader(url, RepoType::Cpan, ResourceKind::Index, line.as_bytes())
+        .unwrap();
+    let (_, small) = cache.inspect(url).unwrap();
+    assert!(small.blob.is_none());
+    assert_eq!(small.size, line.len() as u64);
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_blob_digest_checked() {
+    let (dir, cache) = temp_cache("cache-blob-digest");
+    let url = "https://registry.npmjs.org/left-pad";
+    cache
+        .save(
+            CacheData::new(url.to_string(), String::new(), "x".repeat(100_000))
+                .with_kind(RepoType::Npm, ResourceKind::Metadata),
+        )
+        .unwrap();
+    let (entry, meta) = cache.inspect(url).unwrap();
+    // the same length, so the header stays the same size
+    let digest = meta.blob.unwrap();
+    let escape = format!("{}{}", "../".repeat(21), "x");
+    assert_eq!(escape.len(), digest.len());
+    let mut header = std::fs::read(&entry).unwrap();
+    let at = header
+        .windows(digest.len())
+        .position(|window| window == digest.as_bytes())
+        .unwrap();
+    header[at..at + digest.len()].copy_from_slice(escape.as_bytes());
+    std::fs::write(&entry, header).unwrap();
+
+    assert!(cache.get_cache(RepoType::Npm, url, None, None).is_none());
+    assert!(!entry.exists());
+    assert_eq!(cache.stats().unwrap().ecosystems["quarantine"].entries, 1);
+
+    std::fs::remove_dir_all(dir).ok();
+}
@@ -0,0 +1,165 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::repo::cargo_config::{token_env_var, CargoConfig, CargoRegistry, IndexUrl};
+use crate::repo::crates::{index_path, Cargo, RegistryConfig};
+use crate::repo::Repository;
+
+#[test]
+fn test_cargo_config_registries() {
+    let mut config = CargoConfig::from_str(
+        r#"
+[registries.internal-crates]
+index = "sparse+https://crates.example.com/index/"
+
+[registries.old-git]
+index = "https://github.com/example/crate-index.git"
+
+[source.crates-io]
+replace-with = "mirror"
+
+[source.mirror]
+registry = "sparse+https://mirror.example.com/"
+"#,
+    )
+    .unwrap();
+    // the user's CARGO_HOME config has lower priority than the project's
+    config.merge(
+        CargoConfig::from_str(
+            r#"
+[registries.internal-crates]
+index = "sparse+https://ignored.example.com/"
+token = "from-credentials"
+
+[registry]
+token = "crates-io-token"
+"#,
+        )
+        .unwrap(),
+    );
+
+    let registries = config
+        .registries_with_env(|key| {
+            (key == "CARGO_REGISTRIES_OLD_GIT_TOKEN").then(|| "from-env".to_string())
+        })
+        .unwrap();
+    assert_eq!(registries.len(), 3);
+
+    assert_eq!(registries[0].name, "mirror");
+    assert_eq!(
+        registries[0].index,
+        IndexUrl::Sparse("https://mirror.example.com".to_string())
+    );
+    // the crates.io token doesn't get sent to the mirror
+    assert_eq!(registries[0].token, None);
+
+    assert_eq!(registries[1].name, "internal-crates");
+    assert_eq!(
+        registries[1].index.file_base().unwrap(),
+        "https://crates.example.com/index"
+    );
+    assert_eq!(registries[1].token.as_deref(), Some("from-credentials"));
+
+    assert_eq!(registries[2].token.as_deref(), Some("from-env"));
+    assert_eq!(
+        registries[2].index.file_base().unwrap(),
+        "https://raw.githubusercontent.com/example/crate-index/HEAD"
+    );
+
+    assert_eq!(
+        token_env_var("internal-crates"),
+        "CARGO_REGISTRIES_INTERNAL_CRATES_TOKEN"
+    );
+}
+
+#[test]
+fn test_cargo_index_paths() {
+    assert_eq!(index_path("a"), "1/a");
+    assert_eq!(index_path("cc"), "2/cc");
+    assert_eq!(index_path("syn"), "3/s/syn");
+    assert_eq!(index_path("Base64"), "ba/se/base64");
+    assert_eq!(
+        IndexUrl::from_str("registry+https://github.com/rust-lang/crates.io-index").unwrap(),
+        IndexUrl::Sparse("https://index.crates.io".to_string())
+    );
+
+    let config: RegistryConfig = serde_json::from_str(
+        r#"{"dl": "https://static.crates.io/crates", "api": "https://crates.io"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.download_url("serde", "1.0.0", "abc"),
+        "https://static.crates.io/crates/serde/1.0.0/download"
+    );
+    let config: RegistryConfig = serde_json::from_str(
+        r#"{"dl": "https://dl.example.com/{prefix}/{lowerprefix}/{crate}-{version}.crate?sum={sha256-checksum}", "auth-required": true}"#,
+    )
+    .unwrap();
+    assert!(config.auth_required);
+    assert_eq!(
+        config.download_url("MyCrate", "0.1.0", "abc"),
+        "https://dl.example.com/My/Cr/my/cr/MyCrate-0.1.0.crate?sum=abc"
+    );
+}
+
+#[tokio::test]
+async fn test_token_stays_with_the_registry() {
+    let index = MockServer::start().await;
+    let api = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/config.json"))
+        .and(header("authorization", "secret"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "dl": format!("{}/dl", index.uri()),
+            "api": api.uri(),
+            "auth-required": true,
+        })))
+        .mount(&index)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/config.json"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&index)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/ra/nd/rand"))
+        .and(header("authorization", "secret"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"name":"rand","vers":"0.8.5","deps":[],"features":{},"cksum":"abc","yanked":false}"#,
+        ))
+        .mount(&index)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/crates"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "crates": [{"name": "rand", "max_version": "0.8.5"}]
+        })))
+        .mount(&api)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!("tidetrawler-cargo-token-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut cargo = Cargo::new(cache).with_registry(CargoRegistry {
+        name: "internal".to_string(),
+        index: IndexUrl::Sparse(index.uri()),
+        token: Some("secret".to_string()),
+    });
+    assert_eq!(cargo.get_package("rand").await.unwrap().len(), 1);
+    assert_eq!(cargo.search("rand").await.unwrap().len(), 1);
+
+    // config.json gets asked for without the token first, the way Cargo does
+    let requests = index.received_requests().await.unwrap();
+    assert!(!requests[0].headers.contains_key("authorization"));
+    assert_eq!(requests.len(), 3);
+    // the API's on another origin, so it never sees the token
+    let requests = api.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(!requests[0].headers.contains_key("authorization"));
+    std::fs::remove_dir_all(dir).ok();
+}
@@ -0,0 +1,145 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::repo::conda::{compare_versions, version_matches, Conda, MatchSpec, RepoData};
+use crate::repo::{Package, Repository};
+use crate::{Errors, RepoType};
+
+#[test]
+fn test_parse_repodata() {
+    let repodata: RepoData =
+        serde_json::from_str(include_str!("data/conda-repodata.json")).unwrap();
+    let records = repodata.into_records("https://conda.anaconda.org/conda-forge", "linux-64");
+    assert_eq!(records.len(), 4);
+
+    let numpy = records
+        .iter()
+        .find(|record| record.filename == "numpy-1.26.4-py310hb13e2d6_0.conda")
+        .unwrap();
+    assert_eq!(numpy.constrains, vec!["numpy-base <0a0".to_string()]);
+    assert!(numpy.release_date().is_some());
+
+    let spec = MatchSpec::from_str("numpy >=1.22,<2.0a0").unwrap();
+    let matching: Vec<_> = records.iter().filter(|r| spec.matches(r)).collect();
+    assert_eq!(matching.len(), 1);
+
+    let package: Package = numpy.clone().into();
+    assert_eq!(
+        package.other_metadata.get("download_url").unwrap(),
+        "https://conda.anaconda.org/conda-forge/linux-64/numpy-1.26.4-py310hb13e2d6_0.conda"
+    );
+}
+
+#[test]
+fn test_conda_version_ordering() {
+    let ordered = [
+        "0.4",
+        "0.4.0",
+        "0.4.1.rc",
+        "0.4.1.RC",
+        "0.4.1",
+        "0.5a1",
+        "0.5b3",
+        "0.5C1",
+        "0.5",
+        "0.9.6",
+        "0.960923",
+        "1.0",
+        "1.1dev1",
+        "1.1a1",
+        "1.1.0dev1",
+        "1.1.a1",
+        "1.1.0rc1",
+        "1.1.0",
+        "1.1.0post1",
+        "1.1post1",
+        "1996.07.12",
+        "1!0.4.1",
+        "1!3.1.1.6",
+        "2!0.4.1",
+    ];
+    for pair in ordered.windows(2) {
+        assert_ne!(
+            compare_versions(pair[0], pair[1]),
+            Ordering::Greater,
+            "{} > {}",
+            pair[0],
+            pair[1]
+        );
+    }
+    assert_eq!(compare_versions("2.0.0rc1", "1.26.4"), Ordering::Greater);
+    assert_eq!(compare_versions("2.0.0rc1", "2.0.0"), Ordering::Less);
+    assert_eq!(compare_versions("1.1dev1", "1.1a1"), Ordering::Less);
+    assert_eq!(compare_versions("1.1a1", "1.1.dev1"), Ordering::Less);
+    assert_eq!(compare_versions("1.1rc1", "1.1"), Ordering::Less);
+    assert_eq!(compare_versions("1.1", "1.1post1"), Ordering::Less);
+}
+
+#[test]
+fn test_conda_match_spec() {
+    let spec = MatchSpec::from_str("conda-forge::python_abi 3.10.* *_cp310").unwrap();
+    assert_eq!(spec.channel.as_deref(), Some("conda-forge"));
+    assert_eq!(spec.name, "python_abi");
+    assert_eq!(spec.version.as_deref(), Some("3.10.*"));
+    assert_eq!(spec.build.as_deref(), Some("*_cp310"));
+
+    let spec = MatchSpec::from_str("numpy=1.26=py310*").unwrap();
+    assert_eq!(spec.version.as_deref(), Some("1.26*"));
+    assert_eq!(spec.build.as_deref(), Some("py310*"));
+
+    let spec = MatchSpec::from_str("numpy[version='>=1.2']").unwrap();
+    assert_eq!(spec.version.as_deref(), Some(">=1.2"));
+
+    assert!(version_matches(">=3.10,<3.11.0a0", "3.10.12"));
+    assert!(!version_matches(">=3.10,<3.11.0a0", "3.11.0rc1"));
+    assert!(version_matches("3.10.*", "3.10.1"));
+    assert!(!version_matches("3.1.*", "3.10.1"));
+    assert!(version_matches("1.19.*|>=1.21", "1.19.5"));
+    assert!(version_matches("1.2", "1.2.0"));
+    assert!(version_matches("~=1.2.3", "1.2.9"));
+    assert!(!version_matches("~=1.2.3", "1.3.0"));
+    assert!(version_matches("!=1.2.*", "1.3.0"));
+}
+
+#[tokio::test]
+async fn test_conda_get_package_channels() {
+    let server = MockServer::start().await;
+    let repodata = include_str!("data/conda-repodata.json");
+    for channel in ["main", "other"] {
+        Mock::given(method("GET"))
+            .and(path(format!("/{}/linux-64/repodata.json", channel)))
+            .respond_with(ResponseTemplate::new(200).set_body_string(repodata))
+            .mount(&server)
+            .await;
+    }
+
+    let dir = std::env::temp_dir().join(format!("tidetrawler-conda-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut conda = Conda::new(cache)
+        .with_channel(&format!("{}/main", server.uri()))
+        .with_subdirs(&["linux-64"]);
+
+    let packages = conda.get_package("numpy >=1.22,<2.0a0").await.unwrap();
+    assert_eq!(packages.len(), 1);
+    // the cache it was given made its own directory for it
+    assert!(dir.join(RepoType::Conda.cache_dir()).is_dir());
+    assert!(matches!(
+        conda.get_package("numpy >=3").await,
+        Err(Errors::NotFound { .. })
+    ));
+
+    let spec = format!("{}/other::numpy >=1.22,<2.0a0", server.uri());
+    let packages = conda.get_package(&spec).await.unwrap();
+    assert!(packages[0].other_metadata["download_url"]
+        .as_str()
+        .unwrap()
+        .contains("/other/linux-64/"));
+    std::fs::remove_dir_all(dir).ok();
+}
@@ -0,0 +1,43 @@
+use std::io::BufReader;
+
+use crate::repo::cpan::{decode_02packages, parse_02packages, CpanMeta};
+use crate::repo::Package;
+
+#[test]
+fn test_cpan_02packages_parse() {
+    let text = include_str!("data/cpan-02packages.details.txt");
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, text.as_bytes()).unwrap();
+    let body = encoder.finish().unwrap();
+
+    let modules = parse_02packages(BufReader::new(decode_02packages(&body))).unwrap();
+    assert_eq!(modules.len(), 5);
+    assert_eq!(modules[0].module, "HTTP::Tiny");
+    assert_eq!(modules[4].version, None);
+
+    let ua = &modules[3];
+    assert_eq!(ua.author(), Some("OALDERS"));
+    assert_eq!(
+        ua.distribution(),
+        Some(("libwww-perl".to_string(), "6.76".to_string()))
+    );
+
+    let mut package: Package = ua.clone().into();
+    assert_eq!(package.owner.as_deref(), Some("OALDERS"));
+
+    let meta: CpanMeta = serde_json::from_str(include_str!("data/cpan-libwww-perl.meta")).unwrap();
+    assert_eq!(meta.requires("test").unwrap().len(), 2);
+    assert!(meta.requires("build").is_none());
+
+    meta.apply(&mut package);
+    assert_eq!(package.other_metadata.get("license").unwrap(), "perl_5");
+    assert_eq!(
+        package.other_metadata.get("dependencies").unwrap()["URI"],
+        "1.10"
+    );
+    assert_eq!(
+        package.other_metadata.get("prereqs").unwrap()["runtime"]["suggests"]
+            ["LWP::Protocol::https"],
+        "6.02"
+    );
+}
@@ -0,0 +1,210 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::RwLock;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::repo::cargo_config::{CargoRegistry, IndexUrl};
+use crate::repo::crates::Cargo;
+use crate::repo::github::GitHub;
+use crate::repo::hex::Hex;
+use crate::repo::Repository;
+use crate::request::{parse_json, parse_retry_after, RetryPolicy, WebClient};
+use crate::{Errors, RepoType};
+
+#[test]
+fn test_parse_json_offset() {
+    let body = "{\n  \"name\": \"thing\",\n  \"version\": }";
+    let err = parse_json::<serde_json::Value>("https://example.com/thing.json", body).unwrap_err();
+    match &err {
+        Errors::Parse { url, offset, .. } => {
+            assert_eq!(url.as_deref(), Some("https://example.com/thing.json"));
+            assert_eq!(&body[offset.unwrap()..], "}");
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    // the serde error is still there underneath
+    assert!(err.source().unwrap().is::<serde_json::Error>());
+    assert!(err
+        .to_string()
+        .starts_with("Couldn't parse https://example.com/thing.json"));
+}
+
+#[test]
+fn test_parse_retry_after() {
+    assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon"), None);
+}
+
+#[tokio::test]
+async fn test_status_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/limited"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/private"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/broken"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+
+    let client = WebClient::default().with_retry(RetryPolicy::none());
+    assert!(matches!(
+        client.get_text(&format!("{}/limited", server.uri())).await,
+        Err(Errors::RateLimited { retry_after: Some(retry_after), .. })
+            if retry_after == Duration::from_secs(30)
+    ));
+    assert!(matches!(
+        client.get_text(&format!("{}/private", server.uri())).await,
+        Err(Errors::Unauthorized { .. })
+    ));
+    let err = client
+        .get_text(&format!("{}/broken", server.uri()))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Errors::Http { status, .. } if status.as_u16() == 500));
+    assert!(!err.is_not_found());
+}
+
+#[tokio::test]
+async fn test_missing_package() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/packages/nope"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!(
+        "tidetrawler-missing-package-{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    let mut hex =
+        Hex::new(Arc::new(RwLock::new(Cache::new(dir.clone())))).with_api_url(&server.uri());
+    match hex.get_package("nope").await {
+        Err(Errors::NotFound { repo_type, name }) => {
+            assert_eq!(repo_type, RepoType::Hex);
+            assert_eq!(name, "nope");
+        }
+        other => panic!("expected not found, got {:?}", other),
+    }
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_offline() {
+    // nothing listens on port 9 on a test box
+    let err = WebClient::default()
+        .with_retry(RetryPolicy::none())
+        .get_text("http://127.0.0.1:9/")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Errors::Offline {
+            source: Some(_),
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn test_search_errors_arent_cached() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/config.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "dl": format!("{}/dl", server.uri()),
+            "api": server.uri(),
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/crates"))
+        .respond_with(ResponseTemplate::new(403).set_body_string("go away"))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/crates"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>maintenance</html>"))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/crates"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "crates": [{"name": "rand", "max_version": "0.8.5"}]
+        })))
+        .mount(&server)
+        .await;
+
+    let dir =
+        std::env::temp_dir().join(format!("tidetrawler-search-errors-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut cargo = Cargo::new(cache.clone()).with_registry(CargoRegistry {
+        name: "internal".to_string(),
+        index: IndexUrl::Sparse(server.uri()),
+        token: None,
+    });
+
+    assert!(matches!(
+        cargo.search("rand").await,
+        Err(Errors::Unauthorized { .. })
+    ));
+    match cargo.search("rand").await {
+        Err(Errors::Parse { url, offset, .. }) => {
+            assert!(url.unwrap().contains("/api/v1/crates?q=rand"));
+            assert_eq!(offset, Some(0));
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+    assert!(cache.read().await.entries().unwrap().is_empty());
+
+    let packages = cargo.search("rand").await.unwrap();
+    assert_eq!(packages[0].name, "rand");
+    assert_eq!(cache.read().await.entries().unwrap().len(), 1);
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_response_parse_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/search/repositories"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{\"items\": [}"))
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!("tidetrawler-parse-errors-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut github = GitHub::new(cache).with_api_url(&server.uri());
+    match github.search("tool").await {
+        Err(Errors::Parse { url, offset, .. }) => {
+            assert!(url.unwrap().ends_with("/search/repositories?q=tool"));
+            assert_eq!(offset, Some(11));
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+
+    std::fs::remove_dir_all(dir).ok();
+}
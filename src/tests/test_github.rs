@@ -0,0 +1,274 @@
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use wiremock::matchers::{header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::config::{ConfigLoader, Source};
+use crate::orchestrator::Orchestrator;
+use crate::repo::github::GitHub;
+use crate::repo::{compare_semver, Repository};
+
+#[tokio::test]
+async fn test_github_releases_and_tags() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repos/example/tool/releases"))
+        .and(query_param("page", "2"))
+        .and(header("Authorization", "Bearer s3cret"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+                "tag_name": "v1.9.0",
+                "name": "",
+                "draft": false,
+                "prerelease": false,
+                "published_at": "2023-06-01T00:00:00Z",
+                "assets": []
+            }])),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/repos/example/tool/releases"))
+        .and(header("Authorization", "Bearer s3cret"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header(
+                    "Link",
+                    format!(
+                        r#"<{}/repos/example/tool/releases?per_page=100&page=2>; rel="next", <{}/repos/example/tool/releases?per_page=100&page=2>; rel="last""#,
+                        server.uri(),
+                        server.uri()
+                    )
+                    .as_str(),
+                )
+                .set_body_json(serde_json::json!([
+                    {
+                        "tag_name": "v2.0.0-rc.1",
+                        "name": "Two point oh, nearly",
+                        "draft": false,
+                        "prerelease": true,
+                        "html_url": "https://github.com/example/tool/releases/tag/v2.0.0-rc.1",
+                        "author": {"login": "maintainer"},
+                        "created_at": "2024-01-01T00:00:00Z",
+                        "published_at": "2024-01-02T00:00:00Z",
+                        "assets": [
+                            {
+                                "name": "tool_linux_amd64.tar.gz",
+                                "browser_download_url": "https://example.com/tool_linux_amd64.tar.gz",
+                                "size": 1024,
+                                "content_type": "application/gzip",
+                                "digest": "sha256:abc123"
+                            },
+                            {
+                                "name": "tool_2.0.0-rc.1_checksums.txt",
+                                "browser_download_url": "https://example.com/checksums.txt",
+                                "size": 100
+                            }
+                        ]
+                    },
+                    {
+                        "tag_name": "v2.1.0",
+                        "draft": true,
+                        "prerelease": false,
+                        "created_at": "2024-03-01T00:00:00Z",
+                        "published_at": null,
+                        "assets": []
+                    }
+                ])),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/repos/example/tool/tags"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"name": "v2.0.0-rc.1", "commit": {"sha": "aaaa"}},
+            {"name": "v1.0.0", "commit": {"sha": "bbbb"}, "tarball_url": "https://example.com/v1.0.0.tar.gz"}
+        ])))
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!(
+        "tidetrawler-github-releases-and-tags-{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut github = GitHub::new(cache)
+        .with_api_url(&server.uri())
+        .with_token("s3cret");
+
+    let packages = github.get_package("example/tool").await.unwrap();
+    let versions: Vec<&str> = packages
+        .iter()
+        .map(|p| p.other_metadata["version"].as_str().unwrap())
+        .collect();
+    assert_eq!(versions, vec!["v1.0.0", "v1.9.0", "v2.0.0-rc.1", "v2.1.0"]);
+
+    assert_eq!(packages[0].other_metadata["kind"], "tag");
+    assert_eq!(packages[0].other_metadata["commit"], "bbbb");
+    assert!(!packages[1].other_metadata.contains_key("release_name"));
+
+    let rc = &packages[2];
+    assert_eq!(rc.other_metadata["prerelease"], true);
+    assert_eq!(
+        rc.other_metadata["release_date"],
+        "2024-01-02T00:00:00+00:00"
+    );
+    assert_eq!(
+        rc.other_metadata["checksums_url"],
+        "https://example.com/checksums.txt"
+    );
+    assert_eq!(rc.other_metadata["assets"][0]["checksum"], "sha256:abc123");
+    assert_eq!(rc.owner.as_deref(), Some("maintainer"));
+
+    let draft = &packages[3];
+    assert_eq!(draft.other_metadata["draft"], true);
+    assert_eq!(
+        draft.other_metadata["release_date"],
+        "2024-03-01T00:00:00+00:00"
+    );
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_token_stays_on_its_server() {
+    let server = MockServer::start().await;
+    let elsewhere = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/repos/example/tool/releases"))
+        .and(header("Authorization", "Bearer s3cret"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header(
+                    "Link",
+                    format!(
+                        r#"<{}/repos/example/tool/releases?page=2>; rel="next""#,
+                        elsewhere.uri()
+                    )
+                    .as_str(),
+                )
+                .set_body_json(serde_json::json!([{"tag_name": "v2.0.0", "assets": []}])),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/repos/example/tool/releases"))
+        .and(|req: &wiremock::Request| !req.headers.contains_key("Authorization"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!([{"tag_name": "v1.0.0", "assets": []}])),
+        )
+        .mount(&elsewhere)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/repos/example/tool/tags"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!("tidetrawler-token-server-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut github = GitHub::new(cache)
+        .with_api_url(&server.uri())
+        .with_token("s3cret");
+    assert_eq!(github.get_package("example/tool").await.unwrap().len(), 2);
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_gitea_search() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/repos/search"))
+        .and(query_param("q", "tool"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "ok": true,
+            "data": [{
+                "full_name": "example/tool",
+                "description": "A tool",
+                "html_url": "https://gitea.example.com/example/tool",
+                "owner": {"login": "example"},
+                "stars_count": 12,
+                "archived": true
+            }]
+        })))
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!("tidetrawler-gitea-search-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut gitea = GitHub::new(cache).with_gitea(&format!("{}/api/v1", server.uri()));
+    let packages = gitea.search("tool").await.unwrap();
+    assert_eq!(packages.len(), 1);
+    assert_eq!(packages[0].other_metadata["stars"], 12);
+    assert_eq!(packages[0].other_metadata["deprecated"], "archived");
+
+    // and the same from the config
+    let loaded = ConfigLoader::default()
+        .with_override("backends", "github", Source::Default)
+        .unwrap()
+        .with_override(
+            "registries.github.url",
+            &format!("{}/api/v1", server.uri()),
+            Source::Default,
+        )
+        .unwrap()
+        .with_override("registries.github.kind", "gitea", Source::Default)
+        .unwrap()
+        .build()
+        .unwrap();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let (mut orchestrator, errors) = Orchestrator::from_config(&loaded.config, cache.clone());
+    assert!(errors.is_empty());
+    let results = orchestrator.search("tool").await;
+    assert!(results.errors.is_empty());
+    assert_eq!(results.packages.len(), 1);
+
+    let unknown = ConfigLoader::default()
+        .with_override("backends", "github", Source::Default)
+        .unwrap()
+        .with_override("registries.github.kind", "gitlab", Source::Default)
+        .unwrap()
+        .build()
+        .unwrap();
+    let (_, errors) = Orchestrator::from_config(&unknown.config, cache);
+    assert_eq!(errors.len(), 1);
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_mixed_tags_sort() {
+    // v1.9 isn't semver, so comparing it as a string against v1.10.0 but as a version against
+    // everything else used to be inconsistent enough to make sort_by panic
+    let mut tags: Vec<String> = ["v1.9", "v1.10.0", "nightly", "v1.2.0", "latest", "1.11.0"]
+        .iter()
+        .cycle()
+        .take(60)
+        .enumerate()
+        .map(|(n, tag)| match n % 4 {
+            0 => format!("v{}.{}.0", n % 7, n),
+            1 => format!("build-{}", n),
+            _ => tag.to_string(),
+        })
+        .collect();
+    tags.sort_by(|a, b| compare_semver(a, b));
+    for (n, a) in tags.iter().enumerate() {
+        for b in tags[n..].iter() {
+            assert_ne!(compare_semver(a, b), std::cmp::Ordering::Greater);
+        }
+    }
+
+    let mut tags = vec!["v1.10.0", "nightly", "v1.9", "1.11.0", "v1.2.0", "latest"];
+    tags.sort_by(|a, b| compare_semver(a, b));
+    assert_eq!(
+        tags,
+        ["latest", "nightly", "v1.9", "v1.2.0", "v1.10.0", "1.11.0"]
+    );
+}
@@ -0,0 +1,157 @@
+use std::cmp::Ordering;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::repo::hackage::{
+    compare_versions, read_index_names, read_index_package, version_in_range, CabalDependency,
+    CabalFile, Hackage,
+};
+use crate::repo::Repository;
+use crate::RepoType;
+
+fn build_index() -> Vec<u8> {
+    let cabal = include_str!("data/hackage-text.cabal");
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, content) in [
+        ("text/1.2.5.0/text.cabal", cabal.replace("2.0.2", "1.2.5.0")),
+        (
+            "aeson/2.2.1.0/aeson.cabal",
+            "name: aeson\nversion: 2.2.1.0\n".to_string(),
+        ),
+        (
+            "text/2.0.2/text.cabal",
+            cabal.replace("license:        BSD-2-Clause", ""),
+        ),
+        ("text/2.0.1/text.cabal", cabal.replace("2.0.2", "2.0.1")),
+        // a revision of 2.0.2, which should win
+        ("text/2.0.2/text.cabal", cabal.to_string()),
+        (
+            "text/preferred-versions",
+            "text <2.0.1 || >2.0.1".to_string(),
+        ),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, content.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[test]
+fn test_cabal_parse() {
+    let cabal = CabalFile::parse(include_str!("data/hackage-text.cabal"));
+    assert_eq!(cabal.name, "text");
+    assert_eq!(cabal.version, "2.0.2");
+    assert_eq!(cabal.license.as_deref(), Some("BSD-2-Clause"));
+    assert_eq!(
+        cabal.synopsis.as_deref(),
+        Some("An efficient packed Unicode text type.")
+    );
+
+    let library = cabal.dependencies.get("library").unwrap();
+    assert_eq!(library.len(), 5);
+    assert_eq!(
+        library[0],
+        CabalDependency {
+            name: "array".to_string(),
+            range: Some(">= 0.3 && < 0.6".to_string())
+        }
+    );
+    assert_eq!(library[4].name, "system-cxx-std-lib");
+
+    let tests = cabal.dependencies.get("test:tests").unwrap();
+    assert_eq!(tests.len(), 3);
+    assert_eq!(tests[2].range, None);
+}
+
+#[test]
+fn test_hackage_index_read() {
+    let index = build_index();
+    assert_eq!(
+        read_index_names(Cursor::new(&index)).unwrap(),
+        vec!["aeson".to_string(), "text".to_string()]
+    );
+
+    let package = read_index_package(Cursor::new(&index), "text").unwrap();
+    assert_eq!(package.preferred_range(), Some("<2.0.1 || >2.0.1"));
+
+    let packages = package.into_packages();
+    let versions: Vec<&str> = packages
+        .iter()
+        .map(|p| p.other_metadata.get("version").unwrap().as_str().unwrap())
+        .collect();
+    assert_eq!(versions, vec!["1.2.5.0", "2.0.1", "2.0.2"]);
+    assert!(packages[1].other_metadata.contains_key("deprecated"));
+    assert!(!packages[2].other_metadata.contains_key("deprecated"));
+    // the revision had the license back in
+    assert_eq!(
+        packages[2].other_metadata.get("license").unwrap(),
+        "BSD-2-Clause"
+    );
+}
+
+#[tokio::test]
+async fn test_interrupted_append() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/01-index.tar"))
+        .respond_with(ResponseTemplate::new(416))
+        .mount(&server)
+        .await;
+
+    let dir =
+        std::env::temp_dir().join(format!("tidetrawler-hackage-append-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Cache::new(dir.clone());
+    let index_dir = cache.ecosystem_dir(Some(RepoType::Hackage));
+    let mut hackage = Hackage::new(Arc::new(RwLock::new(cache))).with_base_url(&server.uri());
+
+    // the index is in our cache, and an append to it got as far as overwriting the trailer
+    let index = build_index();
+    let offset = index.len() - 1024;
+    std::fs::create_dir_all(&index_dir).unwrap();
+    let interrupted = |index: &[u8]| {
+        let mut partial = index[..offset].to_vec();
+        partial.extend_from_slice(b"half of a tar header");
+        std::fs::write(index_dir.join("01-index.tar"), partial).unwrap();
+        std::fs::write(index_dir.join("01-index.tar.appending"), offset.to_string()).unwrap();
+    };
+    interrupted(&index);
+
+    assert_eq!(hackage.get_package("text").await.unwrap().len(), 3);
+    assert_eq!(
+        std::fs::read(index_dir.join("01-index.tar")).unwrap(),
+        index
+    );
+    assert!(!index_dir.join("01-index.tar.appending").exists());
+
+    interrupted(&index);
+    hackage.update_cache(None).await.unwrap();
+    assert_eq!(
+        std::fs::read(index_dir.join("01-index.tar")).unwrap(),
+        index
+    );
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+fn test_pvp_versions() {
+    assert_eq!(compare_versions("1.2.10", "1.2.9"), Ordering::Greater);
+    assert_eq!(compare_versions("1.0", "1.0.0"), Ordering::Less);
+    assert!(version_in_range(">= 4.9 && < 5", "4.18.0.0"));
+    assert!(!version_in_range(">= 4.9 && < 5", "5"));
+    assert!(version_in_range("^>=2.1.3", "2.1.9"));
+    assert!(!version_in_range("^>=2.1.3", "2.2"));
+    assert!(version_in_range("==1.2.*", "1.2.3"));
+    assert!(version_in_range("1.0 || ==2.*", "2.5"));
+    assert!(!version_in_range("-none", "1.0"));
+}
@@ -0,0 +1,45 @@
+use std::str::FromStr;
+
+use crate::repo::helm::{HelmIndex, HelmRepository};
+
+#[test]
+fn test_helm_index_parse() {
+    let index = HelmIndex::from_yaml(include_str!("data/helm-index.yaml")).unwrap();
+    let repository = HelmRepository::from_str("example=https://charts.example.com/").unwrap();
+    assert_eq!(repository.url, "https://charts.example.com");
+
+    let versions: Vec<String> = index
+        .versions("redis")
+        .into_iter()
+        .map(|chart| chart.version)
+        .collect();
+    assert_eq!(versions, vec!["18.4.0", "18.12.1"]);
+
+    let latest = index.latest();
+    assert_eq!(latest.len(), 2);
+    let redis = latest[1].clone().into_package(&repository);
+    assert_eq!(redis.other_metadata.get("app_version").unwrap(), "7.2.4");
+    assert_eq!(
+        redis.other_metadata.get("download_url").unwrap(),
+        "https://charts.example.com/charts/redis-18.12.1.tgz"
+    );
+    assert_eq!(
+        redis.other_metadata.get("dependencies").unwrap()[0]["name"],
+        "common"
+    );
+    assert_eq!(
+        redis.other_metadata.get("release_date").unwrap(),
+        "2024-02-01T10:20:30.123456789+00:00"
+    );
+    assert_eq!(
+        redis.owner.as_deref(),
+        Some("Broadcom, Inc. All Rights Reserved.")
+    );
+
+    let old = latest[0].clone().into_package(&repository);
+    assert_eq!(old.other_metadata.get("deprecated").unwrap(), "deprecated");
+    assert_eq!(old.other_metadata.get("version").unwrap(), "v0.1.0");
+
+    let bare = HelmRepository::from_str("https://internal.example.org/charts").unwrap();
+    assert_eq!(bare.name, "internal.example.org");
+}
@@ -0,0 +1,41 @@
+use crate::repo::hex::{decode_package, HexPackage, Signed};
+use crate::repo::Package;
+
+#[test]
+fn test_hex_api_package_parse() {
+    let package: HexPackage = serde_json::from_str(include_str!("data/hex-api-plug.json")).unwrap();
+    let releases = package.into_packages();
+    assert_eq!(releases.len(), 3);
+
+    let versions: Vec<&str> = releases
+        .iter()
+        .map(|p| p.other_metadata.get("version").unwrap().as_str().unwrap())
+        .collect();
+    assert_eq!(versions, vec!["1.4.0", "1.5.0-rc.0", "1.5.0"]);
+
+    let retired: &Package = &releases[1];
+    assert_eq!(retired.other_metadata.get("yanked").unwrap(), true);
+    assert_eq!(
+        retired.other_metadata.get("deprecated").unwrap(),
+        "deprecated: use 1.5.0"
+    );
+    assert!(!releases[2].other_metadata.contains_key("yanked"));
+    assert_eq!(releases[2].owner.as_deref(), Some("josevalim"));
+}
+
+#[test]
+fn test_hex_registry_package_decode() {
+    let signed = Signed::from_bytes(include_bytes!("data/hex-registry-plug.gz")).unwrap();
+    assert_eq!(signed.signature, b"signature-bytes");
+
+    let releases = decode_package(&signed.payload).unwrap();
+    assert_eq!(releases.len(), 3);
+    assert_eq!(releases[0].version, "1.4.0");
+    assert_eq!(releases[0].inner_checksum, "0102");
+    assert_eq!(releases[0].dependencies[0].requirement, "~> 1.0 or ~> 2.0");
+    assert!(releases[2].dependencies[1].optional);
+
+    let retired = releases[1].retired.as_ref().unwrap();
+    assert_eq!(retired.reason, "deprecated");
+    assert_eq!(retired.message.as_deref(), Some("use 1.5.0"));
+}
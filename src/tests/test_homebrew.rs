@@ -0,0 +1,48 @@
+use crate::repo::homebrew::{unwrap_jws, Cask, Formula};
+use crate::repo::Package;
+
+#[test]
+fn test_homebrew_formula_jws_parse() {
+    let payload = unwrap_jws(
+        "https://formulae.brew.sh/api/formula.jws.json",
+        include_str!("data/homebrew-formula.jws.json"),
+    )
+    .unwrap();
+    let formulae: Vec<Formula> = serde_json::from_str(&payload).unwrap();
+    assert_eq!(formulae.len(), 3);
+    assert_eq!(formulae[1].version().as_deref(), Some("3.12.2_1"));
+
+    let wget: Package = formulae[0].clone().into();
+    assert_eq!(wget.other_metadata.get("version").unwrap(), "1.24.5");
+    assert_eq!(
+        wget.other_metadata.get("bottles").unwrap()["x86_64_linux"]["sha256"],
+        "a1b2c3"
+    );
+    assert!(!wget.other_metadata.contains_key("deprecated"));
+
+    let youtube_dl: Package = formulae[2].clone().into();
+    assert_eq!(
+        youtube_dl.other_metadata.get("deprecated").unwrap(),
+        "does not build"
+    );
+    assert_eq!(
+        youtube_dl.other_metadata.get("deprecation_date").unwrap(),
+        "2023-12-01"
+    );
+}
+
+#[test]
+fn test_homebrew_cask_parse() {
+    // the plain endpoints aren't wrapped
+    let content = include_str!("data/homebrew-cask.json");
+    assert_eq!(
+        unwrap_jws("https://formulae.brew.sh/api/cask.json", content).unwrap(),
+        content
+    );
+
+    let casks: Vec<Cask> = serde_json::from_str(content).unwrap();
+    let firefox: Package = casks[0].clone().into();
+    assert_eq!(firefox.name, "firefox");
+    assert_eq!(firefox.other_metadata.get("kind").unwrap(), "cask");
+    assert!(!firefox.other_metadata.contains_key("checksum"));
+}
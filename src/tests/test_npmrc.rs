@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::repo::npm::Npm;
+use crate::repo::npmrc::{interpolate_env, nerf_dart, Npmrc, NPM_REGISTRY_URL};
+use crate::repo::Repository;
+
+fn test_env(key: &str) -> Option<String> {
+    match key {
+        "NPM_TOKEN" => Some("from-env".to_string()),
+        "VERDACCIO_HOST" => Some("verdaccio.internal:4873".to_string()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_npmrc_parse() {
+    assert_eq!(
+        interpolate_env(
+            "${MISSING?}-${NPM_TOKEN}-${MISSING}-\\${NPM_TOKEN}",
+            &test_env
+        ),
+        "-from-env-${MISSING}-${NPM_TOKEN}"
+    );
+    assert_eq!(
+        nerf_dart("https://npm.example.com/repository/npm/"),
+        "//npm.example.com/repository/npm/"
+    );
+
+    let mut npmrc = Npmrc::parse(
+        r#"
+; project .npmrc
+@company:registry=http://${VERDACCIO_HOST}/
+//${VERDACCIO_HOST}/:_authToken=${NPM_TOKEN}
+"#,
+        &test_env,
+    );
+    npmrc.merge(Npmrc::parse(
+        r#"
+registry=https://npm.example.com/repository/npm/
+@company:registry=https://ignored.example.com/
+//npm.example.com/:_authToken=too-broad
+//npm.example.com/repository/npm/:username=ci
+//npm.example.com/repository/npm/:_password="cGFzc3dvcmQ="
+"#,
+        &test_env,
+    ));
+
+    assert_eq!(
+        npmrc.registry_for("@company/widgets"),
+        "http://verdaccio.internal:4873"
+    );
+    assert_eq!(
+        npmrc.registry_for("left-pad"),
+        "https://npm.example.com/repository/npm"
+    );
+    assert_eq!(
+        npmrc
+            .auth_for("http://verdaccio.internal:4873")
+            .unwrap()
+            .header()
+            .as_deref(),
+        Some("Bearer from-env")
+    );
+    // the most specific path wins, ci:password
+    assert_eq!(
+        npmrc
+            .auth_for("https://npm.example.com/repository/npm")
+            .unwrap()
+            .header()
+            .as_deref(),
+        Some("Basic Y2k6cGFzc3dvcmQ=")
+    );
+
+    assert_eq!(
+        Npmrc::default().registry_for("@other/thing"),
+        NPM_REGISTRY_URL
+    );
+}
+
+#[tokio::test]
+async fn test_npm_scoped_private_registry() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/@company%2fwidgets"))
+        .and(header("Authorization", "Bearer local-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "name": "@company/widgets",
+            "dist-tags": {"latest": "1.10.0", "next": "2.0.0-beta.1"},
+            "versions": {
+                "1.10.0": {
+                    "name": "@company/widgets",
+                    "version": "1.10.0",
+                    "license": "UNLICENSED",
+                    "dependencies": {"left-pad": "^1.3.0"},
+                    "dist": {
+                        "tarball": format!("{}/@company/widgets/-/widgets-1.10.0.tgz", server.uri()),
+                        "shasum": "abc",
+                        "integrity": "sha512-xyz"
+                    },
+                    "_npmUser": {"name": "ci", "email": "ci@example.com"}
+                },
+                "1.9.0": {
+                    "name": "@company/widgets",
+                    "version": "1.9.0",
+                    "license": {"type": "MIT"},
+                    "deprecated": "use 1.10.0",
+                    "dist": {"tarball": "https://example.com/widgets-1.9.0.tgz"}
+                },
+                "2.0.0-beta.1": {"name": "@company/widgets", "version": "2.0.0-beta.1"}
+            },
+            "time": {
+                "created": "2023-01-01T00:00:00.000Z",
+                "1.9.0": "2023-01-01T00:00:00.000Z",
+                "1.10.0": "2023-06-01T00:00:00.000Z"
+            }
+        })))
+        .mount(&server)
+        .await;
+
+    let npmrc = Npmrc::parse(
+        &format!(
+            "@company:registry={}/\n{}:_authToken=local-token\n",
+            server.uri(),
+            nerf_dart(&server.uri())
+        ),
+        &test_env,
+    );
+    let dir = std::env::temp_dir().join(format!(
+        "tidetrawler-npm-scoped-private-registry-{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut npm = Npm::new(cache).with_npmrc(npmrc);
+
+    let packages = npm.get_package("@company/widgets").await.unwrap();
+    let versions: Vec<&str> = packages
+        .iter()
+        .map(|p| p.other_metadata["version"].as_str().unwrap())
+        .collect();
+    assert_eq!(versions, vec!["1.9.0", "1.10.0", "2.0.0-beta.1"]);
+    assert_eq!(packages[0].other_metadata["license"], "MIT");
+    assert_eq!(packages[0].other_metadata["deprecated"], "use 1.10.0");
+    assert_eq!(packages[1].other_metadata["checksum"], "sha512-xyz");
+    assert_eq!(packages[1].other_metadata["tags"][0], "latest");
+    assert_eq!(packages[1].owner.as_deref(), Some("ci <ci@example.com>"));
+    assert_eq!(packages[2].other_metadata["tags"][0], "next");
+
+    std::fs::remove_dir_all(dir).ok();
+}
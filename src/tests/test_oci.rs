@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use wiremock::matchers::{header, method, path, path_regex, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::repo::oci::{AuthChallenge, Oci};
+use crate::repo::Repository;
+use crate::request::next_link;
+
+#[test]
+fn test_oci_auth_challenge() {
+    let challenge = AuthChallenge::parse(
+        r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/alpine:pull,push""#,
+    );
+    assert_eq!(challenge.scheme, "Bearer");
+    assert_eq!(challenge.params["realm"], "https://auth.docker.io/token");
+    assert_eq!(
+        challenge.params["scope"],
+        "repository:library/alpine:pull,push"
+    );
+
+    assert_eq!(
+        next_link(r#"</v2/alpine/tags/list?last=3.19&n=2>; rel="next""#),
+        Some("/v2/alpine/tags/list?last=3.19&n=2".to_string())
+    );
+    assert_eq!(next_link(r#"<https://example.com>; rel="prev""#), None);
+}
+
+#[tokio::test]
+async fn test_oci_registry_with_token() {
+    let server = MockServer::start().await;
+    let challenge = format!(
+        r#"Bearer realm="{}/token",service="test-registry",scope="repository:tools/widget:pull""#,
+        server.uri()
+    );
+
+    Mock::given(method("GET"))
+        .and(path("/token"))
+        .and(query_param("service", "test-registry"))
+        .and(query_param("scope", "repository:tools/widget:pull"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({"token": "hunter2"})),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(header("Authorization", "Bearer hunter2"))
+        .and(path("/v2/tools/widget/tags/list"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({"name": "tools/widget", "tags": ["1.0"]}))
+                .insert_header(
+                    "Link",
+                    r#"</v2/tools/widget/tags/list?last=1.0>; rel="next""#,
+                ),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(header("Authorization", "Bearer hunter2"))
+        .and(path("/v2/tools/widget/tags/list"))
+        .and(query_param("last", "1.0"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({"name": "tools/widget", "tags": ["1.1"]})),
+        )
+        .mount(&server)
+        .await;
+    for tag in ["1.0", "1.1"] {
+        Mock::given(method("GET"))
+            .and(header("Authorization", "Bearer hunter2"))
+            .and(path(format!("/v2/tools/widget/manifests/{}", tag)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header(
+                        "Docker-Content-Digest",
+                        format!("sha256:manifest{}", tag).as_str(),
+                    )
+                    .set_body_json(serde_json::json!({
+                        "schemaVersion": 2,
+                        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                        "config": {
+                            "mediaType": "application/vnd.oci.image.config.v1+json",
+                            "digest": "sha256:config",
+                            "size": 100
+                        },
+                        "layers": []
+                    })),
+            )
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("GET"))
+        .and(header("Authorization", "Bearer hunter2"))
+        .and(path("/v2/tools/widget/blobs/sha256:config"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "created": "2024-01-02T03:04:05Z",
+            "os": "linux",
+            "architecture": "arm64",
+            "variant": "v8",
+            "config": {"Labels": {
+                "org.opencontainers.image.source": "https://github.com/example/widget",
+                "org.opencontainers.image.licenses": "MIT"
+            }}
+        })))
+        .mount(&server)
+        .await;
+    // anything without the token gets challenged
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(401).insert_header("WWW-Authenticate", challenge.as_str()),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!(
+        "tidetrawler-oci-registry-with-token-{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut oci = Oci::new(cache).with_registry(&server.uri());
+
+    let packages = oci.get_package("tools/widget").await.unwrap();
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[1].other_metadata["version"], "1.1");
+    assert_eq!(packages[1].other_metadata["digest"], "sha256:manifest1.1");
+    assert_eq!(packages[1].other_metadata["platforms"][0], "linux/arm64/v8");
+    assert_eq!(packages[1].other_metadata["license"], "MIT");
+    assert_eq!(
+        packages[1].other_metadata["release_date"],
+        "2024-01-02T03:04:05+00:00"
+    );
+    assert_eq!(
+        packages[1].url.as_deref(),
+        Some("https://github.com/example/widget")
+    );
+
+    let packages = oci.get_package("tools/widget:1.0").await.unwrap();
+    assert_eq!(packages.len(), 1);
+    assert_eq!(packages[0].name, "tools/widget");
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_oci_newest_tags() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/v2/tools/gadget/tags/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "name": "tools/gadget",
+            "tags": ["1.10.0", "1.9.0", "latest", "v1.2.0"]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path_regex("^/v2/tools/gadget/manifests/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": "sha256:config",
+                "size": 100
+            },
+            "layers": []
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/v2/tools/gadget/blobs/sha256:config"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!(
+        "tidetrawler-oci-newest-tags-{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut oci = Oci::new(cache)
+        .with_registry(&server.uri())
+        .with_max_tags(2);
+
+    let packages = oci.get_package("tools/gadget").await.unwrap();
+    let versions: Vec<_> = packages
+        .iter()
+        .map(|package| package.other_metadata["version"].as_str().unwrap())
+        .collect();
+    assert_eq!(versions, ["1.9.0", "1.10.0"]);
+    std::fs::remove_dir_all(dir).ok();
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::orchestrator::Orchestrator;
+use crate::repo::hex::Hex;
+use crate::repo::pypi::PyPi;
+use crate::repo::Repository;
+use crate::{Errors, RepoType};
+
+#[tokio::test]
+async fn test_unsupported_is_an_error() {
+    let dir = std::env::temp_dir().join(format!(
+        "tidetrawler-unsupported-is-an-error-{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    let mut pypi = PyPi::new(Arc::new(RwLock::new(Cache::new(dir.clone()))));
+    assert!(!pypi.capabilities().search);
+    assert!(matches!(
+        pypi.search("requests").await,
+        Err(Errors::Unsupported {
+            repo_type: RepoType::PyPi,
+            ..
+        })
+    ));
+    assert!(pypi.update_cache(None).await.is_err());
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_orchestrator_skips_unsupported() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/packages"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+                "name": "jason",
+                "html_url": "https://hex.pm/packages/jason",
+                "meta": {"description": "A blazing fast JSON parser and generator in pure Elixir"},
+                "releases": [],
+            }])),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/simple/jason/"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/packages/jason"))
+        .respond_with(ResponseTemplate::new(400))
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!(
+        "tidetrawler-orchestrator-skips-unsupported-{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut orchestrator = Orchestrator::default()
+        .with_repository(
+            PyPi::new(cache.clone())
+                .with_index_url(&format!("{}/simple", server.uri()))
+                .unwrap(),
+        )
+        .with_repository(Hex::new(cache.clone()).with_api_url(&server.uri()));
+
+    let results = orchestrator.search("jason").await;
+    assert_eq!(results.skipped, vec![RepoType::PyPi]);
+    assert!(results.errors.is_empty());
+    assert_eq!(results.packages.len(), 1);
+    assert_eq!(results.packages[0].name, "jason");
+
+    // PyPI doesn't have it, which is fine, but hex falling over gets reported
+    let results = orchestrator.get_package("jason").await;
+    assert!(results.skipped.is_empty());
+    assert!(results.packages.is_empty());
+    assert_eq!(results.errors.len(), 1);
+    assert!(matches!(results.errors[0], Errors::Http { .. }));
+
+    std::fs::remove_dir_all(dir).ok();
+}
@@ -0,0 +1,43 @@
+use crate::repo::packagist::{PackagistMetadata, PackagistSearchResponse};
+use crate::repo::Package;
+
+#[test]
+fn test_packagist_expand_minified() {
+    let metadata: PackagistMetadata =
+        serde_json::from_str(include_str!("data/packagist-p2-monolog.json")).unwrap();
+    let versions = metadata.versions().unwrap();
+    assert_eq!(versions.len(), 3);
+
+    // carried over from the first version
+    assert_eq!(versions[1].name.as_deref(), Some("monolog/monolog"));
+    assert_eq!(versions[1].license, vec!["MIT".to_string()]);
+    assert_eq!(versions[1].keywords.len(), 3);
+    assert_eq!(
+        versions[1].require.as_ref().unwrap()["psr/log"],
+        "^2.0 || ^3.0"
+    );
+
+    // removed with __unset
+    assert!(versions[2].keywords.is_empty());
+    assert_eq!(versions[2].require.as_ref().unwrap()["php"], ">=5.3.0");
+
+    let package: Package = versions[2].clone().into();
+    assert_eq!(package.name, "monolog/monolog");
+    assert_eq!(package.owner.as_deref(), Some("Jordi Boggiano"));
+    assert_eq!(
+        package.other_metadata.get("deprecated").unwrap(),
+        "abandoned, use psr/log instead"
+    );
+    assert!(!package.other_metadata.contains_key("checksum"));
+}
+
+#[test]
+fn test_packagist_search_parse() {
+    let response: PackagistSearchResponse = serde_json::from_str(
+        r#"{"results":[{"name":"monolog/monolog","description":"Sends your logs","url":"https://packagist.org/packages/monolog/monolog","repository":"https://github.com/Seldaek/monolog","downloads":123,"favers":456}],"total":1}"#,
+    )
+    .unwrap();
+    let package: Package = response.results.into_iter().next().unwrap().into();
+    assert_eq!(package.name, "monolog/monolog");
+    assert_eq!(package.other_metadata.get("downloads").unwrap(), 123);
+}
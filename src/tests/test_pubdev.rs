@@ -0,0 +1,75 @@
+use std::str::FromStr;
+
+use semver::Version;
+
+use crate::repo::pubdev::{PubDependencySource, PubPackage, PubScore, VersionConstraint};
+
+#[test]
+fn test_pubdev_package_parse() {
+    let package: PubPackage = serde_json::from_str(include_str!("data/pubdev-http.json")).unwrap();
+
+    let deps = package.versions[1].pubspec.dependencies().unwrap();
+    assert_eq!(deps.len(), 4);
+    assert_eq!(deps[1].name, "flutter");
+    assert_eq!(
+        deps[1].source,
+        PubDependencySource::Sdk("flutter".to_string())
+    );
+    assert_eq!(
+        deps[2].source,
+        PubDependencySource::Hosted(Some("https://pub.dev".to_string()))
+    );
+    assert_eq!(
+        deps[3].source,
+        PubDependencySource::Git("https://github.com/dart-lang/web.git".to_string())
+    );
+
+    let score: PubScore = serde_json::from_str(
+        r#"{"grantedPoints":140,"maxPoints":140,"likeCount":7000,"popularityScore":0.99,"tags":["sdk:dart"]}"#,
+    )
+    .unwrap();
+    let packages = package
+        .into_packages("https://pub.example.com", Some(&score))
+        .unwrap();
+    assert_eq!(packages.len(), 3);
+    assert_eq!(
+        packages[0].url.as_deref(),
+        Some("https://pub.example.com/packages/http")
+    );
+    assert_eq!(packages[0].other_metadata.get("version").unwrap(), "0.13.6");
+    assert_eq!(packages[1].other_metadata.get("yanked").unwrap(), true);
+    assert_eq!(packages[2].other_metadata.get("pub_points").unwrap(), 140);
+    assert_eq!(
+        packages[2].other_metadata.get("dependencies").unwrap()["async"],
+        ">=2.5.0 <3.0.0"
+    );
+}
+
+#[test]
+fn test_pubdev_version_constraint() {
+    let caret = VersionConstraint::from_str("^1.2.3").unwrap();
+    assert!(caret.allows(&Version::new(1, 9, 0)));
+    assert!(!caret.allows(&Version::new(2, 0, 0)));
+
+    let zero = VersionConstraint::from_str("^0.2.3").unwrap();
+    assert!(zero.allows(&Version::new(0, 2, 9)));
+    assert!(!zero.allows(&Version::new(0, 3, 0)));
+
+    let range = VersionConstraint::from_str(">=0.4.2 <0.6.0").unwrap();
+    assert!(range.allows(&Version::new(0, 5, 0)));
+    assert!(!range.allows(&Version::new(0, 6, 0)));
+
+    let spaced = VersionConstraint::from_str(">= 1.0.0 < 2.0.0").unwrap();
+    assert_eq!(
+        spaced,
+        VersionConstraint::from_str(">=1.0.0 <2.0.0").unwrap()
+    );
+
+    assert!(VersionConstraint::from_str("any")
+        .unwrap()
+        .allows(&Version::new(9, 9, 9)));
+    assert!(VersionConstraint::from_str("1.2.3")
+        .unwrap()
+        .allows(&Version::new(1, 2, 3)));
+    assert!(VersionConstraint::from_str("^x").is_err());
+}
@@ -1,10 +1,20 @@
-use crate::repo::pypi::PyPiPackage;
-use crate::repo::Package;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::repo::pypi::{
+    compare_versions, parse_simple_html, simple_files_into_packages, PyPi, PyPiPackage,
+};
+use crate::repo::{Package, Repository};
+use crate::Errors;
 
 #[cfg(feature = "test_live")]
 #[tokio::test]
 async fn test_get_package() {
-    use crate::repo::Repository;
     let pypi = crate::repo::pypi::PyPi::new();
     let res = pypi.get_package("requests").await.unwrap();
     println!("{:#?}", res);
@@ -20,3 +30,178 @@ async fn test_parse_package_mocked() {
     let package: Package = pypipackage.into();
     assert!(package.name == "requests");
 }
+
+#[test]
+fn test_pypi_simple_html() {
+    let files = parse_simple_html(include_str!("data/pypi-simple-requests.html"));
+    assert_eq!(files.len(), 5);
+    assert_eq!(files[2].requires_python.as_deref(), Some(">=2.7, !=3.0.*"));
+    assert_eq!(files[0].version("requests").as_deref(), Some("2.9.2"));
+    assert_eq!(files[1].version("requests").as_deref(), Some("2.9.2"));
+
+    let packages = simple_files_into_packages("requests", "https://pypi.example.com/simple", files);
+    let versions: Vec<&str> = packages
+        .iter()
+        .map(|p| p.other_metadata["version"].as_str().unwrap())
+        .collect();
+    assert_eq!(versions, vec!["2.9.2", "2.10.0", "2.32.0rc1", "2.32.0"]);
+    assert_eq!(packages[0].other_metadata["files"][1]["checksum"], "bbbb");
+    assert_eq!(packages[3].other_metadata["yanked"], true);
+
+    assert_eq!(compare_versions("1.0.dev1", "1.0a1"), Ordering::Less);
+    assert_eq!(compare_versions("1.0a1.dev1", "1.0a1"), Ordering::Less);
+    assert_eq!(compare_versions("1.0", "1.0.post1.dev1"), Ordering::Less);
+    assert_eq!(compare_versions("1.0.post1", "1!0.1"), Ordering::Less);
+    assert_eq!(compare_versions("1.0", "1.0.0"), Ordering::Equal);
+}
+
+#[test]
+fn test_pypi_legacy_versions() {
+    // old indexes still list versions from before PEP 440, which go first
+    let links: String = [
+        "2004d",
+        "2023.3",
+        "1.0-final",
+        "2005r",
+        "2024.1",
+        "0.9.custom",
+    ]
+    .iter()
+    .map(|version| version.to_string())
+    .chain((0..30).map(|n| match n % 2 {
+        0 => format!("{}.2004d", n),
+        _ => format!("2020.{}", n),
+    }))
+    .map(|version| {
+        format!(
+            r#"<a href="../../packages/pytz-{0}.tar.gz">pytz-{0}.tar.gz</a><br />"#,
+            version
+        )
+    })
+    .collect();
+    let files = parse_simple_html(&format!("<html><body>{}</body></html>", links));
+    let packages = simple_files_into_packages("pytz", "https://pypi.example.com/simple", files);
+    let versions: Vec<&str> = packages
+        .iter()
+        .map(|p| p.other_metadata["version"].as_str().unwrap())
+        .collect();
+    for (n, a) in versions.iter().enumerate() {
+        for b in versions[n..].iter() {
+            assert_ne!(compare_versions(a, b), Ordering::Greater);
+        }
+    }
+    let position = |version| versions.iter().position(|v| *v == version).unwrap();
+    assert_eq!(versions[0], "0.2004d");
+    assert!(position("2004d") < position("1.0"));
+    assert_eq!(versions.last(), Some(&"2024.1"));
+    assert_eq!(compare_versions("2004d", "1.0"), Ordering::Less);
+}
+
+#[tokio::test]
+async fn test_pypi_private_simple_json_index() {
+    let server = MockServer::start().await;
+    // artifactory doesn't have the JSON API
+    Mock::given(method("GET"))
+        .and(path("/api/pypi/internal/pypi/my-tool/json"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/pypi/internal/simple/my-tool/"))
+        // user:p@ss
+        .and(header("Authorization", "Basic dXNlcjpwQHNz"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_raw(
+                serde_json::json!({
+                    "meta": {"api-version": "1.1"},
+                    "name": "my-tool",
+                    "files": [
+                        {
+                            "filename": "my_tool-0.2.0-py3-none-any.whl",
+                            "url": "../../packages/my_tool-0.2.0-py3-none-any.whl",
+                            "hashes": {"sha256": "ffff"},
+                            "requires-python": ">=3.10",
+                            "upload-time": "2024-05-01T12:00:00.000000Z"
+                        },
+                        {
+                            "filename": "My.Tool-0.1.0.tar.gz",
+                            "url": "https://files.example.com/My.Tool-0.1.0.tar.gz",
+                            "hashes": {},
+                            "yanked": "broken"
+                        }
+                    ]
+                })
+                .to_string(),
+                "application/vnd.pypi.simple.v1+json",
+            ),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!(
+        "tidetrawler-pypi-private-simple-json-index-{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let index_url =
+        server.uri().replace("http://", "http://user:p%40ss@") + "/api/pypi/internal/simple/";
+    let mut pypi = PyPi::new(cache).with_index_url(&index_url).unwrap();
+    assert_eq!(pypi.indexes[0].password.as_deref(), Some("p@ss"));
+
+    let packages = pypi.get_package("My_Tool").await.unwrap();
+    assert_eq!(packages.len(), 2);
+    assert_eq!(packages[0].other_metadata["version"], "0.1.0");
+    assert_eq!(packages[0].other_metadata["yanked"], true);
+    assert_eq!(
+        packages[1].other_metadata["files"][0]["download_url"],
+        format!(
+            "{}/api/pypi/internal/packages/my_tool-0.2.0-py3-none-any.whl",
+            server.uri()
+        )
+    );
+    assert_eq!(
+        packages[1].other_metadata["release_date"],
+        "2024-05-01T12:00:00+00:00"
+    );
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_pypi_json_api_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/pypi/private-thing/json"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/pypi/broken-thing/json"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{\"info\": "))
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!(
+        "tidetrawler-pypi-json-api-errors-{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut pypi = PyPi::new(cache)
+        .with_index_url(&format!("{}/simple", server.uri()))
+        .unwrap();
+
+    // neither of these should fall through to the simple API as if they weren't there
+    assert!(matches!(
+        pypi.get_package("private-thing").await,
+        Err(Errors::Unauthorized { .. })
+    ));
+    assert!(matches!(
+        pypi.get_package("broken-thing").await,
+        Err(Errors::Parse { .. })
+    ));
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+
+    std::fs::remove_dir_all(dir).ok();
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::request::{ClientSettings, RateLimit, RateLimiter, RetryPolicy, WebClient};
+use crate::{Errors, RepoType};
+
+fn quick_retries() -> RetryPolicy {
+    RetryPolicy {
+        max_retries: 3,
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_secs(1),
+    }
+}
+
+#[test]
+fn test_backoff() {
+    let policy = quick_retries();
+    for attempt in 0..10 {
+        let delay = policy.backoff(attempt);
+        assert!(delay <= Duration::from_millis(10 * 2u64.pow(attempt)));
+        assert!(delay <= policy.max_delay);
+    }
+}
+
+#[tokio::test]
+async fn test_retries_server_errors() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/flaky"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/flaky"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("finally"))
+        .mount(&server)
+        .await;
+
+    let client = WebClient::default().with_retry(quick_retries());
+    let body = client
+        .get_text(&format!("{}/flaky", server.uri()))
+        .await
+        .unwrap();
+    assert_eq!(body, "finally");
+    assert_eq!(server.received_requests().await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_gives_up_eventually() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/down"))
+        .respond_with(ResponseTemplate::new(502))
+        .mount(&server)
+        .await;
+
+    let client = WebClient::default().with_retry(quick_retries());
+    let err = client
+        .get_text(&format!("{}/down", server.uri()))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Errors::Http { status, .. } if status.as_u16() == 502));
+    assert_eq!(server.received_requests().await.unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn test_retry_after() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/limited"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/limited"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+        .mount(&server)
+        .await;
+    // longer than we're prepared to wait
+    Mock::given(method("GET"))
+        .and(path("/very-limited"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+        .mount(&server)
+        .await;
+
+    let client = WebClient::default().with_retry(quick_retries());
+    assert_eq!(
+        client
+            .get_text(&format!("{}/limited", server.uri()))
+            .await
+            .unwrap(),
+        "ok"
+    );
+    assert!(matches!(
+        client.get_text(&format!("{}/very-limited", server.uri())).await,
+        Err(Errors::RateLimited { retry_after: Some(retry_after), .. })
+            if retry_after == Duration::from_secs(3600)
+    ));
+    // one for /limited's 429, one for its retry, and /very-limited gave up straight away
+    assert_eq!(server.received_requests().await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn test_client_errors_are_not_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/missing"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let client = WebClient::default().with_retry(quick_retries());
+    let err = client
+        .get_text(&format!("{}/missing", server.uri()))
+        .await
+        .unwrap_err();
+    assert!(err.is_not_found());
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_rate_limiter() {
+    let limit = RateLimit {
+        per_second: 20.0,
+        burst: 2,
+    };
+    let limiter = RateLimiter::default();
+    let start = Instant::now();
+    // the burst goes straight away, the next two wait 50ms each
+    for _ in 0..4 {
+        limiter.acquire("example.com", limit).await;
+    }
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    // other hosts have their own bucket
+    let start = Instant::now();
+    limiter.acquire("example.org", limit).await;
+    assert!(start.elapsed() < Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn test_rate_limiter_ignores_stalled_limits() {
+    // a limit that never refills falls back to the default rather than dividing by zero
+    let limiter = RateLimiter::default();
+    for per_second in [0.0, -1.0, f64::NAN] {
+        let limit = RateLimit {
+            per_second,
+            burst: 1,
+        };
+        let start = Instant::now();
+        limiter
+            .acquire(&format!("{}.example.com", per_second), limit)
+            .await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
+
+#[tokio::test]
+async fn test_rate_limit_shared_and_overridden() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let host = server.uri().trim_start_matches("http://").to_string();
+
+    let slow = RateLimit {
+        per_second: 10.0,
+        burst: 1,
+    };
+    let client = WebClient::new(&ClientSettings::default())
+        .unwrap()
+        .with_rate_limit(slow);
+    // a clone for another repository shares the same buckets
+    let other = client.clone();
+    assert!(Arc::ptr_eq(&client.limiter, &other.limiter));
+    let start = Instant::now();
+    client.get_text(&server.uri()).await.unwrap();
+    other.get_text(&server.uri()).await.unwrap();
+    client.get_text(&server.uri()).await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    let settings = ClientSettings {
+        rate_limits: HashMap::from([(
+            host,
+            RateLimit {
+                per_second: 1000.0,
+                burst: 100,
+            },
+        )]),
+        ..Default::default()
+    };
+    let client = WebClient::new(&settings).unwrap().with_rate_limit(slow);
+    let start = Instant::now();
+    for _ in 0..5 {
+        client.get_text(&server.uri()).await.unwrap();
+    }
+    assert!(start.elapsed() < Duration::from_millis(200));
+
+    // by ecosystem, for whichever host that ecosystem's client talks to
+    let settings = ClientSettings {
+        ecosystem_rate_limits: HashMap::from([(
+            RepoType::Cargo,
+            RateLimit {
+                per_second: 1000.0,
+                burst: 100,
+            },
+        )]),
+        ..Default::default()
+    };
+    let client = WebClient::new(&settings)
+        .unwrap()
+        .with_repo_type(RepoType::Cargo);
+    let start = Instant::now();
+    for _ in 0..5 {
+        client.get_text(&server.uri()).await.unwrap();
+    }
+    assert!(start.elapsed() < Duration::from_millis(200));
+    let npm = WebClient::new(&settings)
+        .unwrap()
+        .with_repo_type(RepoType::Npm);
+    assert_eq!(npm.rate_limit, RateLimit::default());
+}
+
+#[test]
+fn test_rate_limit_defaults() {
+    assert_eq!(RateLimit::for_repo_type(RepoType::Cargo).per_second, 1.0);
+    assert_eq!(
+        RateLimit::for_repo_type(RepoType::Npm),
+        RateLimit::default()
+    );
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use serde_json::Value;
+use tokio::sync::RwLock;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::orchestrator::Orchestrator;
+use crate::repo::hex::Hex;
+use crate::repo::{Package, Repository};
+use crate::store::{Recorded, Store, StoreQuery};
+use crate::RepoType;
+
+fn package(
+    repo_type: RepoType,
+    name: &str,
+    version: &str,
+    license: &str,
+    age: Duration,
+) -> Package {
+    let other_metadata = HashMap::from([
+        ("version".to_string(), Value::String(version.to_string())),
+        ("license".to_string(), Value::String(license.to_string())),
+        (
+            "release_date".to_string(),
+            Value::String((Utc::now() - age).to_rfc3339()),
+        ),
+    ]);
+    Package {
+        name: name.to_string(),
+        url: None,
+        owner: None,
+        other_metadata,
+        repo_type,
+    }
+}
+
+#[test]
+fn test_store_query() {
+    let store = Store::in_memory().unwrap();
+    store
+        .record(&[
+            package(
+                RepoType::Npm,
+                "left-pad",
+                "1.0.0",
+                "MIT",
+                Duration::days(3000),
+            ),
+            package(
+                RepoType::Npm,
+                "left-pad",
+                "1.3.0",
+                "WTFPL",
+                Duration::days(2000),
+            ),
+            package(
+                RepoType::Cpan,
+                "Moose",
+                "2.2207",
+                "GPL-1.0 OR Artistic-1.0",
+                Duration::days(100),
+            ),
+            package(
+                RepoType::Hackage,
+                "pandoc",
+                "3.1",
+                "GPL-2.0-or-later",
+                Duration::days(30),
+            ),
+            package(
+                RepoType::Hackage,
+                "text",
+                "2.1",
+                "BSD-2-Clause",
+                Duration::days(10),
+            ),
+        ])
+        .unwrap();
+
+    // the newest version is the one that counts
+    let all = store.query(&StoreQuery::default()).unwrap();
+    assert_eq!(all.len(), 4);
+    let left_pad = all
+        .iter()
+        .find(|package| package.name == "left-pad")
+        .unwrap();
+    assert_eq!(left_pad.other_metadata["version"], "1.3.0");
+    assert!(store
+        .query(&StoreQuery {
+            license: Some("MIT".to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+        .is_empty());
+
+    let gpl = store
+        .query(&StoreQuery {
+            license: Some("gpl".to_string()),
+            since: Some(Utc::now() - Duration::days(365)),
+            ..Default::default()
+        })
+        .unwrap();
+    let names: Vec<_> = gpl.iter().map(|package| package.name.as_str()).collect();
+    assert_eq!(names, ["Moose", "pandoc"]);
+    assert_eq!(gpl[0].repo_type, RepoType::Cpan);
+
+    let hackage = store
+        .query(&StoreQuery {
+            ecosystem: Some(RepoType::Hackage),
+            limit: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(hackage.len(), 1);
+    assert_eq!(hackage[0].name, "pandoc");
+
+    // fetching it again replaces the record rather than adding one
+    store
+        .record(&[package(
+            RepoType::Hackage,
+            "text",
+            "2.1",
+            "BSD-3-Clause",
+            Duration::days(10),
+        )])
+        .unwrap();
+    let text = store
+        .query(&StoreQuery {
+            name: Some("tex".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(text.len(), 1);
+    assert_eq!(text[0].other_metadata["license"], "BSD-3-Clause");
+}
+
+#[tokio::test]
+async fn test_orchestrator_records() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/packages"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+                "name": "jason",
+                "html_url": "https://hex.pm/packages/jason",
+                "meta": {"description": "A blazing fast JSON parser and generator in pure Elixir"},
+                "releases": [],
+            }])),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!(
+        "tidetrawler-orchestrator-records-{}",
+        std::process::id()
+    ));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let store = Arc::new(Store::in_memory().unwrap());
+    let mut orchestrator = Orchestrator::default()
+        .with_repository(Hex::new(cache).with_api_url(&server.uri()))
+        .with_store(store.clone());
+    let results = orchestrator.search("jason").await;
+    assert!(results.errors.is_empty());
+
+    let recorded = store
+        .query(&StoreQuery {
+            ecosystem: Some(RepoType::Hex),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(recorded.len(), 1);
+    assert_eq!(recorded[0].name, "jason");
+    assert_eq!(
+        recorded[0].url.as_deref(),
+        Some("https://hex.pm/packages/jason")
+    );
+
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[tokio::test]
+async fn test_recorded_backend() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/packages/jason"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "name": "jason",
+            "html_url": "https://hex.pm/packages/jason",
+            "meta": {"licenses": ["Apache-2.0"]},
+            "releases": [{"version": "1.4.4", "inserted_at": "2024-07-26T08:00:00Z"}],
+        })))
+        .mount(&server)
+        .await;
+
+    let dir = std::env::temp_dir().join(format!("tidetrawler-recorded-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let cache = Arc::new(RwLock::new(Cache::new(dir.clone())));
+    let mut hex = Recorded::<Hex>::new(cache.clone());
+    hex.repository = Hex::new(cache).with_api_url(&server.uri());
+    assert!(!hex.get_package("jason").await.unwrap().is_empty());
+
+    // it went in the store next to the cache, not the default one
+    let store = Store::open(&dir.join(crate::store::STORE_FILE)).unwrap();
+    let recorded = store.query(&StoreQuery::default()).unwrap();
+    assert_eq!(recorded.len(), 1);
+    assert_eq!(recorded[0].name, "jason");
+
+    std::fs::remove_dir_all(dir).ok();
+}
@@ -0,0 +1,94 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::cache::Cache;
+use crate::repo::terraform::{Terraform, TerraformAddress};
+use crate::repo::Repository;
+
+#[test]
+fn test_terraform_address_parse() {
+    assert!(matches!(
+        TerraformAddress::from_str("terraform-aws-modules/vpc/aws").unwrap(),
+        TerraformAddress::Module(module) if module.name == "vpc"
+    ));
+    assert!(matches!(
+        TerraformAddress::from_str("hashicorp/aws").unwrap(),
+        TerraformAddress::Provider(provider) if provider.provider_type == "aws"
+    ));
+    assert!(TerraformAddress::from_str("aws").is_err());
+    assert!(TerraformAddress::from_str("hashicorp//aws").is_err());
+}
+
+#[tokio::test]
+async fn test_terraform_registry() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/.well-known/terraform.json"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "modules.v1": "/api/modules/v1/",
+            "providers.v1": format!("{}/api/providers/v1", server.uri()),
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/modules/v1/example/network/aws/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "modules": [{
+                "source": "example/network/aws",
+                "versions": [
+                    {"version": "1.10.0", "root": {"providers": [
+                        {"name": "aws", "namespace": "hashicorp", "source": "hashicorp/aws", "version": ">= 5.0"}
+                    ], "dependencies": []}, "submodules": []},
+                    {"version": "1.2.0"},
+                    {"version": "1.9.1"}
+                ]
+            }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/providers/v1/hashicorp/random/versions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "id": "hashicorp/random",
+            "versions": [
+                {"version": "3.6.0", "protocols": ["5.0"], "platforms": [
+                    {"os": "linux", "arch": "amd64"},
+                    {"os": "darwin", "arch": "arm64"}
+                ]},
+                {"version": "2.3.1", "protocols": ["4.0", "5.0"], "platforms": []}
+            ],
+            "warnings": null
+        })))
+        .mount(&server)
+        .await;
+
+    let cache_dir =
+        std::env::temp_dir().join(format!("tidetrawler-terraform-{}", std::process::id()));
+    std::fs::create_dir_all(&cache_dir).unwrap();
+    let cache = Arc::new(RwLock::new(Cache::new(cache_dir.clone())));
+    let mut terraform = Terraform::new(cache).with_registry(&server.uri());
+
+    let modules = terraform.get_package("example/network/aws").await.unwrap();
+    let versions: Vec<&str> = modules
+        .iter()
+        .map(|p| p.other_metadata["version"].as_str().unwrap())
+        .collect();
+    assert_eq!(versions, vec!["1.2.0", "1.9.1", "1.10.0"]);
+    assert_eq!(modules[2].other_metadata["kind"], "module");
+    assert_eq!(
+        modules[2].other_metadata["dependencies"][0]["source"],
+        "hashicorp/aws"
+    );
+
+    let providers = terraform.get_package("hashicorp/random").await.unwrap();
+    assert_eq!(providers.len(), 2);
+    assert_eq!(providers[0].other_metadata["version"], "2.3.1");
+    assert_eq!(providers[1].other_metadata["platforms"][1], "darwin_arm64");
+    assert_eq!(providers[1].owner.as_deref(), Some("hashicorp"));
+
+    std::fs::remove_dir_all(cache_dir).ok();
+}